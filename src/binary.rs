@@ -0,0 +1,155 @@
+//! Binary dump of encoded k-mers, to reload them without counting again.
+//!
+//! The file starts with a 24-byte header: the magic bytes `UKMERS\0\x01`, k (u32), flags (u32, bit 0 set for
+//! canonical k-mers, bit 1 for k-mers of both strands) and the number of k-mers (u64). Then come the encoded k-mers, on 8 bytes for k <= 32
+//! and 16 bytes otherwise. All integers are little-endian.
+
+use crate::kmer::{Kmer, Strand};
+use crate::KmerHashing;
+use dashmap::DashSet;
+use std::io::{self, Read, Write};
+
+pub const MAGIC: [u8; 8] = *b"UKMERS\0\x01";
+
+const CANONICAL_FLAG: u32 = 1;
+const BOTH_STRANDS_FLAG: u32 = 2;
+/// Number of k-mers read or written at once
+const CHUNK_KMERS: usize = 4096;
+
+/// Parameters of the k-mers of a binary dump
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BinaryHeader {
+    pub k: usize,
+    pub strand: Strand,
+    pub count: u64,
+}
+
+impl BinaryHeader {
+    pub fn write(&self, mut out: impl Write) -> io::Result<()> {
+        out.write_all(&MAGIC)?;
+        out.write_all(&(self.k as u32).to_le_bytes())?;
+        out.write_all(&strand_flags(self.strand).to_le_bytes())?;
+        out.write_all(&self.count.to_le_bytes())
+    }
+
+    pub fn read(mut input: impl Read) -> io::Result<Self> {
+        let mut header = [0; 24];
+        input.read_exact(&mut header).map_err(truncated)?;
+        if header[..8] != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a binary k-mer file"));
+        }
+        Ok(BinaryHeader {
+            k: u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize,
+            strand: flags_strand(u32::from_le_bytes(header[12..16].try_into().unwrap())),
+            count: u64::from_le_bytes(header[16..24].try_into().unwrap()),
+        })
+    }
+}
+
+/// Flags of the k-mers of a strand mode in a header
+pub fn strand_flags(strand: Strand) -> u32 {
+    match strand {
+        Strand::Forward => 0,
+        Strand::Canonical => CANONICAL_FLAG,
+        Strand::Both => BOTH_STRANDS_FLAG,
+    }
+}
+
+/// Strand mode of the k-mers of a header with these flags
+pub fn flags_strand(flags: u32) -> Strand {
+    if flags & CANONICAL_FLAG != 0 {
+        Strand::Canonical
+    } else if flags & BOTH_STRANDS_FLAG != 0 {
+        Strand::Both
+    } else {
+        Strand::Forward
+    }
+}
+
+fn truncated(e: io::Error) -> io::Error {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        io::Error::new(io::ErrorKind::InvalidData, "truncated binary k-mer file")
+    } else {
+        e
+    }
+}
+
+/// Writes the distinct k-mers of `kmers` with a header recording `k` and `strand`
+pub fn write_binary<T: Kmer, S: KmerHashing>(kmers: &DashSet<T, S>, k: usize, strand: Strand, out: impl Write) -> io::Result<()> {
+    write_binary_iter(kmers.len() as u64, kmers.iter().map(|kmer| *kmer), k, strand, out)
+}
+
+/// Writes the `count` distinct k-mers of `kmers` with a header recording `k` and `strand`
+pub fn write_binary_iter<T: Kmer>(count: u64, kmers: impl Iterator<Item = T>, k: usize, strand: Strand, mut out: impl Write) -> io::Result<()> {
+    BinaryHeader { k, strand, count }.write(&mut out)?;
+    let mut buffer = vec![0; CHUNK_KMERS * T::BYTES];
+    let mut filled = 0;
+    for kmer in kmers {
+        kmer.write_le(&mut buffer[filled..]);
+        filled += T::BYTES;
+        if filled == buffer.len() {
+            out.write_all(&buffer)?;
+            filled = 0;
+        }
+    }
+    out.write_all(&buffer[..filled])?;
+    out.flush()
+}
+
+/// Reads a binary dump of k-mers into `kmers`, returning the number of k-mers read.
+/// Fails if the dump was not written with the same `k` and `strand`.
+pub fn read_binary<T: Kmer, S: KmerHashing>(input: impl Read, k: usize, strand: Strand, kmers: &DashSet<T, S>) -> io::Result<u64> {
+    read_binary_with(input, k, strand, |kmer| {
+        kmers.insert(kmer);
+    })
+}
+
+/// Reads a binary dump of k-mers, passing each of them to `insert`. Same as `read_binary` otherwise.
+pub fn read_binary_with<T: Kmer>(mut input: impl Read, k: usize, strand: Strand, mut insert: impl FnMut(T)) -> io::Result<u64> {
+    let header = BinaryHeader::read(&mut input)?;
+    if header.k != k || header.strand != strand {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "binary k-mer file of {}-mers with strand mode {}, expected {}-mers with strand mode {}",
+                header.k, header.strand, k, strand
+            ),
+        ));
+    }
+    let mut buffer = vec![0; CHUNK_KMERS * T::BYTES];
+    let mut remaining = header.count;
+    while remaining > 0 {
+        let chunk = remaining.min(CHUNK_KMERS as u64) as usize;
+        let bytes = &mut buffer[..chunk * T::BYTES];
+        input.read_exact(bytes).map_err(truncated)?;
+        for encoded in bytes.chunks_exact(T::BYTES) {
+            insert(T::read_le(encoded));
+        }
+        remaining -= chunk as u64;
+    }
+    Ok(header.count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let kmers: DashSet<u128> = (0..10_000u128).map(|i| i * 0x1234_5678_9abc_def1).collect();
+        let mut dump = Vec::new();
+        write_binary(&kmers, 40, Strand::Canonical, &mut dump).unwrap();
+        assert_eq!(dump.len(), 24 + 16 * 10_000);
+
+        let loaded = DashSet::<u128>::new();
+        assert_eq!(read_binary(&dump[..], 40, Strand::Canonical, &loaded).unwrap(), 10_000);
+        assert!(kmers.iter().all(|kmer| loaded.contains(&*kmer)));
+
+        let error = read_binary(&dump[..], 41, Strand::Both, &DashSet::<u128>::new()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "binary k-mer file of 40-mers with strand mode canonical, expected 41-mers with strand mode both"
+        );
+        assert!(read_binary(&dump[..100], 40, Strand::Canonical, &DashSet::<u128>::new()).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Bloom filter of k-mers, for distinct counts under a fixed memory budget and membership queries.
+//!
+//! A Bloom file starts with a 36-byte header: the magic bytes `UKBLOOM\x01`, k (u32), the strand flags (u32, as in
+//! `binary`), the number of bits (u64), the number of hash functions (u32) and the number of new insertions (u64).
+//! Then come the bits, as u64 words. All integers are little-endian. K-mers are inserted with `Kmer::hash64`,
+//! which does not depend on the run or the machine, so that a filter can be queried by other runs.
+
+use crate::binary::{flags_strand, strand_flags};
+use crate::kmer::{mix64, Strand};
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+pub const MAGIC: [u8; 8] = *b"UKBLOOM\x01";
+
+/// Bloom filter whose bits are atomics so that threads can insert concurrently.
+/// It also counts the insertions of elements it did not contain yet.
+#[derive(Debug)]
+pub struct BloomFilter {
+    bits: Vec<AtomicU64>,
+    num_hashes: u32,
+    new_insertions: AtomicUsize,
+}
+
+impl BloomFilter {
+    /// Filter of `size_bytes` bytes (rounded up to a multiple of 8) using `num_hashes` hash functions
+    pub fn new(size_bytes: usize, num_hashes: u32) -> Self {
+        BloomFilter {
+            bits: (0..size_bytes.div_ceil(8).max(1)).map(|_| AtomicU64::new(0)).collect(),
+            num_hashes: num_hashes.max(1),
+            new_insertions: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn num_bits(&self) -> u64 {
+        self.bits.len() as u64 * 64
+    }
+
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+
+    /// Bit positions of an element, by double hashing
+    fn positions(&self, hash: u64) -> impl Iterator<Item = u64> {
+        let num_bits = self.num_bits();
+        let h2 = mix64(hash) | 1;
+        (0..self.num_hashes as u64).map(move |i| hash.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    /// Inserts an element given its 64-bit hash.
+    /// Returns true if the filter did not contain it before.
+    pub fn insert_hash(&self, hash: u64) -> bool {
+        let mut new = false;
+        for position in self.positions(hash) {
+            let mask = 1 << (position % 64);
+            let previous = self.bits[(position / 64) as usize].fetch_or(mask, Ordering::Relaxed);
+            new |= previous & mask == 0;
+        }
+        if new {
+            self.new_insertions.fetch_add(1, Ordering::Relaxed);
+        }
+        new
+    }
+
+    pub fn contains_hash(&self, hash: u64) -> bool {
+        self.positions(hash).all(|position| {
+            self.bits[(position / 64) as usize].load(Ordering::Relaxed) & (1 << (position % 64)) != 0
+        })
+    }
+
+    /// Number of insertions of elements the filter did not contain.
+    /// False positives make it a lower bound of the number of distinct elements inserted.
+    pub fn new_insertions(&self) -> usize {
+        self.new_insertions.load(Ordering::Relaxed)
+    }
+
+    /// Fraction of the bits set
+    pub fn fill_ratio(&self) -> f64 {
+        let ones: u64 = self.bits.iter().map(|word| word.load(Ordering::Relaxed).count_ones() as u64).sum();
+        ones as f64 / self.num_bits() as f64
+    }
+
+    /// Probability that an element not inserted is reported as present, given the current fill ratio
+    pub fn false_positive_rate(&self) -> f64 {
+        self.fill_ratio().powi(self.num_hashes as i32)
+    }
+
+    /// Writes the filter of `k`-mers counted on `strand`
+    pub fn write(&self, k: usize, strand: Strand, mut out: impl Write) -> io::Result<()> {
+        out.write_all(&MAGIC)?;
+        out.write_all(&(k as u32).to_le_bytes())?;
+        out.write_all(&strand_flags(strand).to_le_bytes())?;
+        out.write_all(&self.num_bits().to_le_bytes())?;
+        out.write_all(&self.num_hashes.to_le_bytes())?;
+        out.write_all(&(self.new_insertions() as u64).to_le_bytes())?;
+        for word in &self.bits {
+            out.write_all(&word.load(Ordering::Relaxed).to_le_bytes())?;
+        }
+        out.flush()
+    }
+
+    /// Reads a filter of `k`-mers counted on `strand`
+    pub fn read(mut input: impl Read, k: usize, strand: Strand) -> io::Result<Self> {
+        let mut header = [0; 36];
+        input.read_exact(&mut header).map_err(truncated)?;
+        if header[..8] != MAGIC {
+            return Err(invalid("not a Bloom file".to_string()));
+        }
+        let file_k = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+        let file_strand = flags_strand(u32::from_le_bytes(header[12..16].try_into().unwrap()));
+        if (file_k, file_strand) != (k, strand) {
+            return Err(invalid(format!(
+                "Bloom file of {}-mers with strand mode {}, expected {}-mers with strand mode {}",
+                file_k, file_strand, k, strand
+            )));
+        }
+        let num_bits = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        if num_bits == 0 || num_bits % 64 != 0 {
+            return Err(invalid(format!("{} bits is not a positive multiple of 64", num_bits)));
+        }
+        let mut bits = Vec::new();
+        let mut word = [0; 8];
+        for _ in 0..num_bits / 64 {
+            input.read_exact(&mut word).map_err(truncated)?;
+            bits.push(AtomicU64::new(u64::from_le_bytes(word)));
+        }
+        Ok(BloomFilter {
+            bits,
+            num_hashes: u32::from_le_bytes(header[24..28].try_into().unwrap()).max(1),
+            new_insertions: AtomicUsize::new(u64::from_le_bytes(header[28..36].try_into().unwrap()) as usize),
+        })
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn truncated(e: io::Error) -> io::Error {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        invalid("truncated Bloom file".to_string())
+    } else {
+        e
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_new_insertions() {
+        let bloom = BloomFilter::new(1 << 16, 4);
+        for _ in 0..2 {
+            for i in 0..1000u64 {
+                bloom.insert_hash(mix64(i));
+            }
+        }
+        assert!((0..1000u64).all(|i| bloom.contains_hash(mix64(i))));
+        assert!(bloom.new_insertions() <= 1000 && bloom.new_insertions() > 990);
+        assert!(bloom.false_positive_rate() < 1e-4);
+    }
+
+    #[test]
+    fn round_trip() {
+        let bloom = BloomFilter::new(100, 3);
+        for i in 0..50u64 {
+            bloom.insert_hash(mix64(i));
+        }
+        let mut file = Vec::new();
+        bloom.write(21, Strand::Canonical, &mut file).unwrap();
+        assert_eq!(file.len(), 36 + 104);
+
+        let reloaded = BloomFilter::read(&file[..], 21, Strand::Canonical).unwrap();
+        assert_eq!((reloaded.num_bits(), reloaded.num_hashes(), reloaded.new_insertions()), (832, 3, bloom.new_insertions()));
+        assert!((0..50u64).all(|i| reloaded.contains_hash(mix64(i))));
+        assert_eq!(reloaded.fill_ratio(), bloom.fill_ratio());
+
+        // other settings and truncated files
+        assert!(BloomFilter::read(&file[..], 21, Strand::Forward).is_err());
+        assert!(BloomFilter::read(&file[..], 31, Strand::Canonical).is_err());
+        let error = BloomFilter::read(&file[..file.len() - 1], 21, Strand::Canonical).unwrap_err();
+        assert_eq!(error.to_string(), "truncated Bloom file");
+    }
+}
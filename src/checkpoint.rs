@@ -0,0 +1,161 @@
+//! Checkpoints of a count over several files, to resume it after a crash.
+//!
+//! A checkpoint is a binary dump of the distinct k-mers (see `binary`), also readable with `read_binary`,
+//! followed by the progress: the magic bytes `UKCHKPT\x05`, the number of files counted (u32), the path of each
+//! of them (its length in bytes as u32, then its UTF-8 bytes), the counters of `KmerStats` accumulated over these
+//! files as u64 (total_nucleotides, records, total_kmers, valid_kmers, acgt_nucleotides, gc_nucleotides,
+//! n_nucleotides, n_kmers, skipped_records, short_records, invalid_records, duplicate_records,
+//! unmatched_records, low_complexity_kmers, excluded_kmers) and limit_reached (u8).
+//! All integers are little-endian.
+//!
+//! A checkpoint is only valid for the same k and strand mode, recorded in the header and checked on reading,
+//! and the same other counting settings, which are not recorded.
+
+use crate::binary::{read_binary_with, write_binary_iter};
+use crate::kmer::{Kmer, Strand};
+use crate::KmerStats;
+use std::io::{self, Read, Write};
+
+pub const MAGIC: [u8; 8] = *b"UKCHKPT\x05";
+
+/// Progress of a count: the files counted, in order, and their accumulated statistics
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub files: Vec<String>,
+    /// Statistics of the files, `distinct` being that of the k-mers of the checkpoint
+    pub stats: KmerStats,
+}
+
+fn counters(stats: &KmerStats) -> [usize; 15] {
+    [
+        stats.total_nucleotides,
+        stats.records,
+        stats.total_kmers,
+        stats.valid_kmers,
+        stats.acgt_nucleotides,
+        stats.gc_nucleotides,
+        stats.n_nucleotides,
+        stats.n_kmers,
+        stats.skipped_records,
+        stats.short_records,
+        stats.invalid_records,
+        stats.duplicate_records,
+        stats.unmatched_records,
+        stats.low_complexity_kmers,
+        stats.excluded_kmers,
+    ]
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid checkpoint: {}", message))
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+impl Checkpoint {
+    /// Writes the `count` distinct k-mers of `kmers`, then the progress
+    pub fn write<T: Kmer>(&self, count: u64, kmers: impl Iterator<Item = T>, k: usize, strand: Strand, mut out: impl Write) -> io::Result<()> {
+        write_binary_iter(count, kmers, k, strand, &mut out)?;
+        out.write_all(&MAGIC)?;
+        out.write_all(&(self.files.len() as u32).to_le_bytes())?;
+        for file in &self.files {
+            out.write_all(&(file.len() as u32).to_le_bytes())?;
+            out.write_all(file.as_bytes())?;
+        }
+        for counter in counters(&self.stats) {
+            out.write_all(&(counter as u64).to_le_bytes())?;
+        }
+        out.write_all(&[self.stats.limit_reached as u8])?;
+        out.flush()
+    }
+
+    /// Reads a checkpoint of `k`-mers counted on `strand`, passing each k-mer to `insert`
+    pub fn read<T: Kmer>(mut input: impl Read, k: usize, strand: Strand, insert: impl FnMut(T)) -> io::Result<Self> {
+        let distinct = read_binary_with(&mut input, k, strand, insert)?;
+        Self::read_progress(&mut input, distinct as usize).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid("truncated"),
+            _ => e,
+        })
+    }
+
+    fn read_progress(input: &mut impl Read, distinct: usize) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid("no progress after the k-mers"));
+        }
+        let mut files = Vec::new();
+        for _ in 0..read_u32(input)? {
+            let mut path = vec![0; read_u32(input)? as usize];
+            input.read_exact(&mut path)?;
+            files.push(String::from_utf8(path).map_err(|_| invalid("path not in UTF-8"))?);
+        }
+        // in the order of `counters`
+        let mut counter = || read_u64(input).map(|value| value as usize);
+        let mut stats = KmerStats {
+            distinct,
+            total_nucleotides: counter()?,
+            records: counter()?,
+            total_kmers: counter()?,
+            valid_kmers: counter()?,
+            acgt_nucleotides: counter()?,
+            gc_nucleotides: counter()?,
+            n_nucleotides: counter()?,
+            n_kmers: counter()?,
+            skipped_records: counter()?,
+            short_records: counter()?,
+            invalid_records: counter()?,
+            duplicate_records: counter()?,
+            unmatched_records: counter()?,
+            low_complexity_kmers: counter()?,
+            excluded_kmers: counter()?,
+            limit_reached: false,
+            interrupted: false,
+        };
+        let mut limit_reached = [0];
+        input.read_exact(&mut limit_reached)?;
+        stats.limit_reached = limit_reached[0] != 0;
+        Ok(Checkpoint { files, stats })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn round_trip() {
+        let kmers: Vec<u64> = vec![3, 14, 15];
+        let checkpoint = Checkpoint {
+            files: vec!["a.fa".to_string(), "b.fq.gz".to_string()],
+            stats: KmerStats { distinct: 3, total_nucleotides: 100, records: 4, valid_kmers: 80, limit_reached: true, ..KmerStats::default() },
+        };
+        let mut file = Vec::new();
+        checkpoint.write(3, kmers.iter().copied(), 5, Strand::Canonical, &mut file).unwrap();
+
+        let mut read = HashSet::new();
+        let reloaded = Checkpoint::read(&file[..], 5, Strand::Canonical, |kmer: u64| {
+            read.insert(kmer);
+        })
+        .unwrap();
+        assert_eq!(reloaded, checkpoint);
+        assert_eq!(read, kmers.into_iter().collect());
+
+        // a plain binary dump of the k-mers, other settings and truncated progress
+        assert_eq!(crate::binary::read_binary_with(&file[..], 5, Strand::Canonical, |_: u64| {}).unwrap(), 3);
+        assert!(Checkpoint::read(&file[..], 5, Strand::Forward, |_: u64| {}).is_err());
+        let error = Checkpoint::read(&file[..file.len() - 1], 5, Strand::Canonical, |_: u64| {}).unwrap_err();
+        assert_eq!(error.to_string(), "invalid checkpoint: truncated");
+    }
+}
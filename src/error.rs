@@ -0,0 +1,84 @@
+//! Errors of a count, telling invalid settings from unreadable or malformed inputs
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Failure of a count
+#[derive(Debug)]
+pub enum KmerError {
+    /// k out of the range of the k-mer type, up to `max`
+    InvalidK { k: usize, max: usize },
+    /// Settings of the counter that cannot be used together, or out of range
+    InvalidSettings(String),
+    /// A record that cannot be parsed, the input being unreadable past it. Records are numbered from 1.
+    ParseError { record: usize, message: String },
+    /// A record parsed but not valid, such as an empty sequence, unless invalid records are skipped
+    InvalidRecord { record: usize, id: String, problem: String },
+    /// More records than the records of each k-mer can be kept for
+    TooManyRecords { max: usize },
+    /// An input that cannot be opened or read
+    Io(io::Error),
+}
+
+impl fmt::Display for KmerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KmerError::InvalidK { max, .. } => write!(f, "k must be between 1 and {}", max),
+            KmerError::InvalidSettings(message) => f.write_str(message),
+            KmerError::ParseError { record, message } => write!(f, "record {}: {}", record, message),
+            KmerError::InvalidRecord { record, id, problem } => write!(f, "record {} ({}): {}", record, id, problem),
+            KmerError::TooManyRecords { max } => {
+                write!(f, "more than {} records: the records of each k-mer are only kept for up to {}", max, max)
+            }
+            KmerError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for KmerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            KmerError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for KmerError {
+    fn from(e: io::Error) -> Self {
+        KmerError::Io(e)
+    }
+}
+
+/// The I/O error itself for `Io`, otherwise an error of kind `InvalidInput` for the settings
+/// and `InvalidData` for the records, wrapping the `KmerError`
+impl From<KmerError> for io::Error {
+    fn from(e: KmerError) -> Self {
+        let kind = match e {
+            KmerError::Io(e) => return e,
+            KmerError::InvalidK { .. } | KmerError::InvalidSettings(_) | KmerError::TooManyRecords { .. } => io::ErrorKind::InvalidInput,
+            KmerError::ParseError { .. } | KmerError::InvalidRecord { .. } => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_errors_keep_their_kind() {
+        let error = io::Error::from(KmerError::ParseError { record: 3, message: "no sequence".to_string() });
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "record 3: no sequence");
+        assert!(matches!(error.get_ref().and_then(|e| e.downcast_ref::<KmerError>()), Some(KmerError::ParseError { record: 3, .. })));
+
+        let error = io::Error::from(KmerError::InvalidK { k: 0, max: 32 });
+        assert_eq!((error.kind(), error.to_string()), (io::ErrorKind::InvalidInput, "k must be between 1 and 32".to_string()));
+
+        let error = io::Error::from(KmerError::from(io::Error::new(io::ErrorKind::NotFound, "gone")));
+        assert_eq!((error.kind(), error.to_string()), (io::ErrorKind::NotFound, "gone".to_string()));
+    }
+}
@@ -0,0 +1,225 @@
+//! Set of distinct k-mers in external memory, for more k-mers than fit in memory.
+//!
+//! K-mers are buffered in memory, in shards by hash so that threads rarely wait for each other. A full buffer is
+//! sorted and deduplicated, then written as a run: a temporary file of the encoded k-mers (`Kmer::write_le`) in
+//! increasing order. The distinct k-mers are those of a merge of all the runs, which keeps each k-mer once.
+//! Runs are written to a directory of their own, removed with the set.
+
+use crate::kmer::Kmer;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// Largest number of runs merged at once, to stay within the limit of open files.
+/// More runs are first merged by groups into longer runs.
+const MAX_MERGED_RUNS: usize = 256;
+
+/// Number of sets created by the process, to name their directories
+static SETS: AtomicUsize = AtomicUsize::new(0);
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Next k-mer of a run, None at its end
+fn read_kmer<T: Kmer>(run: &mut impl Read) -> io::Result<Option<T>> {
+    let mut bytes = [0; 16];
+    match run.read_exact(&mut bytes[..T::BYTES]) {
+        Ok(()) => Ok(Some(T::read_le(&bytes))),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Distinct k-mers, buffered in memory up to a limit and sorted into runs on disk beyond
+#[derive(Debug)]
+pub struct ExternalSet<T> {
+    dir: PathBuf,
+    shards: Vec<Mutex<Vec<T>>>,
+    /// Number of k-mers of a full shard buffer
+    shard_capacity: usize,
+    runs: Mutex<Vec<PathBuf>>,
+    /// Number of runs written, including those merged into longer runs
+    runs_written: AtomicUsize,
+    /// First error writing a run, returned by `merge`
+    error: Mutex<Option<io::Error>>,
+    /// Number of distinct k-mers found by the last merge
+    distinct: AtomicUsize,
+}
+
+impl<T: Kmer> ExternalSet<T> {
+    /// Set writing its runs to a new directory in `dir`, buffering up to `memory_limit` bytes of k-mers
+    /// in `shards` shards
+    pub fn new(dir: impl AsRef<Path>, memory_limit: usize, shards: usize) -> io::Result<Self> {
+        let dir = dir.as_ref().join(format!("ukc-{}-{}", process::id(), SETS.fetch_add(1, Ordering::Relaxed)));
+        fs::create_dir_all(&dir)?;
+        let shards = shards.max(1);
+        Ok(ExternalSet {
+            dir,
+            shards: (0..shards).map(|_| Mutex::new(Vec::new())).collect(),
+            shard_capacity: (memory_limit / shards / T::BYTES).max(1),
+            runs: Mutex::new(Vec::new()),
+            runs_written: AtomicUsize::new(0),
+            error: Mutex::new(None),
+            distinct: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn insert(&self, kmer: T) {
+        let mut buffer = lock(&self.shards[(kmer.hash64() % self.shards.len() as u64) as usize]);
+        if buffer.capacity() == 0 {
+            buffer.reserve_exact(self.shard_capacity);
+        }
+        buffer.push(kmer);
+        if buffer.len() >= self.shard_capacity {
+            buffer.sort_unstable();
+            buffer.dedup();
+            // many duplicates: the buffer can take more k-mers before a run is written
+            if buffer.len() > self.shard_capacity / 2 {
+                self.spill(&mut buffer);
+            }
+        }
+    }
+
+    /// Writes the sorted k-mers of `buffer` as a run, keeping the error if any, and empties it
+    fn spill(&self, buffer: &mut Vec<T>) {
+        match self.write_run(|out| buffer.iter().try_for_each(|&kmer| out(kmer))) {
+            Ok(run) => lock(&self.runs).push(run),
+            Err(e) => {
+                lock(&self.error).get_or_insert(e);
+            }
+        }
+        buffer.clear();
+    }
+
+    /// Writes a new run of the k-mers passed by `write` to the function it is given, in increasing order.
+    /// Returns its path.
+    fn write_run(&self, write: impl FnOnce(&mut dyn FnMut(T) -> io::Result<()>) -> io::Result<()>) -> io::Result<PathBuf> {
+        let path = self.dir.join(format!("run-{}", self.runs_written.fetch_add(1, Ordering::Relaxed)));
+        let mut out = BufWriter::new(File::create(&path)?);
+        let mut bytes = [0; 16];
+        write(&mut |kmer: T| {
+            kmer.write_le(&mut bytes);
+            out.write_all(&bytes[..T::BYTES])
+        })?;
+        out.flush()?;
+        Ok(path)
+    }
+
+    /// Number of runs written so far
+    pub fn runs(&self) -> usize {
+        self.runs_written.load(Ordering::Relaxed)
+    }
+
+    /// Number of distinct k-mers found by the last `merge`, 0 before
+    pub fn len(&self) -> usize {
+        self.distinct.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes the k-mers still in memory as runs, then merges all the runs, passing each distinct k-mer to `on_kmer`
+    /// in increasing order. Returns the number of distinct k-mers, or the first error writing or reading a run.
+    /// K-mers inserted afterwards are merged with the same runs by the next call.
+    pub fn merge(&self, mut on_kmer: impl FnMut(T)) -> io::Result<usize> {
+        for shard in &self.shards {
+            let mut buffer = lock(shard);
+            if !buffer.is_empty() {
+                buffer.sort_unstable();
+                buffer.dedup();
+                self.spill(&mut buffer);
+            }
+            *buffer = Vec::new();
+        }
+        if let Some(e) = lock(&self.error).take() {
+            return Err(e);
+        }
+        let mut runs = lock(&self.runs);
+        while runs.len() > MAX_MERGED_RUNS {
+            let group: Vec<PathBuf> = runs.drain(..MAX_MERGED_RUNS).collect();
+            let merged = self.write_run(|out| merge_runs(&group, out).map(|_| ()))?;
+            runs.push(merged);
+            for run in group {
+                fs::remove_file(run)?;
+            }
+        }
+        let distinct = merge_runs(&runs, &mut |kmer| {
+            on_kmer(kmer);
+            Ok(())
+        })?;
+        self.distinct.store(distinct, Ordering::Relaxed);
+        Ok(distinct)
+    }
+}
+
+impl<T> Drop for ExternalSet<T> {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Merges sorted runs, passing each distinct k-mer to `on_kmer` in increasing order. Returns their number.
+fn merge_runs<T: Kmer>(runs: &[PathBuf], on_kmer: &mut dyn FnMut(T) -> io::Result<()>) -> io::Result<usize> {
+    let mut readers = runs.iter().map(|run| File::open(run).map(BufReader::new)).collect::<io::Result<Vec<_>>>()?;
+    // the smallest next k-mer of each run, with the index of its run
+    let mut heap = BinaryHeap::new();
+    for (i, reader) in readers.iter_mut().enumerate() {
+        if let Some(kmer) = read_kmer::<T>(reader)? {
+            heap.push(Reverse((kmer, i)));
+        }
+    }
+    let mut last = None;
+    let mut distinct = 0;
+    while let Some(Reverse((kmer, i))) = heap.pop() {
+        if last != Some(kmer) {
+            on_kmer(kmer)?;
+            last = Some(kmer);
+            distinct += 1;
+        }
+        if let Some(next) = read_kmer(&mut readers[i])? {
+            heap.push(Reverse((next, i)));
+        }
+    }
+    Ok(distinct)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KmerCounter, KmerStore};
+    use dashmap::DashSet;
+
+    #[test]
+    fn merged_runs_give_the_distinct_kmers() {
+        // one k-mer per buffer: a run per distinct k-mer, merged in two passes
+        let set = ExternalSet::<u64>::new(std::env::temp_dir(), 8, 1).unwrap();
+        let kmers: Vec<u64> = (0..1000).map(|i| i * 7919 % 600).collect();
+        kmers.iter().for_each(|&kmer| set.insert(kmer));
+        let mut merged = Vec::new();
+        assert_eq!(set.merge(|kmer| merged.push(kmer)).unwrap(), 600);
+        assert_eq!(merged, (0..600).collect::<Vec<u64>>());
+        assert!(set.runs() > MAX_MERGED_RUNS);
+        let dir = set.dir.clone();
+        drop(set);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn counts_match_those_in_memory() {
+        let fasta: Vec<u8> = (0..200).flat_map(|i| format!(">r{}\nACGTTGCA{}GGCATTAC\n", i, "ACGT".repeat(i % 7)).into_bytes()).collect();
+        let counter = KmerCounter::new(9).canonical(true);
+        let kmers = DashSet::<u128>::new();
+        let expected = counter.count_reader(&fasta[..], KmerStore::Set(&kmers)).unwrap();
+        let set = ExternalSet::<u128>::new(std::env::temp_dir(), 256, 4).unwrap();
+        let stats = counter.count_reader(&fasta[..], KmerStore::<u128>::External(&set)).unwrap();
+        assert_eq!(stats.valid_kmers, expected.valid_kmers);
+        assert_eq!(set.merge(|kmer| assert!(kmers.contains(&kmer))).unwrap(), expected.distinct);
+    }
+}
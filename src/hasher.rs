@@ -0,0 +1,69 @@
+//! Fast seeded hashing of encoded k-mers for the k-mer sets and maps.
+//!
+//! Encoded k-mers are integers, so a single splitmix64 finalizer spreads them well enough
+//! for hash tables, much faster than the default SipHash.
+
+use crate::kmer::mix64;
+use std::hash::{BuildHasher, Hasher};
+
+/// Hasher of encoded k-mers, built by `KmerBuildHasher`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KmerHasher {
+    state: u64,
+}
+
+impl Hasher for KmerHasher {
+    fn finish(&self) -> u64 {
+        mix64(self.state)
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.state = mix64(self.state ^ n);
+    }
+
+    fn write_u128(&mut self, n: u128) {
+        self.write_u64(n as u64);
+        self.write_u64((n >> 64) as u64);
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_u64(u64::from_le_bytes(word));
+        }
+    }
+}
+
+/// Builds `KmerHasher`s from a seed: the same seed gives the same hashes, hence the same sharding
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KmerBuildHasher {
+    seed: u64,
+}
+
+impl KmerBuildHasher {
+    pub fn new(seed: u64) -> Self {
+        KmerBuildHasher { seed }
+    }
+}
+
+impl BuildHasher for KmerBuildHasher {
+    type Hasher = KmerHasher;
+
+    fn build_hasher(&self) -> KmerHasher {
+        KmerHasher { state: self.seed }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_hashes() {
+        let (a, b) = (KmerBuildHasher::new(1), KmerBuildHasher::new(2));
+        assert_eq!(a.hash_one(42u64), KmerBuildHasher::new(1).hash_one(42u64));
+        assert_ne!(a.hash_one(42u64), b.hash_one(42u64));
+        assert_ne!(a.hash_one(1u128), a.hash_one(1u128 << 64));
+    }
+}
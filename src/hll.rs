@@ -0,0 +1,88 @@
+//! HyperLogLog cardinality estimator, for approximate distinct counts in constant memory
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+pub const MIN_PRECISION: u8 = 4;
+pub const MAX_PRECISION: u8 = 18;
+
+/// HyperLogLog sketch with 2^precision registers.
+/// Registers are atomics so that threads can insert concurrently.
+#[derive(Debug)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<AtomicU8>,
+}
+
+impl HyperLogLog {
+    /// Panics if `precision` is not between `MIN_PRECISION` and `MAX_PRECISION`
+    pub fn new(precision: u8) -> Self {
+        assert!(
+            (MIN_PRECISION..=MAX_PRECISION).contains(&precision),
+            "HyperLogLog precision must be between {} and {}",
+            MIN_PRECISION,
+            MAX_PRECISION
+        );
+        HyperLogLog {
+            precision,
+            registers: (0..1usize << precision).map(|_| AtomicU8::new(0)).collect(),
+        }
+    }
+
+    /// Inserts an element given its (uniformly distributed) 64-bit hash
+    pub fn insert_hash(&self, hash: u64) {
+        let index = (hash >> (64 - self.precision)) as usize;
+        let rank = ((hash << self.precision).leading_zeros() + 1).min(64 - self.precision as u32 + 1) as u8;
+        self.registers[index].fetch_max(rank, Ordering::Relaxed);
+    }
+
+    /// Estimated number of distinct elements inserted
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let (sum, zeros) = self.registers.iter().fold((0.0, 0), |(sum, zeros), register| {
+            let rank = register.load(Ordering::Relaxed);
+            (sum + 2f64.powi(-(rank as i32)), zeros + (rank == 0) as usize)
+        });
+        let estimate = alpha * m * m / sum;
+        if estimate <= 2.5 * m && zeros > 0 {
+            // small range correction: linear counting
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+
+    /// Standard error of the estimate, relative to the true cardinality
+    pub fn relative_error(&self) -> f64 {
+        1.04 / (self.registers.len() as f64).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kmer::mix64;
+
+    #[test]
+    fn estimate_is_within_error() {
+        let hll = HyperLogLog::new(14);
+        for _ in 0..2 {
+            for i in 0..100_000u64 {
+                hll.insert_hash(mix64(i));
+            }
+        }
+        let error = (hll.estimate() - 100_000.0).abs() / 100_000.0;
+        assert!(error < 3.0 * hll.relative_error(), "error {}", error);
+
+        let small = HyperLogLog::new(14);
+        for i in 0..100u64 {
+            small.insert_hash(mix64(i));
+        }
+        assert!((small.estimate() - 100.0).abs() < 2.0);
+    }
+}
@@ -0,0 +1,556 @@
+//! 2-bit encoding of k-mers (A=00, C=01, G=10, T=11)
+
+/// Lowercase (soft-masked) nucleotides are encoded as their uppercase counterpart,
+/// and the U of RNA as T
+pub const fn nucleotide_to_bits(n: u8) -> Option<u64> {
+    match n {
+        b'A' | b'a' => Some(0b00),
+        b'C' | b'c' => Some(0b01),
+        b'G' | b'g' => Some(0b10),
+        b'T' | b't' | b'U' | b'u' => Some(0b11),
+        _ => None,
+    }
+}
+
+/// Nucleotides represented by an IUPAC code
+pub const fn iupac_nucleotides(n: u8) -> Option<&'static [u8]> {
+    match n.to_ascii_uppercase() {
+        b'A' => Some(b"A"),
+        b'C' => Some(b"C"),
+        b'G' => Some(b"G"),
+        b'T' | b'U' => Some(b"T"),
+        b'R' => Some(b"AG"),
+        b'Y' => Some(b"CT"),
+        b'S' => Some(b"CG"),
+        b'W' => Some(b"AT"),
+        b'K' => Some(b"GT"),
+        b'M' => Some(b"AC"),
+        b'B' => Some(b"CGT"),
+        b'D' => Some(b"AGT"),
+        b'H' => Some(b"ACT"),
+        b'V' => Some(b"ACG"),
+        b'N' => Some(b"ACGT"),
+        _ => None,
+    }
+}
+
+/// All ACGT sequences a window containing IUPAC codes may stand for.
+/// Returns `None` if the window contains more than `max_ambiguous` ambiguous positions
+/// or a character that is not an IUPAC code.
+pub fn expand_iupac(window: &[u8], max_ambiguous: usize) -> Option<Vec<Vec<u8>>> {
+    let mut ambiguous = 0;
+    let mut resolutions = vec![Vec::with_capacity(window.len())];
+    for &n in window {
+        let nucleotides = iupac_nucleotides(n)?;
+        if nucleotides.len() > 1 {
+            ambiguous += 1;
+            if ambiguous > max_ambiguous {
+                return None;
+            }
+        }
+        resolutions = resolutions
+            .into_iter()
+            .flat_map(|resolution| {
+                nucleotides.iter().map(move |&nucleotide| {
+                    let mut resolution = resolution.clone();
+                    resolution.push(nucleotide);
+                    resolution
+                })
+            })
+            .collect();
+    }
+    Some(resolutions)
+}
+
+const BITS_TO_NUCLEOTIDE: [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+pub fn kmer_to_u64(sequence: &[u8]) -> Option<u64> {
+    if sequence.len() > 32 {
+        return None;
+    }
+
+    let mut encoded: u64 = 0;
+    for &nucleotide in sequence {
+        encoded = (encoded << 2) | nucleotide_to_bits(nucleotide)?;
+    }
+    Some(encoded)
+}
+
+pub fn kmer_to_u128(sequence: &[u8]) -> Option<u128> {
+    if sequence.len() > 64 {
+        return None;
+    }
+
+    let mut encoded: u128 = 0;
+    for &nucleotide in sequence {
+        encoded = (encoded << 2) | nucleotide_to_bits(nucleotide)? as u128;
+    }
+    Some(encoded)
+}
+
+/// Inverse of `kmer_to_u64`
+pub fn u64_to_kmer(encoded: u64, k: usize) -> String {
+    (0..k)
+        .rev()
+        .map(|i| BITS_TO_NUCLEOTIDE[((encoded >> (2 * i)) & 0b11) as usize] as char)
+        .collect()
+}
+
+/// Inverse of `kmer_to_u128`
+pub fn u128_to_kmer(encoded: u128, k: usize) -> String {
+    (0..k)
+        .rev()
+        .map(|i| BITS_TO_NUCLEOTIDE[((encoded >> (2 * i)) & 0b11) as usize] as char)
+        .collect()
+}
+
+/// Reverse complement of a k-mer encoded with `kmer_to_u64`.
+/// With A=00, C=01, G=10, T=11 the complement of a base is a bit flip,
+/// so we flip all bits, reverse the order of the 2-bit groups and
+/// shift the result back to the k lowest groups.
+pub fn reverse_complement_u64(encoded: u64, k: usize) -> u64 {
+    let mut rc = !encoded;
+    rc = ((rc >> 2) & 0x3333_3333_3333_3333) | ((rc & 0x3333_3333_3333_3333) << 2);
+    rc = ((rc >> 4) & 0x0F0F_0F0F_0F0F_0F0F) | ((rc & 0x0F0F_0F0F_0F0F_0F0F) << 4);
+    rc = rc.swap_bytes();
+    rc >> (64 - 2 * k)
+}
+
+/// Same as `reverse_complement_u64` for k-mers encoded with `kmer_to_u128`
+pub fn reverse_complement_u128(encoded: u128, k: usize) -> u128 {
+    const M2: u128 = 0x3333_3333_3333_3333_3333_3333_3333_3333;
+    const M4: u128 = 0x0F0F_0F0F_0F0F_0F0F_0F0F_0F0F_0F0F_0F0F;
+    let mut rc = !encoded;
+    rc = ((rc >> 2) & M2) | ((rc & M2) << 2);
+    rc = ((rc >> 4) & M4) | ((rc & M4) << 4);
+    rc = rc.swap_bytes();
+    rc >> (128 - 2 * k)
+}
+
+/// Bit mixer (splitmix64 finalizer) spreading packed k-mers uniformly over 64 bits
+pub const fn mix64(mut x: u64) -> u64 {
+    x ^= x >> 30;
+    x = x.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x ^= x >> 27;
+    x = x.wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Packed representation of a k-mer, 2 bits per nucleotide.
+/// `u64` holds k-mers up to k=32, `u128` up to k=64.
+/// The encoding converts to a `u128` without loss, to compare k-mers of either encoding.
+pub trait Kmer: Copy + Default + Eq + Ord + std::hash::Hash + Send + Sync + Into<u128> {
+    /// Largest k this encoding can hold
+    const MAX_K: usize;
+
+    /// Size of the encoding in bytes
+    const BYTES: usize;
+
+    fn encode(sequence: &[u8]) -> Option<Self>;
+
+    fn decode(self, k: usize) -> String;
+
+    fn reverse_complement(self, k: usize) -> Self;
+
+    /// Stable 64-bit hash of the encoded k-mer
+    fn hash64(self) -> u64;
+
+    /// Appends a nucleotide (as returned by `nucleotide_to_bits`) to the right of the k-mer,
+    /// dropping its leftmost nucleotide
+    fn push(self, bits: u64, k: usize) -> Self;
+
+    /// Prepends the complement of a nucleotide to the left of a reverse complement k-mer,
+    /// dropping its rightmost nucleotide. Keeps the reverse complement in sync with `push`.
+    fn push_reverse_complement(self, bits: u64, k: usize) -> Self;
+
+    /// Writes the encoding, little-endian, to the first `BYTES` bytes of `bytes`
+    fn write_le(self, bytes: &mut [u8]);
+
+    /// Reads an encoding written by `write_le`
+    fn read_le(bytes: &[u8]) -> Self;
+
+    /// Top `bits` bits of the 2k-bit encoding, that is the first `bits / 2` nucleotides.
+    /// `bits` must not exceed 2k.
+    fn prefix(self, k: usize, bits: u32) -> usize;
+
+    /// Canonical form of an encoded k-mer: the smallest of the k-mer and its reverse complement
+    fn canonical(self, k: usize) -> Self {
+        self.min(self.reverse_complement(k))
+    }
+}
+
+impl Kmer for u64 {
+    const MAX_K: usize = 32;
+    const BYTES: usize = 8;
+
+    fn encode(sequence: &[u8]) -> Option<Self> {
+        kmer_to_u64(sequence)
+    }
+
+    fn decode(self, k: usize) -> String {
+        u64_to_kmer(self, k)
+    }
+
+    fn reverse_complement(self, k: usize) -> Self {
+        reverse_complement_u64(self, k)
+    }
+
+    fn hash64(self) -> u64 {
+        mix64(self)
+    }
+
+    fn push(self, bits: u64, k: usize) -> Self {
+        ((self << 2) | bits) & (u64::MAX >> (64 - 2 * k))
+    }
+
+    fn write_le(self, bytes: &mut [u8]) {
+        bytes[..8].copy_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_le(bytes: &[u8]) -> Self {
+        u64::from_le_bytes(bytes[..8].try_into().unwrap())
+    }
+
+    fn prefix(self, k: usize, bits: u32) -> usize {
+        self.checked_shr(2 * k as u32 - bits).unwrap_or(0) as usize
+    }
+
+    fn push_reverse_complement(self, bits: u64, k: usize) -> Self {
+        (self >> 2) | ((bits ^ 0b11) << (2 * (k - 1)))
+    }
+}
+
+impl Kmer for u128 {
+    const MAX_K: usize = 64;
+    const BYTES: usize = 16;
+
+    fn encode(sequence: &[u8]) -> Option<Self> {
+        kmer_to_u128(sequence)
+    }
+
+    fn decode(self, k: usize) -> String {
+        u128_to_kmer(self, k)
+    }
+
+    fn reverse_complement(self, k: usize) -> Self {
+        reverse_complement_u128(self, k)
+    }
+
+    fn hash64(self) -> u64 {
+        mix64(self as u64 ^ mix64((self >> 64) as u64))
+    }
+
+    fn push(self, bits: u64, k: usize) -> Self {
+        ((self << 2) | bits as u128) & (u128::MAX >> (128 - 2 * k))
+    }
+
+    fn write_le(self, bytes: &mut [u8]) {
+        bytes[..16].copy_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_le(bytes: &[u8]) -> Self {
+        u128::from_le_bytes(bytes[..16].try_into().unwrap())
+    }
+
+    fn prefix(self, k: usize, bits: u32) -> usize {
+        self.checked_shr(2 * k as u32 - bits).unwrap_or(0) as usize
+    }
+
+    fn push_reverse_complement(self, bits: u64, k: usize) -> Self {
+        (self >> 2) | (((bits ^ 0b11) as u128) << (2 * (k - 1)))
+    }
+}
+
+/// What becomes of the ambiguous bases (IUPAC codes other than A, C, G, T, such as N)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NPolicy {
+    /// Left as is: the windows containing them are not valid
+    #[default]
+    Skip,
+    /// Replaced with A
+    A,
+    /// Replaced with one of the nucleotides they stand for, chosen pseudo-randomly
+    Random,
+}
+
+/// Strands on which k-mers are counted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strand {
+    /// K-mers as read
+    #[default]
+    Forward,
+    /// The smallest of a k-mer and its reverse complement, so that both count once
+    Canonical,
+    /// A k-mer and its reverse complement as two k-mers, palindromes counting once
+    Both,
+}
+
+impl Strand {
+    pub fn name(self) -> &'static str {
+        match self {
+            Strand::Forward => "forward",
+            Strand::Canonical => "canonical",
+            Strand::Both => "both",
+        }
+    }
+
+    /// K-mers counted for a window encoded as `forward`, whose reverse complement is `reverse`
+    pub fn kmers<T: Kmer>(self, forward: T, reverse: T) -> impl Iterator<Item = T> {
+        let (first, second) = match self {
+            Strand::Forward => (forward, None),
+            Strand::Canonical => (forward.min(reverse), None),
+            Strand::Both => (forward, (reverse != forward).then_some(reverse)),
+        };
+        std::iter::once(first).chain(second)
+    }
+
+    /// Number of distinct k-mers that can be counted: 4^k, or (4^k + P) / 2 canonical k-mers, the P = 4^(k/2)
+    /// palindromes of an even k being their own reverse complement. None if it does not fit in a u128.
+    pub fn max_kmers(self, k: usize) -> Option<u128> {
+        let kmers = 4u128.checked_pow(k as u32)?;
+        Some(match self {
+            Strand::Forward | Strand::Both => kmers,
+            Strand::Canonical => {
+                let palindromes = if k.is_multiple_of(2) { 4u128.pow(k as u32 / 2) } else { 0 };
+                kmers / 2 + palindromes / 2
+            }
+        })
+    }
+}
+
+/// Spaced seed: only the nucleotides at the `1` positions of a mask such as `1101011` make the k-mer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpacedSeed {
+    positions: Vec<usize>,
+    span: usize,
+}
+
+impl SpacedSeed {
+    /// Number of `1` positions, the size of the k-mers
+    pub fn weight(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Length of the mask, the size of the windows
+    pub fn span(&self) -> usize {
+        self.span
+    }
+
+    /// Offsets of the `1` positions in the window
+    pub fn positions(&self) -> &[usize] {
+        &self.positions
+    }
+}
+
+impl std::str::FromStr for SpacedSeed {
+    type Err = String;
+
+    fn from_str(mask: &str) -> Result<Self, Self::Err> {
+        if !mask.bytes().all(|c| c == b'0' || c == b'1') {
+            return Err(format!("invalid spaced seed {}: only 0 and 1 are allowed", mask));
+        }
+        let positions: Vec<usize> = mask.bytes().enumerate().filter(|&(_, c)| c == b'1').map(|(i, _)| i).collect();
+        if positions.is_empty() {
+            return Err(format!("invalid spaced seed {}: no 1 position", mask));
+        }
+        Ok(SpacedSeed { positions, span: mask.len() })
+    }
+}
+
+/// Symbols of the sequences, each encoded on 2 bits by its rank: A, C, G and T (and U as T) by default.
+/// Other alphabets, such as a reduced amino acid alphabet, have up to 4 symbols. Letters are encoded
+/// whatever their case.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alphabet {
+    symbols: Vec<u8>,
+    /// Code of each byte, NOT_A_SYMBOL for the bytes that are not symbols
+    codes: [u8; 256],
+}
+
+const NOT_A_SYMBOL: u8 = u8::MAX;
+
+impl Alphabet {
+    pub fn dna() -> Self {
+        let mut codes = [NOT_A_SYMBOL; 256];
+        for byte in 0..=u8::MAX {
+            if let Some(bits) = nucleotide_to_bits(byte) {
+                codes[byte as usize] = bits as u8;
+            }
+        }
+        Alphabet { symbols: BITS_TO_NUCLEOTIDE.to_vec(), codes }
+    }
+
+    pub fn is_dna(&self) -> bool {
+        *self == Alphabet::dna()
+    }
+
+    pub fn symbols(&self) -> &[u8] {
+        &self.symbols
+    }
+
+    /// 2-bit code of a symbol, as `nucleotide_to_bits` for DNA
+    #[inline]
+    pub fn bits(&self, symbol: u8) -> Option<u64> {
+        let code = self.codes[symbol as usize];
+        (code != NOT_A_SYMBOL).then_some(code as u64)
+    }
+
+    /// Replaces the A, C, G and T of k-mers decoded by `Kmer::decode` with the symbols of the same codes
+    pub fn translate(&self, text: &mut [u8]) {
+        if self.symbols == BITS_TO_NUCLEOTIDE {
+            return;
+        }
+        for byte in text {
+            if let Some(code) = BITS_TO_NUCLEOTIDE.iter().position(|nucleotide| nucleotide == byte) {
+                *byte = self.symbols[code];
+            }
+        }
+    }
+}
+
+impl Default for Alphabet {
+    fn default() -> Self {
+        Alphabet::dna()
+    }
+}
+
+impl std::str::FromStr for Alphabet {
+    type Err = String;
+
+    /// Alphabet of the symbols given in the order of their codes. ACGT is DNA, with U read as T.
+    fn from_str(symbols: &str) -> Result<Self, Self::Err> {
+        if symbols.eq_ignore_ascii_case("ACGT") {
+            return Ok(Alphabet::dna());
+        }
+        if !(2..=4).contains(&symbols.len()) {
+            return Err(format!("invalid alphabet {}: symbols are encoded on 2 bits, so there are 2 to 4 of them", symbols));
+        }
+        let mut codes = [NOT_A_SYMBOL; 256];
+        for (code, symbol) in symbols.bytes().enumerate() {
+            if !symbol.is_ascii_graphic() {
+                return Err(format!("invalid alphabet {}: symbols are printable ASCII characters", symbols));
+            }
+            for case in [symbol.to_ascii_uppercase(), symbol.to_ascii_lowercase()] {
+                if codes[case as usize] != NOT_A_SYMBOL {
+                    return Err(format!("invalid alphabet {}: {} is repeated", symbols, symbol as char));
+                }
+                codes[case as usize] = code as u8;
+                if !symbol.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        }
+        Ok(Alphabet { symbols: symbols.bytes().collect(), codes })
+    }
+}
+
+impl std::fmt::Display for Strand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_kmers_of_each_strand_mode() {
+        assert_eq!(Strand::Forward.max_kmers(1), Some(4));
+        // A/T and C/G
+        assert_eq!(Strand::Canonical.max_kmers(1), Some(2));
+        // 16 2-mers, of which the 4 palindromes AT, CG, GC, TA
+        assert_eq!(Strand::Canonical.max_kmers(2), Some(10));
+        assert_eq!(Strand::Both.max_kmers(2), Some(16));
+        assert_eq!(Strand::Canonical.max_kmers(31), Some(1 << 61));
+        assert_eq!(Strand::Forward.max_kmers(64), None);
+    }
+
+    #[test]
+    fn lowercase_encodes_as_uppercase() {
+        assert_eq!(kmer_to_u64(b"ACgtAc"), kmer_to_u64(b"ACGTAC"));
+        assert_eq!(kmer_to_u64(b"acgtac"), kmer_to_u64(b"ACGTAC"));
+        assert_eq!(kmer_to_u128(b"ACgtAc"), kmer_to_u128(b"ACGTAC"));
+        assert_eq!(kmer_to_u64(b"ACGNAC"), None);
+        assert_eq!(kmer_to_u64(b"ACGnAC"), None);
+    }
+
+    #[test]
+    fn iupac_expansion() {
+        assert_eq!(expand_iupac(b"ACGT", 0), Some(vec![b"ACGT".to_vec()]));
+        assert_eq!(
+            expand_iupac(b"ARGY", 2),
+            Some(vec![b"AAGC".to_vec(), b"AAGT".to_vec(), b"AGGC".to_vec(), b"AGGT".to_vec()])
+        );
+        assert_eq!(expand_iupac(b"ANGN", 2).unwrap().len(), 16);
+        assert_eq!(expand_iupac(b"ARGY", 1), None);
+        assert_eq!(expand_iupac(b"AXGT", 2), None);
+    }
+
+    #[test]
+    fn alphabets_encode_their_symbols() {
+        let dna = Alphabet::dna();
+        assert!(b"ACGTUacgtuN-".iter().all(|&n| dna.bits(n) == nucleotide_to_bits(n)));
+        assert_eq!("acgt".parse::<Alphabet>(), Ok(dna));
+
+        let alphabet: Alphabet = "HP.*".parse().unwrap();
+        assert!(!alphabet.is_dna());
+        assert_eq!([b'H', b'p', b'.', b'*', b'A'].map(|symbol| alphabet.bits(symbol)), [Some(0), Some(1), Some(2), Some(3), None]);
+        let mut decoded = u64_to_kmer(0b11_00_01_10, 4).into_bytes();
+        alphabet.translate(&mut decoded);
+        assert_eq!(decoded, b"*HP.");
+
+        assert!("ACGTN".parse::<Alphabet>().is_err());
+        assert!("H".parse::<Alphabet>().is_err());
+        assert!("Hh".parse::<Alphabet>().is_err());
+        assert!("H P".parse::<Alphabet>().is_err());
+    }
+
+    #[test]
+    fn rolling_encoding_matches_window_encoding() {
+        let seq = b"ACGTTGCAAGGCTAGCTAGGATCCGATTACAGATTACAGGCATGCATCGATCGGGAATTCCAAGTGTCAGTACGTACGATCGTAGCTAGCTAGGCATCAGTCAGGTCA";
+        for k in [1, 5, 31, 32, 33, 64] {
+            let (mut forward, mut reverse) = (0u128, 0u128);
+            let (mut forward64, mut reverse64) = (0u64, 0u64);
+            for (i, &n) in seq.iter().enumerate() {
+                let bits = nucleotide_to_bits(n).unwrap();
+                forward = forward.push(bits, k);
+                reverse = reverse.push_reverse_complement(bits, k);
+                if k <= u64::MAX_K {
+                    forward64 = forward64.push(bits, k);
+                    reverse64 = reverse64.push_reverse_complement(bits, k);
+                }
+                if i + 1 >= k {
+                    let window = &seq[i + 1 - k..=i];
+                    assert_eq!(Some(forward), kmer_to_u128(window));
+                    assert_eq!(reverse, reverse_complement_u128(forward, k));
+                    if k <= u64::MAX_K {
+                        assert_eq!(Some(forward64), kmer_to_u64(window));
+                        assert_eq!(reverse64, reverse_complement_u64(forward64, k));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn strand_kmers() {
+        let forward = kmer_to_u64(b"AAC").unwrap();
+        let reverse = reverse_complement_u64(forward, 3);
+        assert_eq!(Strand::Forward.kmers(forward, reverse).collect::<Vec<_>>(), vec![forward]);
+        assert_eq!(Strand::Canonical.kmers(reverse, forward).collect::<Vec<_>>(), vec![forward]);
+        assert_eq!(Strand::Both.kmers(forward, reverse).collect::<Vec<_>>(), vec![forward, reverse]);
+        let palindrome = kmer_to_u64(b"ACGT").unwrap();
+        assert_eq!(Strand::Both.kmers(palindrome, palindrome).count(), 1);
+    }
+
+    #[test]
+    fn spaced_seed_parsing() {
+        let seed: SpacedSeed = "1101011".parse().unwrap();
+        assert_eq!(seed.weight(), 5);
+        assert_eq!(seed.span(), 7);
+        assert_eq!(seed.positions(), [0, 1, 3, 5, 6]);
+        assert!("0000".parse::<SpacedSeed>().is_err());
+        assert!("11x1".parse::<SpacedSeed>().is_err());
+    }
+}
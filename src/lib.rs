@@ -0,0 +1,2559 @@
+//! Exact counting of distinct k-mers from FASTA/FASTQ inputs.
+//!
+//! ```
+//! let fasta: &[u8] = b">seq\nACGTTGCA\n";
+//! let stats = unique_kmer_counter::count_unique_kmers(fasta, 4, false).unwrap();
+//! assert_eq!(stats.distinct, 5);
+//! ```
+
+pub mod binary;
+pub mod bloom;
+pub mod checkpoint;
+pub mod error;
+pub mod external;
+pub mod hasher;
+pub mod hll;
+pub mod kmer;
+pub mod minhash;
+pub mod minimizer;
+pub mod raw;
+pub mod sharded;
+pub mod twobit;
+
+use dashmap::{DashMap, DashSet};
+use fxread::{FastaReader, FastqReader, FastxRead, Record};
+use rayon::ThreadPoolBuilder;
+use regex::bytes::Regex;
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashSet};
+use std::fmt;
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex, Once};
+
+pub use bloom::BloomFilter;
+pub use error::KmerError;
+pub use external::ExternalSet;
+pub use hasher::KmerBuildHasher;
+pub use hll::HyperLogLog;
+pub use kmer::{Alphabet, Kmer, NPolicy, SpacedSeed, Strand};
+pub use minhash::MinHash;
+pub use sharded::ShardedSet;
+use kmer::{expand_iupac, iupac_nucleotides};
+use minimizer::MinimizerWindow;
+use raw::RawRecords;
+use twobit::TwoBitRecords;
+
+/// Default maximum number of records in flight: read, and waiting for a thread or being counted
+pub const IN_FLIGHT_RECORDS: usize = 4096;
+/// Maximum number of records counted into a `KmerStore::Membership`, one per bit of its bitsets
+pub const MAX_MEMBERSHIP_RECORDS: usize = 64;
+/// Default maximum number of nucleotides of the records handed to a thread at once, which get at least one record
+pub const CHUNK_NUCLEOTIDES: usize = 4 * 1024 * 1024;
+
+/// Hashers usable by the k-mer sets and maps of a `KmerStore`
+pub trait KmerHashing: BuildHasher + Clone + Send + Sync {}
+
+impl<S: BuildHasher + Clone + Send + Sync> KmerHashing for S {}
+
+/// Statistics gathered while counting k-mers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KmerStats {
+    /// Number of distinct valid k-mers (0 when k-mers are only counted, approximate with HyperLogLog, Bloom filters or MinHash sketches)
+    pub distinct: usize,
+    pub total_nucleotides: usize,
+    /// Number of records read, including those skipped or left out of the sample or by the id pattern
+    pub records: usize,
+    /// Number of k-mer windows, valid or not
+    pub total_kmers: usize,
+    /// Number of k-mer windows made only of A, C, G, T
+    pub valid_kmers: usize,
+    /// Number of A, C, G, T nucleotides (case-insensitive), excluding N and other ambiguous bases
+    pub acgt_nucleotides: usize,
+    /// Number of G and C nucleotides (case-insensitive)
+    pub gc_nucleotides: usize,
+    /// Number of N nucleotides (case-insensitive)
+    pub n_nucleotides: usize,
+    /// Number of k-mer windows giving no k-mer and containing an N, among the windows neither valid nor low-complexity
+    pub n_kmers: usize,
+    /// Number of records skipped by the length filters, not included in the other counts
+    pub skipped_records: usize,
+    /// Number of records shorter than a k-mer window, which give no k-mer
+    pub short_records: usize,
+    /// Number of malformed records skipped with `KmerCounter::skip_invalid`, not included in the other counts
+    pub invalid_records: usize,
+    /// Number of records left out as duplicates of a sequence already counted with `KmerCounter::dedup_records`,
+    /// not included in the other counts
+    pub duplicate_records: usize,
+    /// Number of records whose id does not match `KmerCounter::id_regex`, not included in the other counts
+    pub unmatched_records: usize,
+    /// Number of k-mers left out as low-complexity, not included in `valid_kmers`,
+    /// see `KmerCounter::max_homopolymer` and `KmerCounter::min_entropy`
+    pub low_complexity_kmers: usize,
+    /// Number of valid k-mer occurrences left out because they are excluded, see `KmerCounter::exclude`
+    pub excluded_kmers: usize,
+    /// Whether counting stopped early on reaching `KmerCounter::max_distinct`:
+    /// the counts are then those of a sample of the input
+    pub limit_reached: bool,
+    /// Whether counting stopped early because `KmerCounter::interrupt` was set:
+    /// the counts are then those of the beginning of the input
+    pub interrupted: bool,
+}
+
+/// Statistics of a single record
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecordStats {
+    pub id: String,
+    pub length: usize,
+    /// Number of k-mer windows, valid or not
+    pub total_kmers: usize,
+    /// Number of valid k-mer windows
+    pub valid_kmers: usize,
+    /// Number of distinct valid k-mers, counted only with `KmerCounter::record_distinct`
+    pub distinct: Option<usize>,
+}
+
+/// Format of an input, recognized from its first bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    Fasta,
+    Fastq,
+    TwoBit,
+    /// Sequences one per line, without headers
+    Raw,
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InputFormat::Fasta => "FASTA",
+            InputFormat::Fastq => "FASTQ",
+            InputFormat::TwoBit => ".2bit",
+            InputFormat::Raw => "raw sequences",
+        })
+    }
+}
+
+/// Format and first record of an input, read by `preview_input`
+#[derive(Debug)]
+pub struct InputPreview {
+    /// None for an empty input
+    pub format: Option<InputFormat>,
+    pub first_record: Option<Record>,
+}
+
+/// Where the valid k-mers are recorded while counting.
+/// Sets and maps may use any hasher `S`, like `KmerBuildHasher`.
+pub enum KmerStore<'a, T: Kmer, S: KmerHashing = RandomState> {
+    /// K-mers are only counted
+    None,
+    /// Distinct k-mers
+    Set(&'a DashSet<T, S>),
+    /// Distinct k-mers, in shards by prefix
+    Sharded(&'a ShardedSet<T, S>),
+    /// Distinct k-mers, in shards by prefix, `on_new` being called with each k-mer when first inserted,
+    /// from the counting threads and in no particular order
+    Streamed {
+        kmers: &'a ShardedSet<T, S>,
+        on_new: &'a (dyn Fn(T) + Sync),
+    },
+    /// Number of occurrences of each distinct k-mer
+    Map(&'a DashMap<T, u64, S>),
+    /// Records of each distinct k-mer, as a bitset: bit i for the i-th well-formed record read.
+    /// Counting fails past `MAX_MEMBERSHIP_RECORDS` records.
+    Membership(&'a DashMap<T, u64, S>),
+    /// Approximate number of distinct k-mers
+    Hll(&'a HyperLogLog),
+    /// Distinct k-mers not already reported by the filter, a lower bound because of false positives
+    Bloom(&'a BloomFilter),
+    /// MinHash sketch of the distinct k-mers
+    Sketch(&'a MinHash),
+    /// Distinct k-mers sorted into runs on disk beyond a memory limit, counted by `ExternalSet::merge`
+    External(&'a ExternalSet<T>),
+    /// Distinct k-mers split between those of a `reference` set, recorded in `shared`,
+    /// and the others. `reference` is left untouched.
+    Compare {
+        reference: &'a DashSet<T, S>,
+        shared: &'a DashSet<T, S>,
+        others: &'a DashSet<T, S>,
+    },
+}
+
+// not derived, as the callback of `Streamed` is not Debug
+impl<T: Kmer + fmt::Debug, S: KmerHashing + fmt::Debug> fmt::Debug for KmerStore<'_, T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KmerStore::None => f.write_str("None"),
+            KmerStore::Set(kmers) => f.debug_tuple("Set").field(kmers).finish(),
+            KmerStore::Sharded(kmers) => f.debug_tuple("Sharded").field(kmers).finish(),
+            KmerStore::Streamed { kmers, .. } => f.debug_struct("Streamed").field("kmers", kmers).finish_non_exhaustive(),
+            KmerStore::Map(counts) => f.debug_tuple("Map").field(counts).finish(),
+            KmerStore::Membership(records) => f.debug_tuple("Membership").field(records).finish(),
+            KmerStore::Hll(hll) => f.debug_tuple("Hll").field(hll).finish(),
+            KmerStore::Bloom(bloom) => f.debug_tuple("Bloom").field(bloom).finish(),
+            KmerStore::Sketch(minhash) => f.debug_tuple("Sketch").field(minhash).finish(),
+            KmerStore::External(kmers) => f.debug_tuple("External").field(kmers).finish(),
+            KmerStore::Compare { reference, shared, others } => f
+                .debug_struct("Compare")
+                .field("reference", reference)
+                .field("shared", shared)
+                .field("others", others)
+                .finish(),
+        }
+    }
+}
+
+// not derived, as it would require S: Copy
+impl<T: Kmer, S: KmerHashing> Clone for KmerStore<'_, T, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Kmer, S: KmerHashing> Copy for KmerStore<'_, T, S> {}
+
+impl<T: Kmer, S: KmerHashing> KmerStore<'_, T, S> {
+    /// Number of distinct k-mers stored, approximate for `Hll`, `Bloom` and `Sketch`,
+    /// and that of the last merge for `External`
+    pub fn len(&self) -> usize {
+        match self {
+            KmerStore::None => 0,
+            KmerStore::Set(kmers) => kmers.len(),
+            KmerStore::Sharded(kmers) | KmerStore::Streamed { kmers, .. } => kmers.len(),
+            KmerStore::Map(counts) | KmerStore::Membership(counts) => counts.len(),
+            KmerStore::Hll(hll) => hll.estimate().round() as usize,
+            KmerStore::Bloom(bloom) => bloom.new_insertions(),
+            KmerStore::Sketch(minhash) => minhash.estimate().round() as usize,
+            KmerStore::External(kmers) => kmers.len(),
+            KmerStore::Compare { shared, others, .. } => shared.len() + others.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl KmerStats {
+    /// Adds the nucleotide and k-mer counts of `other`.
+    /// `distinct` is left untouched as distinct k-mers do not add up across inputs.
+    pub fn accumulate(&mut self, other: &KmerStats) {
+        self.total_nucleotides += other.total_nucleotides;
+        self.records += other.records;
+        self.total_kmers += other.total_kmers;
+        self.valid_kmers += other.valid_kmers;
+        self.acgt_nucleotides += other.acgt_nucleotides;
+        self.gc_nucleotides += other.gc_nucleotides;
+        self.n_nucleotides += other.n_nucleotides;
+        self.n_kmers += other.n_kmers;
+        self.skipped_records += other.skipped_records;
+        self.short_records += other.short_records;
+        self.invalid_records += other.invalid_records;
+        self.duplicate_records += other.duplicate_records;
+        self.unmatched_records += other.unmatched_records;
+        self.low_complexity_kmers += other.low_complexity_kmers;
+        self.excluded_kmers += other.excluded_kmers;
+        self.limit_reached |= other.limit_reached;
+        self.interrupted |= other.interrupted;
+    }
+
+    /// Fraction of G and C among the A, C, G, T nucleotides, None if there is none
+    pub fn gc_content(&self) -> Option<f64> {
+        (self.acgt_nucleotides > 0).then(|| self.gc_nucleotides as f64 / self.acgt_nucleotides as f64)
+    }
+
+    /// Fraction of the k-mer windows that are valid, None if there is none: a low ratio points to many Ns
+    /// or to sequences that are not DNA
+    pub fn validity_ratio(&self) -> Option<f64> {
+        (self.total_kmers > 0).then(|| self.valid_kmers as f64 / self.total_kmers as f64)
+    }
+
+    /// Number of k-mer windows giving no k-mer for other reasons than an N or a low complexity:
+    /// other ambiguous bases, soft-masking or low quality
+    pub fn other_invalid_kmers(&self) -> usize {
+        self.total_kmers - self.valid_kmers - self.low_complexity_kmers - self.n_kmers
+    }
+}
+
+/// Counts k-mers of a FASTA/FASTQ input.
+///
+/// ```
+/// use dashmap::DashSet;
+/// use unique_kmer_counter::{KmerCounter, KmerStore};
+///
+/// let fasta: &[u8] = b">a\nACGTTGCA\n>b\nTGCAACGT\n";
+/// let kmers = DashSet::<u64>::new();
+/// let stats = KmerCounter::new(4)
+///     .canonical(true)
+///     .threads(2)
+///     .count_reader(fasta, KmerStore::Set(&kmers))
+///     .unwrap();
+/// assert_eq!(stats.distinct, 5);
+/// ```
+#[derive(Clone, Debug)]
+pub struct KmerCounter {
+    k: usize,
+    strand: Strand,
+    alphabet: Alphabet,
+    case_sensitive: bool,
+    min_quality: Option<u8>,
+    expand_iupac: Option<usize>,
+    n_policy: NPolicy,
+    min_length: usize,
+    max_length: usize,
+    trim_start: usize,
+    trim_end: usize,
+    ends_only: Option<usize>,
+    stride: usize,
+    spaced_seed: Option<SpacedSeed>,
+    minimizer: Option<usize>,
+    max_homopolymer: Option<usize>,
+    min_entropy: Option<f64>,
+    max_distinct: Option<usize>,
+    sample: Option<f64>,
+    seed: u64,
+    record_distinct: bool,
+    skip_invalid: bool,
+    threads: usize,
+    in_flight: usize,
+    chunk_size: usize,
+    /// Pattern of the ids of the records counted
+    id_regex: Option<Regex>,
+    /// Lengths of the well-formed records read
+    lengths: Option<Arc<Mutex<Vec<usize>>>>,
+    snapshots: Option<Arc<Snapshots>>,
+    /// Set to stop reading, see `KmerCounter::interrupt`
+    interrupt: Option<Arc<AtomicBool>>,
+    /// Hashes of the sequences of the records counted, to leave out their duplicates
+    seen_sequences: Option<Arc<DashSet<u128, KmerBuildHasher>>>,
+    /// Encoded k-mers never stored
+    exclude: Option<Arc<Vec<u128>>>,
+    /// Encoded k-mers, the only ones stored
+    include: Option<Arc<Vec<u128>>>,
+    /// Whether the excluded and included k-mers stand for their reverse complements too
+    list_both_strands: bool,
+}
+
+impl KmerCounter {
+    pub fn new(k: usize) -> Self {
+        KmerCounter {
+            k,
+            strand: Strand::Forward,
+            alphabet: Alphabet::dna(),
+            case_sensitive: false,
+            min_quality: None,
+            expand_iupac: None,
+            n_policy: NPolicy::Skip,
+            min_length: 0,
+            max_length: usize::MAX,
+            trim_start: 0,
+            trim_end: 0,
+            ends_only: None,
+            stride: 1,
+            spaced_seed: None,
+            minimizer: None,
+            max_homopolymer: None,
+            min_entropy: None,
+            max_distinct: None,
+            sample: None,
+            seed: 0,
+            record_distinct: false,
+            skip_invalid: false,
+            threads: 0,
+            in_flight: IN_FLIGHT_RECORDS,
+            chunk_size: CHUNK_NUCLEOTIDES,
+            id_regex: None,
+            lengths: None,
+            snapshots: None,
+            interrupt: None,
+            seen_sequences: None,
+            exclude: None,
+            include: None,
+            list_both_strands: true,
+        }
+    }
+
+    /// Counts a k-mer and its reverse complement once.
+    /// Shorthand for `strand(Strand::Canonical)`, or `strand(Strand::Forward)` if false.
+    pub fn canonical(self, canonical: bool) -> Self {
+        self.strand(if canonical { Strand::Canonical } else { Strand::Forward })
+    }
+
+    /// Strands on which k-mers are counted, forward only by default
+    pub fn strand(mut self, strand: Strand) -> Self {
+        self.strand = strand;
+        self
+    }
+
+    /// Symbols of the sequences, DNA by default. Other alphabets have no reverse complement nor IUPAC codes:
+    /// their k-mers are only counted on the forward strand, and ambiguous bases are skipped.
+    pub fn alphabet(mut self, alphabet: Alphabet) -> Self {
+        self.alphabet = alphabet;
+        self
+    }
+
+    /// Ignores k-mers containing lowercase (soft-masked) nucleotides.
+    /// By default lowercase nucleotides are counted as uppercase ones.
+    pub fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+
+    /// Ignores k-mers containing a base of Phred quality below `min_quality` (FASTQ only)
+    pub fn min_quality(mut self, min_quality: Option<u8>) -> Self {
+        self.min_quality = min_quality;
+        self
+    }
+
+    /// Counts every ACGT resolution of the windows containing IUPAC ambiguity codes (R, Y, N...),
+    /// as long as they contain at most `max_ambiguous` ambiguous positions.
+    /// By default such windows are not valid.
+    pub fn expand_iupac(mut self, max_ambiguous: Option<usize>) -> Self {
+        self.expand_iupac = max_ambiguous;
+        self
+    }
+
+    /// Skips the records shorter than `min_length`
+    pub fn min_length(mut self, min_length: usize) -> Self {
+        self.min_length = min_length;
+        self
+    }
+
+    /// Skips the records longer than `max_length`
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Ignores the first `trim_start` nucleotides of each record, such as primers or adapters:
+    /// they are not counted, and no window contains them. The length filters apply to the whole record.
+    pub fn trim_start(mut self, trim_start: usize) -> Self {
+        self.trim_start = trim_start;
+        self
+    }
+
+    /// Ignores the last `trim_end` nucleotides of each record, as `trim_start` does the first ones
+    pub fn trim_end(mut self, trim_end: usize) -> Self {
+        self.trim_end = trim_end;
+        self
+    }
+
+    /// Only counts the k-mers of the first and last `ends_only` nucleotides of each record, after trimming,
+    /// where adapters are found: no window spans both ends, unless they overlap. The nucleotide and k-mer counts
+    /// are those of the ends.
+    pub fn ends_only(mut self, ends_only: Option<usize>) -> Self {
+        self.ends_only = ends_only;
+        self
+    }
+
+    /// Only reads the windows starting every `stride` positions of each record, from its first one after trimming
+    /// (and from the start of each end with `ends_only`), 1 by default: every window. The k-mer counts are those
+    /// of these windows, and the distinct k-mers a sample of those of the input.
+    pub fn stride(mut self, stride: usize) -> Self {
+        self.stride = stride;
+        self
+    }
+
+    /// Makes k-mers from the `1` positions of the seed in windows of its span, instead of contiguous k-mers.
+    /// The weight of the seed must be k. The reverse complement of such a k-mer is that of its nucleotides.
+    pub fn spaced_seed(mut self, spaced_seed: Option<SpacedSeed>) -> Self {
+        self.spaced_seed = spaced_seed;
+        self
+    }
+
+    /// Only records the minimizers of the windows of `w` consecutive valid k-mers: the smallest encoded k-mer,
+    /// canonical if requested. Not available with both strands or IUPAC expansion.
+    pub fn minimizer(mut self, w: Option<usize>) -> Self {
+        self.minimizer = w;
+        self
+    }
+
+    /// Leaves out the k-mers whose window has a run of more than `max_homopolymer` identical nucleotides,
+    /// such as AAAAAAAA, counting them in `KmerStats::low_complexity_kmers` instead of the valid k-mers
+    pub fn max_homopolymer(mut self, max_homopolymer: Option<usize>) -> Self {
+        self.max_homopolymer = max_homopolymer;
+        self
+    }
+
+    /// Leaves out the k-mers whose window has a Shannon entropy of its A, C, G, T composition below `min_entropy` bits
+    /// (from 0 for a homopolymer to 2 for equal counts), counting them in `KmerStats::low_complexity_kmers`
+    pub fn min_entropy(mut self, min_entropy: Option<f64>) -> Self {
+        self.min_entropy = min_entropy;
+        self
+    }
+
+    /// Stops reading records once the store holds `max_distinct` distinct k-mers.
+    /// The records being processed at that time are completed, so the limit may be exceeded.
+    pub fn max_distinct(mut self, max_distinct: Option<usize>) -> Self {
+        self.max_distinct = max_distinct;
+        self
+    }
+
+    /// Stops reading records once `interrupt` is set, e.g. by a signal handler. The records already read are
+    /// counted, so that the counts are those of the beginning of the input, with `KmerStats::interrupted` set.
+    pub fn interrupt(mut self, interrupt: Option<Arc<AtomicBool>>) -> Self {
+        self.interrupt = interrupt;
+        self
+    }
+
+    /// Replaces the ambiguous bases (N and other IUPAC codes) with A, or with one of the nucleotides they stand for,
+    /// chosen from a hash of the record id, the position and the `seed`, so that the windows containing them are valid.
+    /// Lowercase bases stay lowercase. The nucleotide counts (`acgt_nucleotides`, `n_nucleotides`...) are those
+    /// of the input. By default (`NPolicy::Skip`) ambiguous bases are left as is.
+    pub fn n_policy(mut self, n_policy: NPolicy) -> Self {
+        self.n_policy = n_policy;
+        self
+    }
+
+    /// Keeps each record with probability `fraction`, ignoring the others as if they were not in the input.
+    /// Records are chosen from a hash of their id and the `seed`, so that runs are reproducible.
+    pub fn sample(mut self, fraction: Option<f64>) -> Self {
+        self.sample = fraction;
+        self
+    }
+
+    /// Seed of the record sampling and of the random resolution of ambiguous bases
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Counts the distinct k-mers of each record for `RecordStats`, in a temporary set per record
+    pub fn record_distinct(mut self, record_distinct: bool) -> Self {
+        self.record_distinct = record_distinct;
+        self
+    }
+
+    /// Encoded k-mers of size k that are never stored, such as adapters or host k-mers.
+    /// Their occurrences still count as valid k-mers, and in `KmerStats::excluded_kmers`.
+    /// With canonical k-mers or both strands, their reverse complements are excluded too,
+    /// unless `list_both_strands` is false.
+    pub fn exclude(mut self, kmers: Vec<u128>) -> Self {
+        self.exclude = (!kmers.is_empty()).then(|| Arc::new(kmers));
+        self
+    }
+
+    /// Encoded k-mers of size k that are the only ones stored, such as the probes of a targeted search.
+    /// Their occurrences and those of the other k-mers all count as valid k-mers.
+    /// With canonical k-mers or both strands, their reverse complements are included too,
+    /// unless `list_both_strands` is false.
+    pub fn include(mut self, kmers: Vec<u128>) -> Self {
+        self.include = (!kmers.is_empty()).then(|| Arc::new(kmers));
+        self
+    }
+
+    /// Whether the k-mers of `exclude` and `include` stand for their reverse complements too with canonical k-mers
+    /// or both strands, so that matching does not depend on the strand they are given on: true by default.
+    /// If false, they are matched as given against the k-mers stored: with canonical k-mers, a listed k-mer
+    /// that is not canonical never matches.
+    pub fn list_both_strands(mut self, both_strands: bool) -> Self {
+        self.list_both_strands = both_strands;
+        self
+    }
+
+    /// Only counts the records whose id matches `id_regex` anywhere (anchor it with ^ and $ to match the whole id),
+    /// leaving out the others as if they were not in the input but counting them in `KmerStats::unmatched_records`.
+    pub fn id_regex(mut self, id_regex: Option<Regex>) -> Self {
+        self.id_regex = id_regex;
+        self
+    }
+
+    /// Leaves out the records whose sequence is exactly that of a record already counted, such as redundant contigs,
+    /// counting them in `KmerStats::duplicate_records`. Sequences are compared by a 128-bit hash, before the other filters.
+    /// The hashes are kept by the counter and its clones: the duplicates are found across all their counts.
+    pub fn dedup_records(mut self, dedup_records: bool) -> Self {
+        self.seen_sequences = dedup_records.then(|| Arc::new(DashSet::with_hasher(KmerBuildHasher::default())));
+        self
+    }
+
+    /// Keeps the length of each well-formed record read, before any filter, for `KmerCounter::length_stats`.
+    /// The lengths are kept by the counter and its clones, over all their counts.
+    pub fn keep_lengths(mut self, keep_lengths: bool) -> Self {
+        self.lengths = keep_lengths.then(|| Arc::new(Mutex::new(Vec::new())));
+        self
+    }
+
+    /// Statistics of the lengths of the records read so far, None unless kept with `KmerCounter::keep_lengths`
+    pub fn length_stats(&self) -> Option<LengthStats> {
+        let lengths = self.lengths.as_ref()?;
+        Some(length_stats(&mut lengths.lock().unwrap_or_else(|poisoned| poisoned.into_inner())))
+    }
+
+    /// Takes the number of distinct k-mers each time the nucleotides counted cross a multiple of `interval`,
+    /// for `KmerCounter::snapshots`: a rarefaction curve. Snapshots are taken by the counter and its clones,
+    /// over all their counts.
+    pub fn snapshot_interval(mut self, interval: Option<usize>) -> Self {
+        self.snapshots = interval.map(|interval| {
+            Arc::new(Snapshots { interval, nucleotides: AtomicUsize::new(0), taken: Mutex::new(Vec::new()) })
+        });
+        self
+    }
+
+    /// Snapshots taken so far, by increasing number of nucleotides: each multiple of the interval crossed, and the
+    /// number of distinct k-mers then. None unless taken with `KmerCounter::snapshot_interval`.
+    pub fn snapshots(&self) -> Option<Vec<(usize, usize)>> {
+        let snapshots = self.snapshots.as_ref()?;
+        let mut taken = snapshots.taken.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        taken.sort_unstable();
+        Some(taken)
+    }
+
+    /// Skips the malformed records (empty sequence, quality of another length than the sequence)
+    /// instead of failing, counting them in `KmerStats::invalid_records`.
+    /// Records that cannot be parsed still fail: the reader cannot find the next record.
+    pub fn skip_invalid(mut self, skip_invalid: bool) -> Self {
+        self.skip_invalid = skip_invalid;
+        self
+    }
+
+    /// Number of threads used for counting, 0 meaning one per core.
+    /// Each count runs on its own thread pool, leaving rayon's global pool untouched.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Maximum number of records in flight, `IN_FLIGHT_RECORDS` by default. Records are read on the calling thread
+    /// while the others count the k-mers of the previous ones: this bounds the records held in memory, whatever
+    /// the number of threads. Fewer threads count when it is less than twice their number.
+    pub fn in_flight(mut self, records: usize) -> Self {
+        self.in_flight = records;
+        self
+    }
+
+    /// Maximum number of nucleotides handed to a thread at once, `CHUNK_NUCLEOTIDES` by default.
+    /// Longer records are counted in pieces of that many k-mer starts, in parallel, overlapping by k - 1
+    /// nucleotides so that each k-mer is counted once. They are not split with minimizers or with
+    /// per-record statistics.
+    pub fn chunk_size(mut self, nucleotides: usize) -> Self {
+        self.chunk_size = nucleotides;
+        self
+    }
+
+    /// Counts the k-mers of a FASTA/FASTQ file, possibly compressed, of a .2bit file or of raw sequences, one per line.
+    /// Valid k-mers are recorded in `kmers`.
+    pub fn count_file<T: Kmer, S: KmerHashing>(&self, filename: &str, kmers: KmerStore<T, S>) -> Result<KmerStats, KmerError> {
+        self.count_records(file_records(filename)?, kmers, None)
+    }
+
+    /// Counts the k-mers of a FASTA/FASTQ stream, possibly compressed, of a .2bit stream or of raw sequences
+    pub fn count_reader<T: Kmer, S: KmerHashing, R: BufRead>(&self, reader: R, kmers: KmerStore<T, S>) -> Result<KmerStats, KmerError> {
+        self.count_records(fastx_records(reader)?, kmers, None)
+    }
+
+    /// Same as `count_file`, also calling `on_record` with the statistics of each record, in input order
+    pub fn count_file_with<T: Kmer, S: KmerHashing>(
+        &self,
+        filename: &str,
+        kmers: KmerStore<T, S>,
+        mut on_record: impl FnMut(RecordStats),
+    ) -> Result<KmerStats, KmerError> {
+        self.count_records(file_records(filename)?, kmers, Some(&mut on_record))
+    }
+
+    /// Same as `count_reader`, also calling `on_record` with the statistics of each record, in input order
+    pub fn count_reader_with<T: Kmer, S: KmerHashing, R: BufRead>(
+        &self,
+        reader: R,
+        kmers: KmerStore<T, S>,
+        mut on_record: impl FnMut(RecordStats),
+    ) -> Result<KmerStats, KmerError> {
+        self.count_records(fastx_records(reader)?, kmers, Some(&mut on_record))
+    }
+
+    /// Counts the k-mers of paired-end reads, each stream being read as by `count_reader`, as one sample:
+    /// the records of `reader` (R1) and `mate_reader` (R2) are read alternately, so that the counts are those of
+    /// their concatenation. Warns if the streams have different numbers of records.
+    pub fn count_reader_pair<T: Kmer, S: KmerHashing, R1: BufRead, R2: BufRead>(
+        &self,
+        reader: R1,
+        mate_reader: R2,
+        kmers: KmerStore<T, S>,
+    ) -> Result<KmerStats, KmerError> {
+        self.count_records(interleave(fastx_records(reader)?, fastx_records(mate_reader)?), kmers, None)
+    }
+
+    /// Same as `count_reader_pair`, also calling `on_record` with the statistics of each record,
+    /// alternately of each stream
+    pub fn count_reader_pair_with<T: Kmer, S: KmerHashing, R1: BufRead, R2: BufRead>(
+        &self,
+        reader: R1,
+        mate_reader: R2,
+        kmers: KmerStore<T, S>,
+        mut on_record: impl FnMut(RecordStats),
+    ) -> Result<KmerStats, KmerError> {
+        self.count_records(interleave(fastx_records(reader)?, fastx_records(mate_reader)?), kmers, Some(&mut on_record))
+    }
+
+    /// Counts the k-mers of a FASTA/FASTQ file, possibly compressed, for several k at once.
+    /// Same as `count_reader_ks` otherwise.
+    pub fn count_file_ks<T: Kmer, S: KmerHashing>(&self, filename: &str, ks: &[usize], kmers: &[KmerStore<T, S>]) -> Result<Vec<KmerStats>, KmerError> {
+        self.count_records_ks(file_records(filename)?, ks, kmers)
+    }
+
+    /// Counts the k-mers of a FASTA/FASTQ stream, possibly compressed, for several k at once:
+    /// the input is read once, the k-mers of size `ks[i]` being recorded in `kmers[i]`.
+    /// The k of the counter is ignored. Returns the statistics of each k, in the same order.
+    /// Panics if `ks` and `kmers` differ in length.
+    pub fn count_reader_ks<T: Kmer, S: KmerHashing, R: BufRead>(
+        &self,
+        reader: R,
+        ks: &[usize],
+        kmers: &[KmerStore<T, S>],
+    ) -> Result<Vec<KmerStats>, KmerError> {
+        self.count_records_ks(fastx_records(reader)?, ks, kmers)
+    }
+
+    fn count_records_ks<T: Kmer, S: KmerHashing>(
+        &self,
+        records: impl Iterator<Item = io::Result<Record>>,
+        ks: &[usize],
+        kmers: &[KmerStore<T, S>],
+    ) -> Result<Vec<KmerStats>, KmerError> {
+        assert_eq!(ks.len(), kmers.len(), "one k-mer store is needed per k");
+        let counters: Vec<KmerCounter> = ks.iter().map(|&k| KmerCounter { k, ..self.clone() }).collect();
+        let jobs: Vec<(&KmerCounter, KmerStore<T, S>)> = counters.iter().zip(kmers.iter().copied()).collect();
+        self.count_jobs(records, &jobs, None)
+    }
+
+    fn count_records<T: Kmer, S: KmerHashing>(
+        &self,
+        records: impl Iterator<Item = io::Result<Record>>,
+        kmers: KmerStore<T, S>,
+        on_record: Option<&mut dyn FnMut(RecordStats)>,
+    ) -> Result<KmerStats, KmerError> {
+        Ok(self.count_jobs(records, &[(self, kmers)], on_record)?.remove(0))
+    }
+
+    /// Fails if the settings are inconsistent, or k is out of the range of `T`
+    fn check_settings<T: Kmer>(&self) -> Result<(), KmerError> {
+        if self.k == 0 || self.k > T::MAX_K {
+            return Err(KmerError::InvalidK { k: self.k, max: T::MAX_K });
+        }
+        if self.minimizer.is_some() && (self.strand == Strand::Both || self.expand_iupac.is_some()) {
+            return Err(KmerError::InvalidSettings("minimizers are not available with both strands or IUPAC expansion".to_string()));
+        }
+        if self.max_homopolymer == Some(0) {
+            return Err(KmerError::InvalidSettings("the maximum homopolymer length must be positive".to_string()));
+        }
+        if self.min_entropy.is_some_and(|bits| !(0.0..=2.0).contains(&bits)) {
+            return Err(KmerError::InvalidSettings("the minimum entropy must be between 0 and 2 bits".to_string()));
+        }
+        if self.in_flight == 0 {
+            return Err(KmerError::InvalidSettings("at least one record must be in flight".to_string()));
+        }
+        if self.snapshots.as_ref().is_some_and(|snapshots| snapshots.interval == 0) {
+            return Err(KmerError::InvalidSettings("the snapshot interval must be positive".to_string()));
+        }
+        if self.chunk_size == 0 {
+            return Err(KmerError::InvalidSettings("chunks must contain at least one nucleotide".to_string()));
+        }
+        if self.stride == 0 {
+            return Err(KmerError::InvalidSettings("the stride must be positive".to_string()));
+        }
+        if self.minimizer == Some(0) {
+            return Err(KmerError::InvalidSettings("minimizer windows must contain at least one k-mer".to_string()));
+        }
+        if !self.alphabet.is_dna() && (self.strand != Strand::Forward || self.expand_iupac.is_some() || self.n_policy != NPolicy::Skip) {
+            return Err(KmerError::InvalidSettings("alphabets other than DNA are only counted on the forward strand, without IUPAC codes".to_string()));
+        }
+        if let Some(seed) = &self.spaced_seed {
+            if seed.weight() != self.k {
+                return Err(KmerError::InvalidSettings(format!("the spaced seed has {} 1 positions, k is {}", seed.weight(), self.k)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Excluded and included k-mers, with their reverse complements unless only the forward strand is counted
+    /// or `list_both_strands` is false
+    fn kmer_filter(&self) -> KmerFilter {
+        let strand_set = |kmers: &Arc<Vec<u128>>| {
+            let mut set = HashSet::with_capacity_and_hasher(2 * kmers.len(), KmerBuildHasher::default());
+            for &kmer in kmers.iter() {
+                set.insert(kmer);
+                if self.strand != Strand::Forward && self.list_both_strands {
+                    set.insert(kmer.reverse_complement(self.k));
+                }
+            }
+            set
+        };
+        KmerFilter {
+            excluded: self.exclude.as_ref().map(strand_set),
+            included: self.include.as_ref().map(strand_set),
+        }
+    }
+
+    /// Reads the records once, each of them being counted by every counter of `jobs` into its store.
+    /// `on_record` is only given with a single counter.
+    fn count_jobs<T: Kmer, S: KmerHashing>(
+        &self,
+        mut records: impl Iterator<Item = io::Result<Record>>,
+        jobs: &[(&KmerCounter, KmerStore<T, S>)],
+        mut on_record: Option<&mut dyn FnMut(RecordStats)>,
+    ) -> Result<Vec<KmerStats>, KmerError> {
+        for (counter, _) in jobs {
+            counter.check_settings::<T>()?;
+        }
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()
+            .map_err(io::Error::other)?;
+
+        let counters: Vec<Counters> = jobs.iter().map(|_| Counters::default()).collect();
+        let fasta_warning = Once::new();
+        for ((counter, kmers), counters) in jobs.iter().zip(&counters) {
+            counter.check_limit(*kmers, counters);
+        }
+        let filters: Vec<KmerFilter> = jobs.iter().map(|(counter, _)| counter.kmer_filter()).collect();
+        let limit_reached = || counters.iter().all(|counters| counters.limit_reached.load(Ordering::Relaxed));
+        let interrupted = || self.interrupt.as_ref().is_some_and(|interrupt| interrupt.load(Ordering::Relaxed));
+
+        let unmatched_records = AtomicUsize::new(0);
+        let duplicate_records = AtomicUsize::new(0);
+        let max_records = jobs
+            .iter()
+            .any(|(_, kmers)| matches!(kmers, KmerStore::Membership(_)))
+            .then_some(MAX_MEMBERSHIP_RECORDS);
+        // whether a record is counted by all the jobs, once per record
+        let admit = |record: &Record| {
+            if let Some(lengths) = &self.lengths {
+                lengths.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(record.seq().len());
+            }
+            if self.id_regex.as_ref().is_some_and(|id_regex| !id_regex.is_match(record.id())) {
+                unmatched_records.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+            if self.seen_sequences.as_ref().is_some_and(|seen| !seen.insert(sequence_hash(record.seq()))) {
+                duplicate_records.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+            true
+        };
+        // `number`: that of the record among the well-formed ones, from 0; `windows`: the starts of its k-mers counted
+        let count = |number: usize, record: &Record, windows: Range<usize>| {
+            jobs.iter().zip(&counters).zip(&filters).fold(None, |record_stats, (((counter, kmers), counters), filter)| {
+                counter.process_record(record, number, windows.clone(), *kmers, filter, counters, &fasta_warning).or(record_stats)
+            })
+        };
+        let process = |number: usize, record: &Record| {
+            if !admit(record) {
+                return None;
+            }
+            count(number, record, 0..record.seq().len())
+        };
+
+        // Records are sent to the threads in numbered chunks through a bounded channel: at most `queued` chunks
+        // wait while each thread counts one, so that no more than `in_flight` records are read ahead.
+        let threads = pool.current_num_threads().min(self.in_flight / 2).max(1);
+        let chunk_records = (self.in_flight / (2 * threads)).max(1);
+        let queued = (self.in_flight / chunk_records).saturating_sub(threads);
+        // each chunk comes with the number of its first record
+        let (sender, receiver) = mpsc::sync_channel::<(usize, usize, Chunk)>(queued);
+        let receiver = Mutex::new(receiver);
+        // the statistics of each chunk, sent back to be passed to `on_record` in input order
+        let (stats_sender, stats_receiver) = mpsc::channel::<(usize, Vec<RecordStats>)>();
+        let keep_stats = on_record.is_some();
+        // the k-mers of a piece do not depend on the previous ones, except for minimizers
+        let split = !keep_stats && jobs.iter().all(|(counter, _)| counter.minimizer.is_none());
+        let mut pending = BTreeMap::new();
+        let mut next_chunk = 0;
+        let mut report = |chunks: &mut dyn Iterator<Item = (usize, Vec<RecordStats>)>| {
+            if let Some(on_record) = on_record.as_mut() {
+                pending.extend(chunks);
+                while let Some(chunk) = pending.remove(&next_chunk) {
+                    chunk.into_iter().for_each(&mut **on_record);
+                    next_chunk += 1;
+                }
+            }
+        };
+
+        let mut records_read = 0;
+        let mut invalid_records = 0;
+        pool.in_place_scope(|scope| -> Result<(), KmerError> {
+            for _ in 0..threads {
+                let (receiver, process, count, stats_sender) = (&receiver, &process, &count, stats_sender.clone());
+                scope.spawn(move |_| {
+                    while let Ok((index, first, chunk)) = receiver.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).recv() {
+                        let chunk = match chunk {
+                            Chunk::Records(records) => records,
+                            Chunk::Piece { record, windows } => {
+                                count(first, &record, windows);
+                                continue;
+                            }
+                        };
+                        if keep_stats {
+                            let stats = chunk.iter().enumerate().filter_map(|(i, record)| {
+                                let stats = process(first + i, record)?;
+                                Some(RecordStats { id: String::from_utf8_lossy(record.id()).into_owned(), ..stats })
+                            });
+                            // the receiver outlives the threads
+                            let _ = stats_sender.send((index, stats.collect()));
+                        } else {
+                            chunk.iter().enumerate().for_each(|(i, record)| {
+                                process(first + i, record);
+                            });
+                        }
+                    }
+                });
+            }
+            drop(stats_sender);
+            let send = |sender: &SyncSender<(usize, usize, Chunk)>, index: &mut usize, first: &mut usize, chunk: &mut Vec<Record>| {
+                let records = chunk.len();
+                // the threads only stop once the sender is dropped
+                sender.send((*index, *first, Chunk::Records(std::mem::take(chunk)))).expect("counting threads stopped");
+                *index += 1;
+                *first += records;
+            };
+            let mut index = 0;
+            let mut first = 0;
+            let mut chunk = Vec::new();
+            let mut chunk_nucleotides = 0;
+            while !limit_reached() && !interrupted() {
+                let Some(record) = records.next() else {
+                    break;
+                };
+                records_read += 1;
+                let record = record.map_err(|e| match e.kind() {
+                    io::ErrorKind::InvalidData => KmerError::ParseError { record: records_read, message: e.to_string() },
+                    kind => KmerError::Io(io::Error::new(kind, format!("record {}: {}", records_read, e))),
+                })?;
+                if let Err(problem) = check_record(&record) {
+                    if self.skip_invalid {
+                        invalid_records += 1;
+                        continue;
+                    }
+                    let id = String::from_utf8_lossy(record.id()).into_owned();
+                    return Err(KmerError::InvalidRecord { record: records_read, id, problem: problem.to_string() });
+                }
+                if max_records.is_some_and(|max| records_read - invalid_records > max) {
+                    return Err(KmerError::TooManyRecords { max: MAX_MEMBERSHIP_RECORDS });
+                }
+                if split && record.seq().len() > self.chunk_size {
+                    if !chunk.is_empty() {
+                        send(&sender, &mut index, &mut first, &mut chunk);
+                        chunk_nucleotides = 0;
+                    }
+                    if admit(&record) {
+                        let record = Arc::new(record);
+                        let length = record.seq().len();
+                        for start in (0..length).step_by(self.chunk_size) {
+                            let windows = start..(start + self.chunk_size).min(length);
+                            let piece = Chunk::Piece { record: Arc::clone(&record), windows };
+                            sender.send((index, first, piece)).expect("counting threads stopped");
+                            index += 1;
+                        }
+                    }
+                    first += 1;
+                    report(&mut stats_receiver.try_iter());
+                    continue;
+                }
+                chunk_nucleotides += record.seq().len();
+                chunk.push(record);
+                if chunk.len() == chunk_records || chunk_nucleotides >= self.chunk_size {
+                    send(&sender, &mut index, &mut first, &mut chunk);
+                    chunk_nucleotides = 0;
+                    report(&mut stats_receiver.try_iter());
+                }
+            }
+            if !chunk.is_empty() {
+                send(&sender, &mut index, &mut first, &mut chunk);
+            }
+            drop(sender);
+            // until every thread is done
+            report(&mut stats_receiver.iter());
+            Ok(())
+        })?;
+
+        // relaxed loads suffice: the scope has joined the threads, so that their updates are all visible
+        let interrupted = interrupted();
+        Ok(jobs
+            .iter()
+            .zip(&counters)
+            .map(|((_, kmers), counters)| KmerStats {
+                distinct: kmers.len(),
+                total_nucleotides: counters.total_nucleotides.load(Ordering::Relaxed),
+                records: records_read,
+                total_kmers: counters.total_kmers.load(Ordering::Relaxed),
+                valid_kmers: counters.valid_kmers.load(Ordering::Relaxed),
+                acgt_nucleotides: counters.acgt_nucleotides.load(Ordering::Relaxed),
+                gc_nucleotides: counters.gc_nucleotides.load(Ordering::Relaxed),
+                n_nucleotides: counters.n_nucleotides.load(Ordering::Relaxed),
+                n_kmers: counters.n_kmers.load(Ordering::Relaxed),
+                skipped_records: counters.skipped_records.load(Ordering::Relaxed),
+                short_records: counters.short_records.load(Ordering::Relaxed),
+                invalid_records,
+                duplicate_records: duplicate_records.load(Ordering::Relaxed),
+                unmatched_records: unmatched_records.load(Ordering::Relaxed),
+                low_complexity_kmers: counters.low_complexity_kmers.load(Ordering::Relaxed),
+                excluded_kmers: counters.excluded_kmers.load(Ordering::Relaxed),
+                limit_reached: counters.limit_reached.load(Ordering::Relaxed),
+                interrupted,
+            })
+            .collect())
+    }
+
+    /// Counts the k-mers of a record starting in `windows`, None if it is skipped by the length filters.
+    /// The nucleotides counted are those of `windows`, and the record itself is counted with its first k-mers,
+    /// so that the pieces of a record add up to it. The returned statistics have no id.
+    #[allow(clippy::too_many_arguments)]
+    fn process_record<T: Kmer, S: KmerHashing>(
+        &self,
+        record: &Record,
+        number: usize,
+        windows: Range<usize>,
+        kmers: KmerStore<T, S>,
+        filter: &KmerFilter,
+        counters: &Counters,
+        fasta_warning: &Once,
+    ) -> Option<RecordStats> {
+        if counters.limit_reached.load(Ordering::Relaxed) {
+            return None;
+        }
+        if self.sample.is_some_and(|fraction| !is_sampled(record.id(), fraction, self.seed)) {
+            return None;
+        }
+        let k = self.k;
+        let first_piece = windows.start == 0;
+        if record.seq().len() < self.min_length || record.seq().len() > self.max_length {
+            counters.skipped_records.fetch_add(first_piece as usize, Ordering::Relaxed);
+            return None;
+        }
+        let record_qual = record_quality(record, self.min_quality, fasta_warning);
+
+        // distinct k-mers of the record, only for its stats: k-mers go straight to the store
+        let local_kmers = self
+            .record_distinct
+            .then(|| RefCell::new(HashSet::with_hasher(KmerBuildHasher::default())));
+        let mut local_valid_kmers = 0;
+        let mut low_complexity_kmers = 0;
+        let mut acgt_nucleotides = 0;
+        let mut gc_nucleotides = 0;
+        let mut n_nucleotides = 0;
+        let mut n_kmers = 0;
+
+        let span = self.spaced_seed.as_ref().map_or(k, SpacedSeed::span);
+        let min_entropy = self.min_entropy.map(|bits| (bits, entropy_terms(span)));
+
+        let excluded_kmers = Cell::new(0);
+        let insert = |compact_kmer: T| {
+            if filter.included.as_ref().is_some_and(|included| !included.contains(&compact_kmer.into())) {
+                return;
+            }
+            if filter.excluded.as_ref().is_some_and(|excluded| excluded.contains(&compact_kmer.into())) {
+                excluded_kmers.set(excluded_kmers.get() + 1);
+                return;
+            }
+            if let Some(local_kmers) = &local_kmers {
+                local_kmers.borrow_mut().insert(compact_kmer);
+            }
+            match kmers {
+                KmerStore::None => {}
+                KmerStore::Set(kmers) => {
+                    kmers.insert(compact_kmer);
+                }
+                KmerStore::Sharded(kmers) => {
+                    kmers.insert(compact_kmer);
+                }
+                KmerStore::Streamed { kmers, on_new } => {
+                    if kmers.insert(compact_kmer) {
+                        on_new(compact_kmer);
+                    }
+                }
+                KmerStore::Map(counts) => *counts.entry(compact_kmer).or_insert(0) += 1,
+                KmerStore::Membership(records) => *records.entry(compact_kmer).or_insert(0) |= 1 << number,
+                KmerStore::Hll(hll) => hll.insert_hash(compact_kmer.hash64()),
+                KmerStore::Bloom(bloom) => {
+                    bloom.insert_hash(compact_kmer.hash64());
+                }
+                KmerStore::Sketch(minhash) => minhash.insert_hash(compact_kmer.hash64()),
+                KmerStore::External(kmers) => kmers.insert(compact_kmer),
+                KmerStore::Compare { reference, shared, others } => {
+                    if reference.contains(&compact_kmer) {
+                        shared.insert(compact_kmer);
+                    } else {
+                        others.insert(compact_kmer);
+                    }
+                }
+            }
+        };
+        // the k-mers of each segment are counted separately: no window spans two of them.
+        // Of a segment, only the part with the windows starting in `windows` is read, and of it, only the
+        // nucleotides before `counted` are counted.
+        let mut total_kmers = 0;
+        let mut length = 0;
+        let segments = self.kept_segments(record.seq().len());
+        for segment in segments.clone() {
+            let kept = segment.start.max(windows.start)..segment.end.min(windows.end + span - 1);
+            if kept.start >= kept.end.min(windows.end) {
+                continue;
+            }
+            let counted = kept.end.min(windows.end) - kept.start;
+            let resolved = self.resolve_ambiguous(record, kept.clone());
+            let seq = resolved.as_deref().unwrap_or(&record.seq()[kept.clone()]);
+            let qual = record_qual.map(|qual| &qual[kept.clone()]);
+            // the windows start every `stride` positions of the segment
+            let is_read = |start: usize| (kept.start + start - segment.start).is_multiple_of(self.stride);
+            let first_read = (self.stride - (kept.start - segment.start) % self.stride) % self.stride;
+            total_kmers += if seq.len() >= span + first_read { (seq.len() - span - first_read) / self.stride + 1 } else { 0 };
+            length += counted;
+
+            // k-mers are stored with the start of their window, for minimizers
+            let minimizers = self.minimizer.map(|w| RefCell::new(MinimizerWindow::new(w)));
+            let store = |start: usize, compact_kmer: T| match &minimizers {
+                Some(minimizers) => {
+                    if let Some(minimizer) = minimizers.borrow_mut().push(start, compact_kmer) {
+                        insert(minimizer);
+                    }
+                }
+                None => insert(compact_kmer),
+            };
+
+            // A window is valid iff all its nucleotides are valid: A, C, G or T in any case (the symbols of the
+            // alphabet), and not masked.
+            // N is not special: like any other byte, it just does not encode.
+            // The k-mer ending at position i and its reverse complement are updated incrementally,
+            // `run` being the number of consecutive valid nucleotides ending at i.
+            // Masked nucleotides (soft-masked or low quality) also prevent IUPAC expansion.
+            // `homopolymer` is the length of the run of identical A, C, G or T ending at i, and
+            // `long_homopolymer` the start of the last max_homopolymer + 1 identical ones.
+            // `composition` counts the A, C, G and T of the window, for its entropy.
+            // `last_n` is the position of the last N, to tell the windows invalid because of an N.
+            let mut forward = T::default();
+            let mut reverse = T::default();
+            let mut run = 0;
+            let mut last_masked = None;
+            let mut homopolymer = 0;
+            let mut long_homopolymer = None;
+            let mut composition = [0; 4];
+            let mut last_n = None;
+            for (i, &nucleotide) in seq.iter().enumerate() {
+                let masked = self.is_masked(seq, qual, i);
+                if masked {
+                    last_masked = Some(i);
+                }
+                let bits = self.alphabet.bits(nucleotide);
+                if let Some(bits) = bits {
+                    acgt_nucleotides += (i < counted) as usize;
+                    // C = 01, G = 10
+                    gc_nucleotides += (i < counted && (bits == 0b01 || bits == 0b10)) as usize;
+                } else if nucleotide.eq_ignore_ascii_case(&b'N') {
+                    n_nucleotides += (i < counted) as usize;
+                    last_n = Some(i);
+                }
+                if let Some(max_homopolymer) = self.max_homopolymer {
+                    homopolymer = match bits {
+                        Some(bits) if i > 0 && self.alphabet.bits(seq[i - 1]) == Some(bits) => homopolymer + 1,
+                        Some(_) => 1,
+                        None => 0,
+                    };
+                    if homopolymer > max_homopolymer {
+                        long_homopolymer = Some(i - max_homopolymer);
+                    }
+                }
+                if min_entropy.is_some() {
+                    if let Some(bits) = bits {
+                        composition[bits as usize] += 1;
+                    }
+                    if let Some(bits) = i.checked_sub(span).and_then(|out| self.alphabet.bits(seq[out])) {
+                        composition[bits as usize] -= 1;
+                    }
+                }
+                match bits.filter(|_| !masked) {
+                    Some(bits) => {
+                        forward = forward.push(bits, k);
+                        if self.strand != Strand::Forward {
+                            reverse = reverse.push_reverse_complement(bits, k);
+                        }
+                        run += 1;
+                    }
+                    None => run = 0,
+                }
+                if i + 1 < span {
+                    continue;
+                }
+
+                let start = i + 1 - span;
+                if self.stride > 1 && !is_read(start) {
+                    continue;
+                }
+                let n_window = |valid: bool| (!valid && last_n.is_some_and(|n| n >= start)) as usize;
+                if long_homopolymer.is_some_and(|homopolymer| homopolymer >= start)
+                    || min_entropy.as_ref().is_some_and(|(bits, terms)| entropy(&composition, terms) < *bits)
+                {
+                    // valid low-complexity windows are only counted
+                    let valid = self.is_valid_window::<T>(seq, qual, start, i, run, last_masked);
+                    low_complexity_kmers += valid as usize;
+                    n_kmers += n_window(valid);
+                    continue;
+                }
+                if run >= k && self.spaced_seed.is_none() {
+                    self.strand.kmers(forward, reverse).for_each(|kmer| store(start, kmer));
+                    local_valid_kmers += 1;
+                    continue;
+                }
+                let valid = if let Some(seed) = &self.spaced_seed {
+                    let masked = |offset| self.is_masked(seq, qual, start + offset);
+                    self.store_spaced(seed, &seq[start..=i], masked, |kmer| store(start, kmer))
+                } else if let Some(max_ambiguous) = self.expand_iupac {
+                    last_masked.is_none_or(|masked| masked < start)
+                        && self.store_expanded(&seq[start..=i], max_ambiguous, |kmer| store(start, kmer))
+                } else {
+                    false
+                };
+                local_valid_kmers += valid as usize;
+                n_kmers += n_window(valid);
+            }
+
+            // the nucleotide counts are those of the input
+            if resolved.is_some() {
+                let resolutions = record.seq()[kept].iter().zip(seq).take(counted);
+                for (&nucleotide, &resolution) in resolutions.filter(|(nucleotide, resolution)| nucleotide != resolution) {
+                    acgt_nucleotides -= 1;
+                    gc_nucleotides -= b"CGcg".contains(&resolution) as usize;
+                    n_nucleotides += nucleotide.eq_ignore_ascii_case(&b'N') as usize;
+                }
+            }
+        }
+        if first_piece && segments.iter().all(|segment| segment.len() < span) {
+            counters.short_records.fetch_add(1, Ordering::Relaxed);
+        }
+        counters.total_kmers.fetch_add(total_kmers, Ordering::Relaxed);
+        counters.total_nucleotides.fetch_add(length, Ordering::Relaxed);
+        if let Some(snapshots) = &self.snapshots {
+            snapshots.add(length, || kmers.len());
+        }
+        counters.valid_kmers.fetch_add(local_valid_kmers, Ordering::Relaxed);
+        counters.acgt_nucleotides.fetch_add(acgt_nucleotides, Ordering::Relaxed);
+        counters.gc_nucleotides.fetch_add(gc_nucleotides, Ordering::Relaxed);
+        counters.n_nucleotides.fetch_add(n_nucleotides, Ordering::Relaxed);
+        counters.n_kmers.fetch_add(n_kmers, Ordering::Relaxed);
+        counters.excluded_kmers.fetch_add(excluded_kmers.get(), Ordering::Relaxed);
+        counters.low_complexity_kmers.fetch_add(low_complexity_kmers, Ordering::Relaxed);
+        self.check_limit(kmers, counters);
+        Some(RecordStats {
+            id: String::new(),
+            length,
+            total_kmers,
+            valid_kmers: local_valid_kmers,
+            distinct: local_kmers.map(|local_kmers| local_kmers.into_inner().len()),
+        })
+    }
+
+    /// Positions left by the trimming in a record of `length` nucleotides, possibly none
+    fn untrimmed(&self, length: usize) -> Range<usize> {
+        let start = self.trim_start.min(length);
+        start..length.saturating_sub(self.trim_end).max(start)
+    }
+
+    /// Segments of a record of `length` nucleotides whose k-mers are counted: the untrimmed positions,
+    /// or only their first and last `ends_only` ones, a single segment if they overlap. The second may be empty.
+    fn kept_segments(&self, length: usize) -> [Range<usize>; 2] {
+        let kept = self.untrimmed(length);
+        match self.ends_only {
+            Some(end) if kept.len() > 2 * end => [kept.start..kept.start + end, kept.end - end..kept.end],
+            _ => [kept.clone(), kept.end..kept.end],
+        }
+    }
+
+    /// Nucleotides of a record at `positions` with their ambiguous bases resolved according to the `n_policy`,
+    /// None if they are skipped or there is none. A base is resolved the same way whatever the positions.
+    fn resolve_ambiguous(&self, record: &Record, positions: Range<usize>) -> Option<Vec<u8>> {
+        let is_ambiguous = |n: u8| iupac_nucleotides(n).is_some_and(|nucleotides| nucleotides.len() > 1);
+        let seq = &record.seq()[positions.clone()];
+        if self.n_policy == NPolicy::Skip || !seq.iter().any(|&n| is_ambiguous(n)) {
+            return None;
+        }
+        let id_hash = id_hash(record.id(), self.seed);
+        let resolved = seq.iter().zip(positions).map(|(&n, i)| {
+            let Some(nucleotides) = iupac_nucleotides(n).filter(|nucleotides| nucleotides.len() > 1) else {
+                return n;
+            };
+            let resolution = match self.n_policy {
+                NPolicy::Random => nucleotides[(kmer::mix64(id_hash ^ i as u64) % nucleotides.len() as u64) as usize],
+                _ => b'A',
+            };
+            if n.is_ascii_lowercase() { resolution.to_ascii_lowercase() } else { resolution }
+        });
+        Some(resolved.collect())
+    }
+
+    /// Whether the window `start..=end` of `seq` gives k-mers, `run` and `last_masked` being those at its end
+    fn is_valid_window<T: Kmer>(&self, seq: &[u8], qual: Option<&[u8]>, start: usize, end: usize, run: usize, last_masked: Option<usize>) -> bool {
+        if let Some(seed) = &self.spaced_seed {
+            let masked = |offset| self.is_masked(seq, qual, start + offset);
+            self.store_spaced::<T>(seed, &seq[start..=end], masked, |_| {})
+        } else if run >= self.k {
+            true
+        } else if let Some(max_ambiguous) = self.expand_iupac {
+            last_masked.is_none_or(|masked| masked < start) && expand_iupac(&seq[start..=end], max_ambiguous).is_some()
+        } else {
+            false
+        }
+    }
+
+    /// Flags the limit of distinct k-mers as reached if it is
+    fn check_limit<T: Kmer, S: KmerHashing>(&self, kmers: KmerStore<T, S>, counters: &Counters) {
+        if self.max_distinct.is_some_and(|max_distinct| kmers.len() >= max_distinct) {
+            counters.limit_reached.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether the nucleotide at `position` is ignored: soft-masked or of low quality
+    fn is_masked(&self, seq: &[u8], qual: Option<&[u8]>, position: usize) -> bool {
+        (self.case_sensitive && seq[position].is_ascii_lowercase()) || is_low_quality(qual, position, self.min_quality)
+    }
+
+    /// Stores every ACGT resolution of a k-mer with IUPAC codes. Returns whether the k-mer is valid.
+    fn store_expanded<T: Kmer>(&self, kmer: &[u8], max_ambiguous: usize, store: impl Fn(T)) -> bool {
+        let Some(resolutions) = expand_iupac(kmer, max_ambiguous) else {
+            return false;
+        };
+        for resolution in resolutions {
+            if let Some(forward) = T::encode(&resolution) {
+                self.strand.kmers(forward, forward.reverse_complement(self.k)).for_each(&store);
+            }
+        }
+        true
+    }
+
+    /// Stores the k-mer made of the `1` positions of a spaced seed window. Returns whether the window is valid.
+    fn store_spaced<T: Kmer>(&self, seed: &SpacedSeed, window: &[u8], masked: impl Fn(usize) -> bool, store: impl Fn(T)) -> bool {
+        if seed.positions().iter().any(|&offset| masked(offset)) {
+            return false;
+        }
+        let mut forward = T::default();
+        for &offset in seed.positions() {
+            match self.alphabet.bits(window[offset]) {
+                Some(bits) => forward = forward.push(bits, self.k),
+                None => {
+                    return match self.expand_iupac {
+                        Some(max_ambiguous) => {
+                            let kmer: Vec<u8> = seed.positions().iter().map(|&offset| window[offset]).collect();
+                            self.store_expanded(&kmer, max_ambiguous, store)
+                        }
+                        None => false,
+                    };
+                }
+            }
+        }
+        self.strand.kmers(forward, forward.reverse_complement(self.k)).for_each(store);
+        true
+    }
+}
+
+/// Counts the distinct k-mers of a FASTA/FASTQ stream, possibly compressed, with default settings
+pub fn count_unique_kmers(reader: impl BufRead, k: usize, canonical: bool) -> Result<KmerStats, KmerError> {
+    let counter = KmerCounter::new(k).canonical(canonical);
+    if k <= u64::MAX_K {
+        counter.count_reader(reader, KmerStore::Set(&DashSet::<u64>::new()))
+    } else {
+        counter.count_reader(reader, KmerStore::Set(&DashSet::<u128>::new()))
+    }
+}
+
+/// Writes each distinct k-mer, one per line. The order is unspecified.
+pub fn write_kmers<T: Kmer, S: KmerHashing>(kmers: &DashSet<T, S>, k: usize, out: impl Write) -> io::Result<()> {
+    write_kmer_iter(kmers.iter().map(|kmer| *kmer), k, out)
+}
+
+/// Writes each k-mer as it is produced by `kmers`, one per line
+pub fn write_kmer_iter<T: Kmer>(kmers: impl Iterator<Item = T>, k: usize, mut out: impl Write) -> io::Result<()> {
+    for kmer in kmers {
+        writeln!(out, "{}", kmer.decode(k))?;
+    }
+    out.flush()
+}
+
+/// Writes each distinct k-mer and its number of occurrences, tab separated, one per line.
+/// The order is unspecified.
+pub fn write_kmer_counts<T: Kmer, S: KmerHashing>(counts: &DashMap<T, u64, S>, k: usize, out: impl Write) -> io::Result<()> {
+    write_kmer_count_iter(counts.iter().map(|entry| (*entry.key(), *entry.value())), k, out)
+}
+
+/// Writes each k-mer and its number of occurrences as they are produced by `counts`, tab separated, one per line
+pub fn write_kmer_count_iter<T: Kmer>(counts: impl Iterator<Item = (T, u64)>, k: usize, mut out: impl Write) -> io::Result<()> {
+    for (kmer, count) in counts {
+        writeln!(out, "{}\t{}", kmer.decode(k), count)?;
+    }
+    out.flush()
+}
+
+/// Layout of the k-mers and counts written by `write_jellyfish_dump_iter`, those of `jellyfish dump`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JellyfishDump {
+    /// A FASTA record per k-mer, with its count as header: `>count` then the k-mer
+    Fasta,
+    /// A `<kmer> <count>` line per k-mer, as `jellyfish dump -c`
+    Columns,
+}
+
+/// Writes each k-mer and its number of occurrences as `jellyfish dump` does, to be read by Jellyfish-based tools
+pub fn write_jellyfish_dump_iter<T: Kmer>(counts: impl Iterator<Item = (T, u64)>, k: usize, layout: JellyfishDump, mut out: impl Write) -> io::Result<()> {
+    for (kmer, count) in counts {
+        match layout {
+            JellyfishDump::Fasta => writeln!(out, ">{}\n{}", count, kmer.decode(k))?,
+            JellyfishDump::Columns => writeln!(out, "{} {}", kmer.decode(k), count)?,
+        }
+    }
+    out.flush()
+}
+
+/// Writes each k-mer and the bitset of its records, as counted by `KmerStore::Membership`, tab separated,
+/// one per line. The bitset is written as a `0`/`1` character per record, the first record first.
+pub fn write_kmer_membership_iter<T: Kmer>(
+    memberships: impl Iterator<Item = (T, u64)>,
+    k: usize,
+    records: usize,
+    mut out: impl Write,
+) -> io::Result<()> {
+    let mut bits = String::with_capacity(records);
+    for (kmer, bitset) in memberships {
+        bits.clear();
+        bits.extend((0..records).map(|i| if bitset >> i & 1 == 1 { '1' } else { '0' }));
+        writeln!(out, "{}\t{}", kmer.decode(k), bits)?;
+    }
+    out.flush()
+}
+
+/// Numbers of occurrences of the distinct k-mers
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MultiplicityStats {
+    /// Sum of the multiplicities: number of k-mer occurrences counted
+    pub occurrences: u64,
+    pub max: u64,
+    pub mean: f64,
+    /// Number of k-mers occurring exactly once, bucket 1 of `kmer_histogram`
+    pub singletons: usize,
+    /// Fraction of the distinct k-mers occurring once, 0 if there is none
+    pub singleton_fraction: f64,
+}
+
+/// Total, maximum and mean number of occurrences of the distinct k-mers, and number of singletons
+pub fn multiplicity_stats<T: Kmer, S: KmerHashing>(counts: &DashMap<T, u64, S>) -> MultiplicityStats {
+    let (max, sum, singletons) = counts.iter().fold((0, 0, 0), |(max, sum, singletons), entry| {
+        (max.max(*entry.value()), sum + *entry.value(), singletons + (*entry.value() == 1) as usize)
+    });
+    let per_kmer = |value: f64| if counts.is_empty() { 0.0 } else { value / counts.len() as f64 };
+    MultiplicityStats { occurrences: sum, max, mean: per_kmer(sum as f64), singletons, singleton_fraction: per_kmer(singletons as f64) }
+}
+
+/// The `n` most frequent k-mers with their numbers of occurrences, by decreasing number, then increasing k-mer.
+/// Only `n` of them are kept while the counts are scanned, in a min-heap.
+pub fn top_kmers<T: Kmer, S: KmerHashing>(counts: &DashMap<T, u64, S>, n: usize) -> Vec<(T, u64)> {
+    // the least frequent k-mer kept on top, the largest first among those of the same count
+    let mut heap = BinaryHeap::with_capacity(n + 1);
+    for entry in counts.iter() {
+        heap.push((Reverse(*entry.value()), *entry.key()));
+        if heap.len() > n {
+            heap.pop();
+        }
+    }
+    heap.into_sorted_vec().into_iter().map(|(Reverse(count), kmer)| (kmer, count)).collect()
+}
+
+/// Multiplicity of the main peak of an abundance histogram of `kmer_histogram`, the coverage of a genome sequenced
+/// by reads: the most frequent multiplicity after the first minimum, past the decrease from multiplicity 1
+/// of the k-mers with errors. The last bucket, which gathers the k-mers of all higher multiplicities, is left out.
+/// None if the histogram has no such peak.
+pub fn coverage_peak(histogram: &[u64]) -> Option<usize> {
+    let histogram = &histogram[..histogram.len().saturating_sub(1)];
+    let mut minimum = 1;
+    while minimum + 1 < histogram.len() && histogram[minimum + 1] <= histogram[minimum] {
+        minimum += 1;
+    }
+    let peak = (minimum + 1..histogram.len()).max_by_key(|&i| histogram[i])?;
+    (histogram[peak] > histogram[minimum]).then_some(peak)
+}
+
+/// Shortest, longest and mean lengths of records, and their N50
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LengthStats {
+    /// Number of records
+    pub records: usize,
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    /// Length such that the records at least as long hold half of the nucleotides, or more
+    pub n50: usize,
+}
+
+/// Statistics of records of the given `lengths`, which are sorted. All zero if there is no record.
+pub fn length_stats(lengths: &mut [usize]) -> LengthStats {
+    lengths.sort_unstable_by(|a, b| b.cmp(a));
+    let total: usize = lengths.iter().sum();
+    let mut cumulated = 0;
+    let n50 = lengths.iter().find(|&&length| {
+        cumulated += length;
+        2 * cumulated >= total
+    });
+    LengthStats {
+        records: lengths.len(),
+        min: lengths.last().copied().unwrap_or(0),
+        max: lengths.first().copied().unwrap_or(0),
+        mean: if lengths.is_empty() { 0.0 } else { total as f64 / lengths.len() as f64 },
+        n50: n50.copied().unwrap_or(0),
+    }
+}
+
+/// Abundance histogram: number of distinct k-mers occurring exactly i times, at index i.
+/// The last bucket, `max_bucket`, gathers the k-mers occurring `max_bucket` times or more.
+pub fn kmer_histogram<T: Kmer, S: KmerHashing>(counts: &DashMap<T, u64, S>, max_bucket: usize) -> Vec<u64> {
+    let mut histogram = vec![0; max_bucket + 1];
+    for entry in counts.iter() {
+        histogram[(*entry.value() as usize).min(max_bucket)] += 1;
+    }
+    histogram
+}
+
+/// Writes the non-empty buckets of a histogram as `multiplicity\tcount` lines
+pub fn write_histogram(histogram: &[u64], out: impl Write) -> io::Result<()> {
+    write_buckets(histogram, '\t', out)
+}
+
+/// Writes the non-empty buckets of a histogram as `jellyfish histo` does: `<multiplicity> <count>` lines
+pub fn write_jellyfish_histo(histogram: &[u64], out: impl Write) -> io::Result<()> {
+    write_buckets(histogram, ' ', out)
+}
+
+fn write_buckets(histogram: &[u64], separator: char, mut out: impl Write) -> io::Result<()> {
+    for (multiplicity, &count) in histogram.iter().enumerate() {
+        if count > 0 {
+            writeln!(out, "{}{}{}", multiplicity, separator, count)?;
+        }
+    }
+    out.flush()
+}
+
+/// K-mers left out of the store, with the strand settings of a counter applied
+struct KmerFilter {
+    excluded: Option<HashSet<u128, KmerBuildHasher>>,
+    /// When given, the only k-mers stored
+    included: Option<HashSet<u128, KmerBuildHasher>>,
+}
+
+#[derive(Default)]
+struct Counters {
+    total_nucleotides: AtomicUsize,
+    total_kmers: AtomicUsize,
+    valid_kmers: AtomicUsize,
+    acgt_nucleotides: AtomicUsize,
+    gc_nucleotides: AtomicUsize,
+    n_nucleotides: AtomicUsize,
+    n_kmers: AtomicUsize,
+    skipped_records: AtomicUsize,
+    short_records: AtomicUsize,
+    low_complexity_kmers: AtomicUsize,
+    excluded_kmers: AtomicUsize,
+    limit_reached: AtomicBool,
+}
+
+/// Distinct k-mer counts taken as the nucleotides counted cross the multiples of an interval
+#[derive(Debug)]
+struct Snapshots {
+    interval: usize,
+    /// Nucleotides counted so far, over all the counts
+    nucleotides: AtomicUsize,
+    /// Multiples crossed, with the number of distinct k-mers then, in the order they are taken
+    taken: Mutex<Vec<(usize, usize)>>,
+}
+
+impl Snapshots {
+    /// Adds `nucleotides` counted, taking a snapshot of `distinct` for each multiple crossed.
+    /// Each multiple is crossed by a single addition, whatever the threads.
+    fn add(&self, nucleotides: usize, distinct: impl FnOnce() -> usize) {
+        let before = self.nucleotides.fetch_add(nucleotides, Ordering::Relaxed);
+        let crossed = before / self.interval + 1..=(before + nucleotides) / self.interval;
+        if !crossed.is_empty() {
+            let distinct = distinct();
+            let mut taken = self.taken.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            taken.extend(crossed.map(|multiple| (multiple * self.interval, distinct)));
+        }
+    }
+}
+
+/// What a thread counts at once: whole records, or the k-mers of a long record starting in `windows`
+enum Chunk {
+    Records(Vec<Record>),
+    Piece { record: Arc<Record>, windows: Range<usize> },
+}
+
+/// Reads the first record of an input as the counter does, to check it before counting.
+/// Fails if the format is not recognized, or if the first record cannot be parsed or is malformed.
+pub fn preview_input(reader: impl BufRead) -> io::Result<InputPreview> {
+    let (format, mut records) = fastx_format_records(reader)?;
+    let first_record = records.next().transpose()?;
+    if let Some(record) = &first_record {
+        check_record(record).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("malformed first record: {}", e)))?;
+    }
+    Ok(InputPreview { format, first_record })
+}
+
+/// Records of an input, failing on the first one that cannot be parsed
+type Records<'a> = Box<dyn Iterator<Item = io::Result<Record>> + 'a>;
+
+/// Decompresses an input if it starts with the magic bytes of gzip, bzip2, xz or zstd, whatever its file name,
+/// and returns the compression found
+pub fn decompress<'a>(mut reader: impl BufRead + 'a) -> io::Result<(Box<dyn BufRead + 'a>, niffler::compression::Format)> {
+    // niffler reads the first 5 bytes: shorter inputs are not compressed
+    if reader.fill_buf()?.len() < 5 {
+        return Ok((Box::new(reader), niffler::compression::Format::No));
+    }
+    let (reader, compression) = niffler::get_reader(Box::new(reader)).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok((Box::new(BufReader::new(reader)), compression))
+}
+
+/// Format of a decompressed input, from its first bytes and without consuming them: `>` for FASTA, `@` for FASTQ,
+/// the signature of .2bit files, or a first line of nucleotides and IUPAC codes only for raw sequences.
+/// None for an empty input.
+pub fn detect_format(reader: &mut impl BufRead) -> io::Result<Option<InputFormat>> {
+    let start = reader.fill_buf()?;
+    match start.first() {
+        None => Ok(None),
+        Some(b'>') => Ok(Some(InputFormat::Fasta)),
+        Some(b'@') => Ok(Some(InputFormat::Fastq)),
+        Some(_) if twobit::is_two_bit(start) => Ok(Some(InputFormat::TwoBit)),
+        Some(_) if raw::is_raw(start) => Ok(Some(InputFormat::Raw)),
+        Some(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "Unrecognized file format")),
+    }
+}
+
+/// Reads FASTA, FASTQ, .2bit or raw sequence records depending on the first bytes of the stream, decompressed first if needed
+fn fastx_records<'a, R: BufRead + 'a>(reader: R) -> io::Result<Records<'a>> {
+    Ok(fastx_format_records(reader)?.1)
+}
+
+/// Same as `fastx_records`, also returning the format, None for an empty stream
+fn fastx_format_records<'a, R: BufRead + 'a>(reader: R) -> io::Result<(Option<InputFormat>, Records<'a>)> {
+    let (mut reader, _) = decompress(reader)?;
+    let Some(format) = detect_format(&mut reader)? else {
+        return Ok((None, Box::new(std::iter::empty())));
+    };
+    let records: Records<'a> = match format {
+        InputFormat::Fasta => Box::new(fastx_results(Box::new(FastaReader::new(reader)))),
+        InputFormat::Fastq => Box::new(fastx_results(Box::new(FastqReader::new(reader)))),
+        InputFormat::TwoBit => Box::new(TwoBitRecords::new(reader)?),
+        InputFormat::Raw => Box::new(RawRecords::new(reader)),
+    };
+    Ok((Some(format), records))
+}
+
+/// Records of a FASTA/FASTQ file, possibly compressed, of a .2bit file or of raw sequences, whatever its name
+fn file_records(filename: &str) -> io::Result<Records<'static>> {
+    fastx_records(BufReader::new(File::open(filename)?))
+}
+
+/// Records of paired files, alternately from each, warning at the end if the files have different numbers of records
+fn interleave<'a>(
+    records: impl Iterator<Item = io::Result<Record>> + 'a,
+    mates: impl Iterator<Item = io::Result<Record>> + 'a,
+) -> impl Iterator<Item = io::Result<Record>> + 'a {
+    let mut records = [
+        Box::new(records.fuse()) as Box<dyn Iterator<Item = io::Result<Record>>>,
+        Box::new(mates.fuse().map(|mate| mate.map_err(|e| io::Error::new(e.kind(), format!("mate file: {}", e))))),
+    ];
+    let mut counts = [0, 0];
+    let mut turn = 0;
+    let mut done = false;
+    std::iter::from_fn(move || {
+        for _ in 0..2 {
+            let side = turn;
+            turn = 1 - turn;
+            if let Some(record) = records[side].next() {
+                counts[side] += 1;
+                return Some(record);
+            }
+        }
+        if !done && counts[0] != counts[1] {
+            log::warn!("the paired files have different numbers of records: {} and {}", counts[0], counts[1]);
+        }
+        done = true;
+        None
+    })
+}
+
+/// Iterates over the records of a fxread reader, returning parse errors instead of panicking
+fn fastx_results<'a>(mut reader: Box<dyn FastxRead<Item = Record> + 'a>) -> impl Iterator<Item = io::Result<Record>> + 'a {
+    std::iter::from_fn(move || {
+        reader
+            .next_record()
+            .map(|record| record.map(strip_whitespace))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+            .transpose()
+    })
+}
+
+/// Record without the whitespace fxread leaves in it: the carriage returns of Windows line endings, at the end
+/// of the id, sequence and quality lines, and stray spaces in the sequence and quality, which would otherwise
+/// make k-mers invalid. Records without any are returned as they are.
+fn strip_whitespace(record: Record) -> Record {
+    let has_whitespace = |line: &[u8]| line.iter().any(u8::is_ascii_whitespace);
+    if !has_whitespace(record.seq())
+        && !record.qual().is_some_and(has_whitespace)
+        && !record.id().last().is_some_and(u8::is_ascii_whitespace)
+    {
+        return record;
+    }
+    let strip = |line: &[u8]| -> Vec<u8> { line.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect() };
+    let (id, seq) = (record.id().trim_ascii_end(), strip(record.seq()));
+    let stripped = match record.qual() {
+        Some(qual) => Record::new_fastq_from_parts(id, &seq, &strip(qual)),
+        None => Record::new_fasta_from_parts(id, &seq),
+    };
+    // sequence and quality of different lengths: left to `check_record`
+    stripped.unwrap_or(record)
+}
+
+/// Checks that a record has a sequence, and as many quality scores as nucleotides
+fn check_record(record: &Record) -> Result<(), &'static str> {
+    let seq = record.seq();
+    // a header right after another one is read as the sequence of the first
+    if seq.is_empty() || (record.is_fasta() && seq.starts_with(b">")) {
+        return Err("empty sequence");
+    }
+    if record.plus().is_some_and(|plus| !plus.starts_with(b"+")) {
+        return Err("missing '+' line");
+    }
+    if record.qual().is_some_and(|qual| qual.len() != seq.len()) {
+        return Err("quality and sequence of different lengths");
+    }
+    Ok(())
+}
+
+/// c * log2(c) for the counts c of a window of `span` nucleotides, for `entropy`
+fn entropy_terms(span: usize) -> Vec<f64> {
+    (0..=span).map(|count| if count == 0 { 0.0 } else { count as f64 * (count as f64).log2() }).collect()
+}
+
+/// Shannon entropy in bits of nucleotide counts, 0 if there is none.
+/// With n the total, it is log2(n) - sum(c * log2(c)) / n, from the terms of `entropy_terms`.
+fn entropy(counts: &[usize; 4], terms: &[f64]) -> f64 {
+    let total: usize = counts.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    (terms[total] - counts.iter().map(|&count| terms[count]).sum::<f64>()) / total as f64
+}
+
+/// Quality scores of a record when quality masking is requested.
+/// Records without qualities (FASTA) are not masked, with a warning printed once.
+fn record_quality<'a>(record: &'a Record, min_quality: Option<u8>, fasta_warning: &Once) -> Option<&'a [u8]> {
+    min_quality?;
+    let qual = record.qual();
+    if qual.is_none() {
+        fasta_warning.call_once(|| {
+            log::warn!("--min-quality ignored, the input has no quality scores");
+        });
+    }
+    qual
+}
+
+/// Whether a record is in the sample of the given fraction, from a seeded hash of its id
+fn is_sampled(id: &[u8], fraction: f64, seed: u64) -> bool {
+    (kmer::mix64(id_hash(id, seed)) as f64) < fraction * u64::MAX as f64
+}
+
+/// FNV-1a hash of a record id, to be mixed for uniformity
+fn id_hash(id: &[u8], seed: u64) -> u64 {
+    id.iter().fold(0xcbf2_9ce4_8422_2325 ^ seed, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3))
+}
+
+/// 128-bit hash of a sequence, from two unrelated 64-bit hashes so that distinct sequences practically never collide
+fn sequence_hash(seq: &[u8]) -> u128 {
+    let mut hasher = std::hash::DefaultHasher::new();
+    hasher.write(seq);
+    (hasher.finish() as u128) << 64 | kmer::mix64(id_hash(seq, 0)) as u128
+}
+
+/// Whether the base at `position` has a Phred quality (offset 33) below `min_quality`
+fn is_low_quality(qual: Option<&[u8]>, position: usize, min_quality: Option<u8>) -> bool {
+    match (qual, min_quality) {
+        (Some(qual), Some(min_quality)) => qual[position] < min_quality.saturating_add(33),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lowercase_is_counted_unless_case_sensitive() {
+        let fasta = b">upper\nACGTACGTAC\n>lower\nacgtACGTac\n";
+        let stats = count_unique_kmers(&fasta[..], 5, false).unwrap();
+        assert_eq!(stats.valid_kmers, 12);
+        assert_eq!(stats.distinct, 4);
+
+        let kmers = DashSet::<u64>::new();
+        let stats = KmerCounter::new(5)
+            .case_sensitive(true)
+            .count_reader(&fasta[..], KmerStore::Set(&kmers))
+            .unwrap();
+        assert_eq!(stats.valid_kmers, 6);
+        assert_eq!(stats.distinct, 4);
+    }
+
+    #[test]
+    fn iupac_codes_are_expanded_on_request() {
+        let fasta = b">iupac\nACRTA\n";
+        let stats = count_unique_kmers(&fasta[..], 5, false).unwrap();
+        assert_eq!(stats.valid_kmers, 0);
+
+        let kmers = DashSet::<u64>::new();
+        let stats = KmerCounter::new(5)
+            .expand_iupac(Some(1))
+            .count_reader(&fasta[..], KmerStore::Set(&kmers))
+            .unwrap();
+        assert_eq!(stats.valid_kmers, 1);
+        assert_eq!(stats.distinct, 2);
+
+        let stats = KmerCounter::new(5)
+            .expand_iupac(Some(1))
+            .count_reader(&b">iupac\nNCRTA\n"[..], KmerStore::Set(&DashSet::<u64>::new()))
+            .unwrap();
+        assert_eq!(stats.valid_kmers, 0);
+    }
+
+    #[test]
+    fn counting_twice_in_one_process() {
+        let fasta = b">a\nACGTTGCA\n>b\nTGCAACGT\n";
+        let counter = KmerCounter::new(4).threads(2);
+        for _ in 0..2 {
+            let kmers = DashSet::<u64>::new();
+            let stats = counter.count_reader(&fasta[..], KmerStore::Set(&kmers)).unwrap();
+            assert_eq!(stats.distinct, 8);
+        }
+        assert_eq!(count_unique_kmers(&fasta[..], 4, true).unwrap().distinct, 5);
+    }
+
+    #[test]
+    fn several_kmer_sizes_in_one_pass() {
+        let fasta = b">a\nACGTTGCAAC\n>b\nTGCAACGTNACG\n";
+        let counter = KmerCounter::new(1).canonical(true);
+        let sets: Vec<DashSet<u64>> = (0..3).map(|_| DashSet::new()).collect();
+        let stores: Vec<KmerStore<u64>> = sets.iter().map(KmerStore::Set).collect();
+        let stats = counter.count_reader_ks(&fasta[..], &[3, 5, 9], &stores).unwrap();
+        for (k, stats) in [3, 5, 9].into_iter().zip(stats) {
+            assert_eq!(stats, count_unique_kmers(&fasta[..], k, true).unwrap());
+        }
+    }
+
+    #[test]
+    fn excluded_kmers_are_not_stored() {
+        let fasta = b">a\nAACGTT\n";
+        let exclude = vec![kmer::kmer_to_u128(b"AAC").unwrap()];
+        let kmers = DashSet::<u64>::new();
+        let stats = KmerCounter::new(3).exclude(exclude.clone()).count_reader(&fasta[..], KmerStore::Set(&kmers)).unwrap();
+        assert_eq!((stats.valid_kmers, stats.distinct, stats.excluded_kmers), (4, 3, 1));
+
+        // GTT is the reverse complement of AAC
+        let kmers = DashSet::<u64>::new();
+        let stats = KmerCounter::new(3)
+            .canonical(true)
+            .exclude(exclude)
+            .count_reader(&fasta[..], KmerStore::Set(&kmers))
+            .unwrap();
+        assert_eq!((stats.distinct, stats.excluded_kmers), (1, 2));
+    }
+
+    #[test]
+    fn listed_kmers_match_both_strands_unless_told_otherwise() {
+        // AAC on the forward strand, its reverse complement GTT on the reverse one
+        let fasta = b">f\nAAC\n>r\nGTT\n";
+        for (listed, strand) in [(b"AAC", Strand::Canonical), (b"GTT", Strand::Canonical), (b"AAC", Strand::Both), (b"GTT", Strand::Both)] {
+            let counter = KmerCounter::new(3).strand(strand).exclude(vec![kmer::kmer_to_u128(listed).unwrap()]);
+            let stats = counter.count_reader(&fasta[..], KmerStore::Set(&DashSet::<u64>::new())).unwrap();
+            assert_eq!((stats.distinct, stats.excluded_kmers), (0, if strand == Strand::Both { 4 } else { 2 }));
+
+            // as given: only the canonical AAC matches the canonical k-mers, each listed k-mer one of both strands
+            let stats = counter.list_both_strands(false).count_reader(&fasta[..], KmerStore::Set(&DashSet::<u64>::new())).unwrap();
+            let excluded = match strand {
+                Strand::Canonical if listed == b"AAC" => 2,
+                Strand::Canonical => 0,
+                _ => 2,
+            };
+            assert_eq!(stats.excluded_kmers, excluded, "{} {}", String::from_utf8_lossy(listed), strand);
+        }
+        let counts = DashMap::<u64, u64>::new();
+        KmerCounter::new(3)
+            .canonical(true)
+            .include(vec![kmer::kmer_to_u128(b"GTT").unwrap()])
+            .count_reader(&fasta[..], KmerStore::Map(&counts))
+            .unwrap();
+        assert_eq!(counts.get(&u64::encode(b"AAC").unwrap()).map(|count| *count), Some(2));
+    }
+
+    #[test]
+    fn only_included_kmers_are_stored() {
+        let include = vec![kmer::kmer_to_u128(b"AAC").unwrap(), kmer::kmer_to_u128(b"CCC").unwrap()];
+        let kmers = DashSet::<u64>::new();
+        let stats = KmerCounter::new(3).include(include).count_reader(&b">a\nAACGTT\n"[..], KmerStore::Set(&kmers)).unwrap();
+        assert_eq!((stats.valid_kmers, stats.distinct), (4, 1));
+        assert!(kmers.contains(&u64::encode(b"AAC").unwrap()));
+    }
+
+    #[test]
+    fn unreadable_files_are_errors() {
+        let kmers = DashSet::<u64>::new();
+        let error = KmerCounter::new(3).count_file("/nonexistent/input.fa", KmerStore::Set(&kmers)).unwrap_err();
+        assert!(matches!(error, KmerError::Io(ref e) if e.kind() == io::ErrorKind::NotFound), "{:?}", error);
+
+        let path = std::env::temp_dir().join(format!("ukc_unrecognized_{}.txt", std::process::id()));
+        std::fs::write(&path, "not a FASTA file\n").unwrap();
+        let error = KmerCounter::new(3).count_file(path.to_str().unwrap(), KmerStore::Set(&kmers)).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(error, KmerError::Io(ref e) if e.kind() == io::ErrorKind::InvalidData), "{:?}", error);
+        assert_eq!(error.to_string(), "Unrecognized file format");
+        // a first line of letters that are not all nucleotides is not a raw sequence
+        let error = KmerCounter::new(3).count_reader(&b"Sequences\nACGTACGT\n"[..], KmerStore::Set(&kmers)).unwrap_err();
+        assert_eq!(error.to_string(), "Unrecognized file format");
+
+        let error = KmerCounter::new(33).count_reader(&b">a\nACGT\n"[..], KmerStore::Set(&kmers)).unwrap_err();
+        assert!(matches!(error, KmerError::InvalidK { k: 33, max: 32 }), "{:?}", error);
+    }
+
+    #[test]
+    fn malformed_records_fail_or_are_skipped() {
+        let fastq = b"@r1\nACGT\n+\nIIII\n@r2\nACGT\n+\nII\n@r3\n\n+\n\n@r4\nACGTA\n+\nIIIII\n";
+        let kmers = DashSet::<u64>::new();
+        let error = KmerCounter::new(3).count_reader(&fastq[..], KmerStore::Set(&kmers)).unwrap_err();
+        assert!(matches!(error, KmerError::InvalidRecord { record: 2, .. }), "{:?}", error);
+        assert_eq!(error.to_string(), "record 2 (r2): quality and sequence of different lengths");
+
+        let kmers = DashSet::<u64>::new();
+        let stats = KmerCounter::new(3).skip_invalid(true).count_reader(&fastq[..], KmerStore::Set(&kmers)).unwrap();
+        assert_eq!((stats.records, stats.invalid_records, stats.total_nucleotides), (4, 2, 9));
+
+        let error = count_unique_kmers(&b">a\nACGT\n>b\n>c\nACGT\n"[..], 3, false).unwrap_err();
+        assert_eq!(error.to_string(), "record 2 (b): empty sequence");
+        let error = count_unique_kmers(&b">a\nACGT\nACGT\n>b\nACGT\n"[..], 3, false).unwrap_err();
+        assert!(matches!(error, KmerError::ParseError { record: 2, .. }), "{:?}", error);
+        assert!(error.to_string().starts_with("record 2: "));
+    }
+
+    #[test]
+    fn long_homopolymers_are_low_complexity() {
+        // the 4-mers of ACAAAAAG, with one run of 5 A
+        let fasta = b">a\nACAAAAAG\n";
+        let kmers = DashSet::<u64>::new();
+        let stats = KmerCounter::new(4).max_homopolymer(Some(3)).count_reader(&fasta[..], KmerStore::Set(&kmers)).unwrap();
+        assert_eq!((stats.valid_kmers, stats.low_complexity_kmers, stats.distinct), (3, 2, 3));
+        assert!(!kmers.contains(&u64::encode(b"AAAA").unwrap()));
+
+        // runs are case-insensitive, and counted on the window of spaced seeds
+        let kmers = DashSet::<u64>::new();
+        let stats = KmerCounter::new(3)
+            .spaced_seed(Some("1101".parse().unwrap()))
+            .max_homopolymer(Some(2))
+            .count_reader(&b">a\nCaAAG\n"[..], KmerStore::Set(&kmers))
+            .unwrap();
+        assert_eq!((stats.valid_kmers, stats.low_complexity_kmers), (0, 2));
+        assert!(KmerCounter::new(4).max_homopolymer(Some(0)).count_reader(&fasta[..], KmerStore::Set(&kmers)).is_err());
+    }
+
+    #[test]
+    fn low_entropy_windows_are_low_complexity() {
+        let terms = entropy_terms(4);
+        assert_eq!(entropy(&[4, 0, 0, 0], &terms), 0.0);
+        assert_eq!(entropy(&[2, 2, 0, 0], &terms), 1.0);
+        assert_eq!(entropy(&[1, 1, 1, 1], &terms), 2.0);
+        assert_eq!(entropy(&[0, 0, 0, 0], &terms), 0.0);
+
+        // the 4-mers ATAT, TATA (1 bit), ATAC (1.5 bits), TACG and ACGT (2 bits)
+        let fasta = b">a\nATATACGT\n";
+        let kmers = DashSet::<u64>::new();
+        let stats = KmerCounter::new(4).min_entropy(Some(1.5)).count_reader(&fasta[..], KmerStore::Set(&kmers)).unwrap();
+        assert_eq!((stats.valid_kmers, stats.low_complexity_kmers, stats.distinct), (3, 2, 3));
+        assert!(!kmers.contains(&u64::encode(b"TATA").unwrap()));
+        assert!(KmerCounter::new(4).min_entropy(Some(2.5)).count_reader(&fasta[..], KmerStore::Set(&kmers)).is_err());
+    }
+
+    #[test]
+    fn sequence_shorter_than_k_has_no_kmer() {
+        let stats = count_unique_kmers(&b">short\nACGTACGTAC\n"[..], 21, false).unwrap();
+        assert_eq!(stats.total_nucleotides, 10);
+        assert_eq!(stats.total_kmers, 0);
+        assert_eq!(stats.valid_kmers, 0);
+        assert_eq!(stats.distinct, 0);
+        assert_eq!(stats.short_records, 1);
+
+        let stats = count_unique_kmers(&b">short\nACGTACGTAC\n>long\nACGTACGTACGTACGTACGTA\n"[..], 21, false).unwrap();
+        assert_eq!(stats.total_kmers, 1);
+        assert_eq!(stats.short_records, 1);
+    }
+
+    #[test]
+    fn windows_with_n_are_told_apart() {
+        // windows 1 to 4 have an N, 5 to 8 an R
+        let stats = count_unique_kmers(&b">a\nACGTNACGRACGT\n"[..], 4, false).unwrap();
+        assert_eq!(stats.n_nucleotides, 1);
+        assert_eq!((stats.total_kmers, stats.valid_kmers, stats.n_kmers, stats.other_invalid_kmers()), (10, 2, 4, 4));
+
+        // windows with a single N are expanded, those with two are not
+        let kmers = DashSet::<u64>::new();
+        let stats = KmerCounter::new(4)
+            .expand_iupac(Some(1))
+            .count_reader(&b">a\nACnNACGT\n"[..], KmerStore::Set(&kmers))
+            .unwrap();
+        assert_eq!(stats.n_nucleotides, 2);
+        assert_eq!((stats.valid_kmers, stats.n_kmers, stats.other_invalid_kmers()), (2, 3, 0));
+    }
+
+    #[test]
+    fn ambiguous_bases_are_resolved_by_the_n_policy() {
+        let fasta = b">a\nACGTNACrTAC-\n";
+        let count = |n_policy, seed| {
+            let kmers = DashSet::<u64>::new();
+            let counter = KmerCounter::new(3).n_policy(n_policy).seed(seed);
+            let stats = counter.count_reader(&fasta[..], KmerStore::Set(&kmers)).unwrap();
+            let mut kmers: Vec<String> = kmers.iter().map(|kmer| kmer.decode(3)).collect();
+            kmers.sort();
+            (stats, kmers)
+        };
+        let (skipped, _) = count(NPolicy::Skip, 0);
+        assert_eq!(skipped.valid_kmers, 3);
+        let (resolved, kmers) = count(NPolicy::A, 0);
+        assert_eq!(kmers, ["AAC", "ACA", "ACG", "ATA", "CAT", "CGT", "GTA", "TAA", "TAC"]);
+        // every window but that of the gap is valid, the nucleotide counts are unchanged
+        assert_eq!(resolved.valid_kmers, 9);
+        assert_eq!((resolved.acgt_nucleotides, resolved.gc_nucleotides, resolved.n_nucleotides), (9, 4, 1));
+        assert_eq!((skipped.acgt_nucleotides, skipped.gc_nucleotides, skipped.n_nucleotides), (9, 4, 1));
+
+        // reproducible resolutions, among those of --expand-iupac
+        let (random, kmers) = count(NPolicy::Random, 1);
+        assert_eq!(random.valid_kmers, 9);
+        assert_eq!(count(NPolicy::Random, 1).1, kmers);
+        let expanded = DashSet::<u64>::new();
+        KmerCounter::new(3).expand_iupac(Some(1)).count_reader(&fasta[..], KmerStore::Set(&expanded)).unwrap();
+        assert!(kmers.iter().all(|kmer| expanded.contains(&u64::encode(kmer.as_bytes()).unwrap())));
+    }
+
+    #[test]
+    fn gc_content_excludes_ambiguous_bases() {
+        let stats = count_unique_kmers(&b">a\nGCgcNNAT\n>b\nRYAA\n"[..], 3, false).unwrap();
+        assert_eq!(stats.acgt_nucleotides, 8);
+        assert_eq!(stats.gc_nucleotides, 4);
+        assert_eq!(stats.gc_content(), Some(0.5));
+        assert_eq!(count_unique_kmers(&b">n\nNNN\n"[..], 3, false).unwrap().gc_content(), None);
+    }
+
+    #[test]
+    fn validity_ratio_is_that_of_the_windows() {
+        // ACG CGN GNA NAC ACG: 2 valid windows of 5
+        let stats = count_unique_kmers(&b">a\nACGNACG\n"[..], 3, false).unwrap();
+        assert_eq!(stats.validity_ratio(), Some(0.4));
+        assert_eq!(count_unique_kmers(&b">a\nAC\n"[..], 3, false).unwrap().validity_ratio(), None);
+    }
+
+    #[test]
+    fn records_outside_length_range_are_skipped() {
+        let fasta: &[u8] = b">a\nACGT\n>b\nACGTACGT\n>c\nACGTACGTACGT\n";
+        let stats = KmerCounter::new(3)
+            .min_length(5)
+            .max_length(8)
+            .count_reader(fasta, KmerStore::<u64>::None)
+            .unwrap();
+        assert_eq!(stats.skipped_records, 2);
+        assert_eq!(stats.total_nucleotides, 8);
+        assert_eq!(stats.total_kmers, 6);
+    }
+
+    #[test]
+    fn trimmed_ends_are_not_counted() {
+        let fastq: &[u8] = b"@r\nNTACGTNCGGA\n+\n#IIIIIIIII#\n@s\nACG\n+\nIII\n";
+        let trimmed: &[u8] = b"@r\nACGTNCG\n+\nIIIIIII\n";
+        for n_policy in [NPolicy::Skip, NPolicy::A] {
+            let counter = KmerCounter::new(3).min_quality(Some(20)).n_policy(n_policy);
+            let (kmers, expected_kmers) = (DashSet::<u64>::new(), DashSet::<u64>::new());
+            let stats = counter.clone().trim_start(2).trim_end(2).count_reader(fastq, KmerStore::Set(&kmers)).unwrap();
+            let expected = counter.count_reader(trimmed, KmerStore::Set(&expected_kmers)).unwrap();
+            assert_eq!(kmers.into_iter().collect::<HashSet<u64>>(), expected_kmers.into_iter().collect());
+            assert_eq!(
+                (stats.total_nucleotides, stats.total_kmers, stats.valid_kmers, stats.n_nucleotides, stats.acgt_nucleotides),
+                (expected.total_nucleotides, expected.total_kmers, expected.valid_kmers, expected.n_nucleotides, expected.acgt_nucleotides)
+            );
+            // the record of 3 nucleotides has none left
+            assert_eq!(stats.short_records, 1);
+        }
+    }
+
+    #[test]
+    fn only_the_ends_are_counted() {
+        // the ends of r, once trimmed, count as two records; s is shorter than both ends and counts once
+        let fasta: &[u8] = b">r\nAAAAACCCCCGGGGGTTTTTNACGT\n>s\nACGTAC\n";
+        let ends: &[u8] = b">r1\nAAAAA\n>r2\nTNACG\n>s\nACGTA\n";
+        for n_policy in [NPolicy::Skip, NPolicy::A] {
+            let counter = KmerCounter::new(3).n_policy(n_policy);
+            let (kmers, expected_kmers) = (DashSet::<u64>::new(), DashSet::<u64>::new());
+            let mut lengths = Vec::new();
+            let stats = counter
+                .clone()
+                .trim_end(1)
+                .ends_only(Some(5))
+                .count_reader_with(fasta, KmerStore::Set(&kmers), |record| lengths.push(record.length))
+                .unwrap();
+            let expected = counter.count_reader(ends, KmerStore::Set(&expected_kmers)).unwrap();
+            assert_eq!(kmers.into_iter().collect::<HashSet<u64>>(), expected_kmers.into_iter().collect());
+            assert_eq!(
+                (stats.total_nucleotides, stats.total_kmers, stats.valid_kmers, stats.n_nucleotides),
+                (expected.total_nucleotides, expected.total_kmers, expected.valid_kmers, expected.n_nucleotides)
+            );
+            assert_eq!(lengths, [10, 5]);
+        }
+    }
+
+    #[test]
+    fn duplicate_records_are_counted_once() {
+        let fasta: &[u8] = b">a\nACGTACGT\n>b\nACGTACGT\n>c\nacgtacgt\n>d\nTTTT\n";
+        let counter = KmerCounter::new(3).dedup_records(true);
+        let stats = counter.count_reader(fasta, KmerStore::<u64>::None).unwrap();
+        assert_eq!((stats.records, stats.duplicate_records, stats.total_nucleotides), (4, 1, 20));
+        // the sequences of the previous counts are remembered
+        let stats = counter.count_reader(&b">e\nTTTT\n>f\nTTTTT\n"[..], KmerStore::<u64>::None).unwrap();
+        assert_eq!((stats.duplicate_records, stats.total_nucleotides), (1, 5));
+
+        // each record is checked once for all k
+        let sets = [DashSet::<u64>::new(), DashSet::new()];
+        let stores = [KmerStore::Set(&sets[0]), KmerStore::Set(&sets[1])];
+        let stats = KmerCounter::new(3).dedup_records(true).count_reader_ks(fasta, &[3, 4], &stores).unwrap();
+        assert!(stats.iter().all(|stats| (stats.duplicate_records, stats.total_nucleotides) == (1, 20)));
+    }
+
+    #[test]
+    fn only_records_of_matching_ids_are_counted() {
+        let fasta: &[u8] = b">chr1 first\nACGTA\n>scaffold_1\nCCCCCC\n>chrX\nGGG\n";
+        let kmers = DashSet::<u64>::new();
+        let counter = KmerCounter::new(3).id_regex(Some(Regex::new("^chr").unwrap()));
+        let stats = counter.count_reader(fasta, KmerStore::Set(&kmers)).unwrap();
+        assert_eq!((stats.records, stats.unmatched_records, stats.total_nucleotides, stats.distinct), (3, 1, 8, 4));
+        assert!(!kmers.contains(&u64::encode(b"CCC").unwrap()));
+    }
+
+    #[test]
+    fn compare_splits_shared_kmers() {
+        let counter = KmerCounter::new(3).canonical(true);
+        let reference = DashSet::<u64>::new();
+        counter.count_reader(&b">a\nACGTT\n"[..], KmerStore::Set(&reference)).unwrap();
+        let (shared, others) = (DashSet::new(), DashSet::new());
+        let stats = counter
+            .count_reader(&b">b\nAACGAA\n"[..], KmerStore::Compare { reference: &reference, shared: &shared, others: &others })
+            .unwrap();
+        // ACG and AAC (GTT) are shared, CGA and GAA are not
+        assert_eq!(stats.distinct, 4);
+        assert_eq!(shared.len(), 2);
+        assert_eq!(others.len(), 2);
+        assert_eq!(reference.len(), 2);
+    }
+
+    #[test]
+    fn both_strands_count_palindromes_once() {
+        let count = |strand| {
+            KmerCounter::new(4)
+                .strand(strand)
+                .count_reader(&b">a\nAAACGT\n"[..], KmerStore::Set(&DashSet::<u64>::new()))
+                .unwrap()
+                .distinct
+        };
+        // AAAC, AACG, ACGT (palindrome)
+        assert_eq!(count(Strand::Forward), 3);
+        assert_eq!(count(Strand::Canonical), 3);
+        // plus GTTT and CGTT
+        assert_eq!(count(Strand::Both), 5);
+    }
+
+    #[test]
+    fn canonical_counting_counts_each_palindrome_once() {
+        // reverse complement of the text, independent of the 2-bit encoding
+        let reverse_complement = |kmer: &str| -> String {
+            kmer.chars().rev().map(|n| match n { 'A' => 'T', 'C' => 'G', 'G' => 'C', _ => 'A' }).collect()
+        };
+        for k in 1..=6 {
+            // every k-mer, once per record, so that each window is the k-mer itself
+            let all: Vec<String> = (0..4u64.pow(k as u32)).map(|i| kmer::u64_to_kmer(i, k)).collect();
+            let palindromes: Vec<&String> = all.iter().filter(|&kmer| *kmer == reverse_complement(kmer)).collect();
+            assert_eq!(palindromes.len(), if k % 2 == 0 { 4usize.pow(k as u32 / 2) } else { 0 }, "k = {}", k);
+            for kmer in &all {
+                let encoded = u64::encode(kmer.as_bytes()).unwrap();
+                assert_eq!(encoded.reverse_complement(k).decode(k), reverse_complement(kmer));
+            }
+
+            let fasta: Vec<u8> = all.iter().flat_map(|kmer| format!(">{}\n{}\n", kmer, kmer).into_bytes()).collect();
+            let counts = DashMap::<u64, u64>::new();
+            let stats = KmerCounter::new(k).canonical(true).count_reader(&fasta[..], KmerStore::Map(&counts)).unwrap();
+            assert_eq!(stats.distinct as u128, Strand::Canonical.max_kmers(k).unwrap());
+            // a palindrome is its own canonical k-mer, read once; any other k-mer is read along with its reverse complement
+            for kmer in &all {
+                let canonical = kmer.clone().min(reverse_complement(kmer));
+                let expected = if palindromes.contains(&kmer) { 1 } else { 2 };
+                assert_eq!(counts.get(&u64::encode(canonical.as_bytes()).unwrap()).map(|count| *count), Some(expected), "{}", kmer);
+            }
+
+            // the palindromes alone: as many distinct k-mers on either strand
+            let fasta: Vec<u8> = palindromes.iter().flat_map(|kmer| format!(">{}\n{}\n", kmer, kmer).into_bytes()).collect();
+            for strand in [Strand::Forward, Strand::Canonical, Strand::Both] {
+                let kmers = DashSet::<u128>::new();
+                let stats = KmerCounter::new(k).strand(strand).count_reader(&fasta[..], KmerStore::Set(&kmers)).unwrap();
+                assert_eq!(stats.distinct, palindromes.len(), "k = {}, {}", k, strand);
+            }
+        }
+    }
+
+    #[test]
+    fn spaced_seed_skips_zero_positions() {
+        let count = |fasta: &[u8]| {
+            let kmers = DashSet::<u64>::new();
+            let stats = KmerCounter::new(3)
+                .spaced_seed(Some("1101".parse().unwrap()))
+                .count_reader(fasta, KmerStore::Set(&kmers))
+                .unwrap();
+            let mut decoded: Vec<String> = kmers.iter().map(|kmer| kmer.decode(3)).collect();
+            decoded.sort();
+            (stats, decoded)
+        };
+        // windows ACGT, CGTN and GTNA: the N is under a 1 in the second one, under a 0 in the third one
+        let (stats, kmers) = count(b">a\nACGTNA\n");
+        assert_eq!(stats.total_kmers, 3);
+        assert_eq!(stats.valid_kmers, 2);
+        assert_eq!(kmers, ["ACT", "GTA"]);
+    }
+
+    #[test]
+    fn counting_stops_at_max_distinct() {
+        let fasta: Vec<u8> = (0..5000).flat_map(|i| format!(">{}\n{}\n", i, kmer::u64_to_kmer(i, 12)).into_bytes()).collect();
+        let kmers = DashSet::<u64>::new();
+        let stats = KmerCounter::new(12)
+            .max_distinct(Some(100))
+            .threads(2)
+            .count_reader(&fasta[..], KmerStore::Set(&kmers))
+            .unwrap();
+        assert!(stats.limit_reached);
+        assert!(stats.distinct >= 100 && stats.distinct < 5000);
+        assert_eq!(stats.valid_kmers, stats.distinct);
+    }
+
+    #[test]
+    fn counting_stops_when_interrupted() {
+        let fasta: Vec<u8> = (0..5000).flat_map(|i| format!(">{}\n{}\n", i, kmer::u64_to_kmer(i, 12)).into_bytes()).collect();
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let counter = KmerCounter::new(12).interrupt(Some(Arc::clone(&interrupt))).in_flight(4);
+        let kmers = DashSet::<u64>::new();
+        let stats = counter.count_reader_with(&fasta[..], KmerStore::Set(&kmers), |_| interrupt.store(true, Ordering::Relaxed)).unwrap();
+        assert!(stats.interrupted);
+        assert!(stats.records > 0 && stats.records < 5000);
+        // the records read are all counted
+        assert_eq!(stats.distinct, stats.records);
+
+        let stats = counter.count_reader(&fasta[..], KmerStore::Set(&DashSet::<u64>::new())).unwrap();
+        assert_eq!((stats.records, stats.interrupted), (0, true));
+        interrupt.store(false, Ordering::Relaxed);
+        let stats = counter.count_reader(&fasta[..], KmerStore::Set(&DashSet::<u64>::new())).unwrap();
+        assert_eq!((stats.records, stats.interrupted), (5000, false));
+    }
+
+    #[test]
+    fn rna_gives_the_counts_of_its_dna() {
+        let count = |fasta: &[u8]| {
+            let kmers = DashSet::<u64>::new();
+            let stats = KmerCounter::new(5).canonical(true).count_reader(fasta, KmerStore::Set(&kmers)).unwrap();
+            let mut kmers: Vec<u64> = kmers.into_iter().collect();
+            kmers.sort_unstable();
+            (stats, kmers)
+        };
+        assert_eq!(count(b">rna\nAUGGCUUACGuuaNCCU\n"), count(b">dna\nATGGCTTACGttaNCCT\n"));
+    }
+
+    #[test]
+    fn windows_line_endings_give_the_same_counts() {
+        let count = |input: &[u8]| {
+            let kmers = DashSet::<u64>::new();
+            let mut ids = Vec::new();
+            let stats = KmerCounter::new(4).count_reader_with(input, KmerStore::Set(&kmers), |record| ids.push(record.id)).unwrap();
+            let mut kmers: Vec<u64> = kmers.into_iter().collect();
+            kmers.sort_unstable();
+            (stats, kmers, ids)
+        };
+        assert_eq!(count(b">a\r\nACGTACGTTA\r\n>b\r\nAC GT\r\n"), count(b">a\nACGTACGTTA\n>b\nACGT\n"));
+        assert_eq!(count(b"@a\r\nACGTACGTTA\r\n+\r\nIIIIIIIIII\r\n"), count(b"@a\nACGTACGTTA\n+\nIIIIIIIIII\n"));
+    }
+
+    #[test]
+    fn membership_records_the_records_of_each_kmer() {
+        // the malformed record is not numbered
+        let fasta = b">a\nACGTA\n>bad\n\n>b\nCGTAA\n>c\nTTTT\n";
+        let records = DashMap::<u64, u64>::new();
+        let counter = KmerCounter::new(4).skip_invalid(true).in_flight(2);
+        counter.count_reader(&fasta[..], KmerStore::Membership(&records)).unwrap();
+        let mut out = Vec::new();
+        let mut memberships: Vec<(u64, u64)> = records.iter().map(|entry| (*entry.key(), *entry.value())).collect();
+        memberships.sort_unstable();
+        write_kmer_membership_iter(memberships.into_iter(), 4, 3, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "ACGT\t100\nCGTA\t110\nGTAA\t010\nTTTT\t001\n");
+
+        let fasta = |records: usize| -> Vec<u8> { (0..records).flat_map(|i| format!(">{}\nACGT\n", i).into_bytes()).collect() };
+        assert!(counter.count_reader(&fasta(MAX_MEMBERSHIP_RECORDS)[..], KmerStore::Membership(&records)).is_ok());
+        let error = counter.count_reader(&fasta(MAX_MEMBERSHIP_RECORDS + 1)[..], KmerStore::Membership(&records)).unwrap_err();
+        assert!(matches!(error, KmerError::TooManyRecords { max: MAX_MEMBERSHIP_RECORDS }), "{:?}", error);
+    }
+
+    #[test]
+    fn counts_do_not_depend_on_the_number_of_threads() {
+        // records of various lengths, with N and other invalid nucleotides, sharing many k-mers
+        let fasta: Vec<u8> = (0..3000)
+            .flat_map(|i: u64| {
+                let random = kmer::u64_to_kmer(i * 7919, 10);
+                format!(">r{}\n{}N{}ACGTRACG{}\n", i, "ACGGT".repeat(i as usize % 11), random, "TTGCA".repeat(i as usize % 5)).into_bytes()
+            })
+            .collect();
+        let count = |threads| {
+            let counter = KmerCounter::new(7).canonical(true).threads(threads).in_flight(64);
+            let kmers = DashSet::<u64>::new();
+            let stats = counter.count_reader(&fasta[..], KmerStore::Set(&kmers)).unwrap();
+            let counts = DashMap::<u64, u64>::new();
+            assert_eq!(counter.count_reader(&fasta[..], KmerStore::Map(&counts)).unwrap(), stats);
+            let mut counts: Vec<(u64, u64)> = counts.into_iter().collect();
+            counts.sort_unstable();
+            (stats, counts)
+        };
+        let (stats, counts) = count(1);
+        assert!(stats.distinct > 1000 && stats.n_kmers > 0 && stats.valid_kmers < stats.total_kmers);
+        assert_eq!(counts.len(), stats.distinct);
+        for threads in [2, 4, 8] {
+            assert_eq!(count(threads), (stats, counts.clone()), "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn stride_reads_every_few_windows() {
+        let seq = "ACGTTGCATGACCTAGGTCAAN";
+        let fasta = format!(">a\n{}\n", seq);
+        for (stride, trim_start) in [(1, 0), (3, 0), (3, 2), (4, 1), (30, 0)] {
+            let kmers = DashSet::<u64>::new();
+            let stats = KmerCounter::new(5)
+                .stride(stride)
+                .trim_start(trim_start)
+                .count_reader(fasta.as_bytes(), KmerStore::Set(&kmers))
+                .unwrap();
+            let windows: Vec<&str> = (trim_start..=seq.len() - 5).step_by(stride).map(|start| &seq[start..start + 5]).collect();
+            let valid: HashSet<&str> = windows.iter().copied().filter(|window| !window.contains('N')).collect();
+            assert_eq!(stats.total_kmers, windows.len(), "stride {}", stride);
+            assert_eq!(stats.valid_kmers + stats.n_kmers, windows.len());
+            assert_eq!(kmers.iter().map(|kmer| kmer.decode(5)).collect::<HashSet<_>>(), valid.iter().map(|window| window.to_string()).collect());
+        }
+        assert!(KmerCounter::new(3).stride(0).count_reader(fasta.as_bytes(), KmerStore::<u64>::None).is_err());
+    }
+
+    #[test]
+    fn long_records_counted_in_pieces_give_the_same_counts() {
+        // long records with N, other IUPAC codes and soft-masked stretches, and short ones
+        let fasta: Vec<u8> = (0..40)
+            .flat_map(|i: u64| {
+                let seq: String = (0..i * 13 % 97).map(|j| kmer::u64_to_kmer(i * 7919 + j * 104_729, 10)).collect();
+                format!(">r{}\n{}N{}acgtRYACG{}\n", i, seq, "ACGGT".repeat(i as usize % 11), seq.to_lowercase()).into_bytes()
+            })
+            .collect();
+        let counters = [
+            KmerCounter::new(7).canonical(true),
+            KmerCounter::new(7).n_policy(NPolicy::Random).trim_start(3).trim_end(20),
+            KmerCounter::new(9).case_sensitive(true).ends_only(Some(150)),
+            KmerCounter::new(6).spaced_seed(Some("11011101".parse().unwrap())).max_homopolymer(Some(3)),
+            KmerCounter::new(7).stride(5).trim_start(2),
+            KmerCounter::new(9).stride(3).ends_only(Some(150)),
+        ];
+        for counter in counters {
+            let count = |counter: KmerCounter| {
+                let kmers = DashSet::<u64>::new();
+                let stats = counter.count_reader(&fasta[..], KmerStore::Set(&kmers)).unwrap();
+                let mut kmers: Vec<u64> = kmers.into_iter().collect();
+                kmers.sort_unstable();
+                (stats, kmers)
+            };
+            let (stats, kmers) = count(counter.clone().threads(1));
+            assert!(stats.distinct > 100 && stats.total_nucleotides > 10_000);
+            for chunk_size in [1, 7, 100] {
+                assert_eq!(count(counter.clone().threads(3).chunk_size(chunk_size)), (stats, kmers.clone()), "{:?}", counter);
+            }
+        }
+        assert!(KmerCounter::new(3).chunk_size(0).count_reader(&fasta[..], KmerStore::<u64>::None).is_err());
+    }
+
+    #[test]
+    fn snapshots_are_taken_at_each_multiple_of_the_interval() {
+        // records of 100 nucleotides with distinct k-mers of their own
+        let fasta = |records: u64| -> Vec<u8> {
+            (0..records).flat_map(|i| format!(">r{}\n{}\n", i, kmer::u64_to_kmer(i * 7919, 25).repeat(4)).into_bytes()).collect()
+        };
+        let distinct = |records| KmerCounter::new(11).count_reader(&fasta(records)[..], KmerStore::Set(&DashSet::<u64>::new())).unwrap().distinct;
+        let counter = KmerCounter::new(11).threads(1).snapshot_interval(Some(250));
+        assert_eq!(counter.snapshots(), Some(Vec::new()));
+        let kmers = DashSet::<u64>::new();
+        counter.count_reader(&fasta(10)[..], KmerStore::Set(&kmers)).unwrap();
+        // the 3rd record crosses 250, the 5th 500, the 8th 750 and the 10th 1000
+        let expected = [(250, distinct(3)), (500, distinct(5)), (750, distinct(8)), (1000, distinct(10))];
+        assert_eq!(counter.snapshots().unwrap(), expected);
+
+        // the snapshots go on over the counts, whatever the threads
+        let counter = KmerCounter::new(11).threads(4).snapshot_interval(Some(30));
+        for _ in 0..2 {
+            counter.count_reader(&fasta(10)[..], KmerStore::Set(&DashSet::<u64>::new())).unwrap();
+        }
+        let multiples: Vec<usize> = counter.snapshots().unwrap().into_iter().map(|(nucleotides, _)| nucleotides).collect();
+        assert_eq!(multiples, (1..=66).map(|multiple| multiple * 30).collect::<Vec<_>>());
+        assert!(KmerCounter::new(11).snapshot_interval(Some(0)).count_reader(&fasta(1)[..], KmerStore::<u64>::None).is_err());
+        assert_eq!(KmerCounter::new(11).snapshots(), None);
+    }
+
+    #[test]
+    fn other_alphabets_are_counted_as_their_codes() {
+        // H and P stand for A and C, X is not a symbol
+        let count = |alphabet: &str, fasta: &[u8]| {
+            let kmers = DashSet::<u64>::new();
+            let counter = KmerCounter::new(3).case_sensitive(true).alphabet(alphabet.parse().unwrap());
+            let stats = counter.count_reader(fasta, KmerStore::Set(&kmers)).unwrap();
+            let mut kmers: Vec<u64> = kmers.into_iter().collect();
+            kmers.sort_unstable();
+            (stats.valid_kmers, stats.total_nucleotides, kmers)
+        };
+        assert_eq!(count("HP", b">r\nHPPHXHPhPPHH\n"), count("ACGT", b">r\nACCANACaCCAA\n"));
+        assert_eq!(count("HP", b">r\nHPPHXHPhPPHH\n").0, 4);
+
+        let fasta = b">r\nHPPH\n";
+        let counter = KmerCounter::new(3).alphabet("HP".parse().unwrap());
+        assert!(counter.clone().count_reader(&fasta[..], KmerStore::<u64>::None).is_ok());
+        assert!(counter.clone().canonical(true).count_reader(&fasta[..], KmerStore::<u64>::None).is_err());
+        assert!(counter.n_policy(NPolicy::A).count_reader(&fasta[..], KmerStore::<u64>::None).is_err());
+    }
+
+    #[test]
+    fn records_in_flight_do_not_change_the_counts() {
+        let fasta: Vec<u8> = (0..500).flat_map(|i| format!(">read{}\nACGTAC{:b}\n", i, i).into_bytes()).collect();
+        let count = |in_flight| {
+            let kmers = DashSet::<u64>::new();
+            let mut ids = Vec::new();
+            let counter = KmerCounter::new(3).threads(3).in_flight(in_flight);
+            let stats = counter.count_reader_with(&fasta[..], KmerStore::Set(&kmers), |record| ids.push(record.id)).unwrap();
+            (stats, ids)
+        };
+        let (stats, ids) = count(IN_FLIGHT_RECORDS);
+        assert_eq!(ids, (0..500).map(|i| format!("read{}", i)).collect::<Vec<_>>());
+        for in_flight in [1, 2, 5, 64] {
+            assert_eq!(count(in_flight), (stats, ids.clone()));
+        }
+        assert!(KmerCounter::new(3).in_flight(0).count_reader(&fasta[..], KmerStore::<u64>::None).is_err());
+    }
+
+    #[test]
+    fn paired_files_count_as_their_concatenation() {
+        let r1 = b"@a/1\nACGTAC\n+\nIIIIII\n@b/1\nGGGTTA\n+\nIIIIII\n";
+        let r2 = b"@a/2\nTTGCAA\n+\nIIIIII\n@b/2\nCCATGA\n+\nIIIIII\n@c/2\nACGTTT\n+\nIIIIII\n";
+        let counter = KmerCounter::new(4).canonical(true);
+        let kmers = DashSet::<u64>::new();
+        let concatenated = counter.count_reader(&[&r1[..], &r2[..]].concat()[..], KmerStore::Set(&kmers)).unwrap();
+        let kmers = DashSet::<u64>::new();
+        let mut ids = Vec::new();
+        let paired = counter.count_reader_pair_with(&r1[..], &r2[..], KmerStore::Set(&kmers), |record| ids.push(record.id)).unwrap();
+        assert_eq!(paired, concatenated);
+        assert_eq!(ids, ["a/1", "a/2", "b/1", "b/2", "c/2"]);
+        let error = counter.count_reader_pair(&r1[..], &b"@a/2\nACGT\n+\nII\n"[..], KmerStore::<u64>::None).unwrap_err();
+        assert!(error.to_string().contains("(a/2)"), "{}", error);
+    }
+
+    #[test]
+    fn singletons_are_bucket_one_of_the_histogram() {
+        let counts = DashMap::<u64, u64>::new();
+        KmerCounter::new(3).count_reader(&b">a\nACGTACGTT\n"[..], KmerStore::Map(&counts)).unwrap();
+        // ACG and CGT twice, GTA, TAC and GTT once
+        let stats = multiplicity_stats(&counts);
+        assert_eq!((stats.max, stats.mean, stats.singletons, stats.singleton_fraction), (2, 1.4, 3, 0.6));
+        assert_eq!(kmer_histogram(&counts, 10)[1], stats.singletons as u64);
+        assert_eq!(multiplicity_stats(&DashMap::<u64, u64>::new()), MultiplicityStats::default());
+    }
+
+    #[test]
+    fn jellyfish_layouts() {
+        let counts = [(u64::encode(b"ACG").unwrap(), 2), (u64::encode(b"GTT").unwrap(), 1)];
+        let mut out = Vec::new();
+        write_jellyfish_dump_iter(counts.into_iter(), 3, JellyfishDump::Fasta, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), ">2\nACG\n>1\nGTT\n");
+        let mut out = Vec::new();
+        write_jellyfish_dump_iter(counts.into_iter(), 3, JellyfishDump::Columns, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "ACG 2\nGTT 1\n");
+        let mut out = Vec::new();
+        write_jellyfish_histo(&[0, 3, 0, 1], &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "1 3\n3 1\n");
+    }
+
+    #[test]
+    fn top_kmers_by_decreasing_count() {
+        let counts = DashMap::<u64, u64>::new();
+        for (kmer, count) in [(5, 2), (1, 9), (7, 4), (3, 4), (2, 1)] {
+            counts.insert(kmer, count);
+        }
+        assert_eq!(top_kmers(&counts, 3), [(1, 9), (3, 4), (7, 4)]);
+        assert_eq!(top_kmers(&counts, 10).len(), 5);
+        assert!(top_kmers(&counts, 0).is_empty());
+    }
+
+    #[test]
+    fn coverage_peak_follows_the_error_spike() {
+        // errors at 1 and 2, coverage around 5, repeats in the last bucket
+        assert_eq!(coverage_peak(&[0, 900, 200, 40, 60, 90, 70, 10, 500]), Some(5));
+        assert_eq!(coverage_peak(&[0, 900, 200, 40, 40, 500]), None);
+        assert_eq!(coverage_peak(&[0, 10]), None);
+        assert_eq!(coverage_peak(&[]), None);
+    }
+
+    #[test]
+    fn n50_of_record_lengths() {
+        // 18 nucleotides, of which the two longest records hold 12
+        let stats = length_stats(&mut [2, 8, 4, 4]);
+        assert_eq!(stats, LengthStats { records: 4, min: 2, max: 8, mean: 4.5, n50: 4 });
+        assert_eq!(length_stats(&mut []), LengthStats::default());
+
+        let counter = KmerCounter::new(3).keep_lengths(true).min_length(5);
+        counter.count_reader(&b">a\nACGTACGT\n>b\nAC\n"[..], KmerStore::<u64>::None).unwrap();
+        counter.count_reader(&b">c\nACGT\n>d\nACGT\n"[..], KmerStore::<u64>::None).unwrap();
+        assert_eq!(counter.length_stats(), Some(LengthStats { records: 4, min: 2, max: 8, mean: 4.5, n50: 4 }));
+        assert_eq!(KmerCounter::new(3).length_stats(), None);
+    }
+
+    #[test]
+    fn sampling_is_reproducible() {
+        let fasta: Vec<u8> = (0..2000).flat_map(|i| format!(">read{}\nACGTACGT\n", i).into_bytes()).collect();
+        let sample = |seed| {
+            let counter = KmerCounter::new(4).sample(Some(0.1)).seed(seed);
+            let mut ids = Vec::new();
+            counter.count_reader_with(&fasta[..], KmerStore::<u64>::None, |record| ids.push(record.id)).unwrap();
+            ids
+        };
+        let ids = sample(1);
+        assert!(ids.len() > 150 && ids.len() < 250, "{} records sampled", ids.len());
+        assert_eq!(ids, sample(1));
+        assert_ne!(ids, sample(2));
+    }
+
+    #[test]
+    fn valid_kmers_are_the_encodable_windows() {
+        let seq = b"ACGTNacgtRYKM-.*ACGT0ACGTXacgtACGTACGTnACGT#ACGTAC";
+        let fasta = [&b">a\n"[..], seq, b"\n"].concat();
+        for k in [1, 2, 3, 4, 5, 8] {
+            let stats = count_unique_kmers(&fasta[..], k, false).unwrap();
+            let encodable = seq.windows(k).filter(|window| kmer::kmer_to_u64(window).is_some()).count();
+            assert_eq!(stats.valid_kmers, encodable, "k = {}", k);
+
+            let stats = KmerCounter::new(k)
+                .case_sensitive(true)
+                .count_reader(&fasta[..], KmerStore::<u64>::None)
+                .unwrap();
+            let uppercase = seq
+                .windows(k)
+                .filter(|window| window.iter().all(|n| b"ACGT".contains(n)))
+                .count();
+            assert_eq!(stats.valid_kmers, uppercase, "k = {}", k);
+        }
+    }
+}
@@ -1,123 +1,1541 @@
-use clap::{Arg, Command};
-use dashmap::DashSet;
-use fxread::initialize_reader;
-use rayon::ThreadPoolBuilder;
-use std::io::{self};
-use std::sync::atomic::{AtomicUsize, Ordering};
+mod memory;
+mod report;
+
+use clap::{Arg, ArgAction, Command};
+use dashmap::{DashMap, DashSet};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{Level, LevelFilter};
+use niffler::compression::Format as CompressionFormat;
+use regex::bytes::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+use std::hash::RandomState;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
 use std::process;
-use std::sync::Arc;
-
-const fn nucleotide_to_bits(n: u8) -> Option<u64> {
-    match n {
-        b'A' => Some(0b00),
-        b'C' => Some(0b01),
-        b'G' => Some(0b10),
-        b'T' => Some(0b11),
-        _ => None,
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use report::{Format, Report};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use unique_kmer_counter::binary::{read_binary_with, write_binary_iter};
+use unique_kmer_counter::checkpoint::Checkpoint;
+use unique_kmer_counter::kmer::{iupac_nucleotides, kmer_to_u128};
+use unique_kmer_counter::minhash::{self, Sketch};
+use unique_kmer_counter::twobit;
+use unique_kmer_counter::{
+    coverage_peak, decompress, detect_format, hll, kmer_histogram, multiplicity_stats, preview_input, top_kmers, write_histogram, write_jellyfish_dump_iter, write_jellyfish_histo, write_kmer_count_iter, write_kmer_iter, write_kmer_membership_iter, BloomFilter, HyperLogLog, Kmer,
+    Alphabet, ExternalSet, JellyfishDump, KmerBuildHasher, KmerCounter, KmerError, KmerHashing, KmerStats, KmerStore, MinHash, NPolicy, RecordStats, ShardedSet, SpacedSeed, Strand, MAX_MEMBERSHIP_RECORDS,
+};
+
+/// Opens the output file, `-` meaning stdout. Files ending in .gz are gzip-compressed at `compression`.
+/// Errors are prefixed with the path.
+fn create_output(path: &str, compression: Compression) -> io::Result<Box<dyn Write + Send>> {
+    if path == "-" {
+        // not locked, to be writable from the counting threads
+        return Ok(Box::new(BufWriter::new(io::stdout())));
+    }
+    let file = File::create(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+    if path.ends_with(".gz") {
+        // the encoder writes the end of the gzip stream when dropped
+        Ok(Box::new(BufWriter::new(GzEncoder::new(file, compression))))
+    } else {
+        Ok(Box::new(BufWriter::new(file)))
     }
 }
 
-fn kmer_to_u64(sequence: &[u8]) -> Option<u64> {
-    if sequence.len() > 32 {
-        return None;
+/// Output of k-mers in the symbols of --alphabet, translated from the A, C, G and T they are decoded to
+struct AlphabetWriter {
+    alphabet: Alphabet,
+    out: Box<dyn Write + Send>,
+    buffer: Vec<u8>,
+}
+
+impl Write for AlphabetWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buffer.clear();
+        self.buffer.extend_from_slice(bytes);
+        self.alphabet.translate(&mut self.buffer);
+        self.out.write_all(&self.buffer)?;
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
     }
+}
 
-    let mut encoded: u64 = 0;
-    for &nucleotide in sequence {
-        encoded = (encoded << 2) | nucleotide_to_bits(nucleotide)?;
+/// Opens an output of k-mers as `create_output` does, in the symbols of --alphabet
+fn create_kmer_output(path: &str, options: &Options) -> io::Result<Box<dyn Write + Send>> {
+    let out = create_output(path, options.compression)?;
+    if options.alphabet.is_dna() {
+        return Ok(out);
     }
-    Some(encoded)
+    Ok(Box::new(AlphabetWriter { alphabet: options.alphabet.clone(), out, buffer: Vec::new() }))
+}
+
+/// K-mer in the symbols of --alphabet
+fn decode<T: Kmer>(kmer: T, options: &Options) -> String {
+    let mut decoded = kmer.decode(options.k).into_bytes();
+    options.alphabet.translate(&mut decoded);
+    String::from_utf8_lossy(&decoded).into_owned()
 }
 
-// fn get_reader(filename: &str) -> io::Result<Box<dyn BufRead>> {
-//     let path = Path::new(filename);
-//     let file = File::open(path)?;
+/// Set of k-mers written with --compare and --output
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SetOp {
+    Intersection,
+    Union,
+    /// K-mers of the input files only
+    AOnly,
+    /// K-mers of the compared file only
+    BOnly,
+}
 
-//     if path.extension().and_then(|s| s.to_str()) == Some("gz") {
-//         Ok(Box::new(BufReader::new(GzDecoder::new(file))))
-//     } else {
-//         Ok(Box::new(BufReader::new(file)))
-//     }
-// }
+/// Command line options that are not counting settings
+struct Options {
+    fasta_files: Vec<String>,
+    /// R2 files of --pair, one per input file, or none
+    pair: Vec<String>,
+    k: usize,
+    strand: Strand,
+    alphabet: Alphabet,
+    /// Several k counted at once, in which case `k` is the largest
+    kmer_sizes: Vec<usize>,
+    reserve_size: usize,
+    shards: usize,
+    only_count: bool,
+    frequencies: bool,
+    /// Checkpoint written after each input file
+    checkpoint: Option<String>,
+    /// Checkpoint to resume from
+    resume: Option<String>,
+    /// Whether low-complexity k-mers are left out
+    low_complexity: bool,
+    /// Whether malformed records are skipped instead of failing
+    skip_invalid: bool,
+    /// Whether the duplicate records are left out
+    dedup_records: bool,
+    /// Whether only the records whose id matches --id-regex are counted
+    id_regex: bool,
+    /// Multiplicity from which k-mers are solid
+    min_count: Option<u64>,
+    /// Target k-mers of --include, the only ones counted
+    include: Option<Vec<u128>>,
+    output: Option<String>,
+    /// Level of the outputs written to .gz files
+    compression: Compression,
+    stream_output: Option<String>,
+    sort: bool,
+    output_binary: Option<String>,
+    input_binary: Vec<String>,
+    /// Whether the distinct k-mers are written back to the single file of --input-binary
+    save: bool,
+    histogram: Option<String>,
+    histo_max: usize,
+    /// Layout of --output, that of `jellyfish dump`, with the k-mer frequencies
+    jellyfish_dump: Option<JellyfishDump>,
+    /// Whether the histogram is written as by `jellyfish histo`
+    jellyfish_histo: bool,
+    /// Whether the genome size is estimated from the histogram
+    estimate_genome_size: bool,
+    /// Number of most frequent k-mers reported
+    top: Option<usize>,
+    /// File of the records of each k-mer
+    membership: Option<String>,
+    /// File of the distinct counts of --snapshot-interval
+    snapshot_output: Option<String>,
+    /// Largest number of threads of --benchmark
+    benchmark: Option<usize>,
+    /// Number of files counted at once with --file-parallel
+    file_parallel: Option<usize>,
+    /// Whether each file is counted into a set of its own, for its distinct k-mers and those shared by all the files
+    per_file_distinct: bool,
+    timing: bool,
+    report_memory: bool,
+    /// Whether k-mers are excluded with --exclude
+    exclude: bool,
+    strict: bool,
+    gc: bool,
+    progress: bool,
+    per_record: Option<String>,
+    hll_precision: Option<u8>,
+    bloom: Option<(usize, u32)>,
+    /// File the Bloom filter of --bloom is written to
+    bloom_output: Option<String>,
+    /// Bloom file whose k-mers are looked up
+    query: Option<String>,
+    /// Size of the MinHash sketch of --sketch
+    sketch: Option<usize>,
+    /// Memory limit without --disk, in bytes, and the number of k-mer table entries that fit in it
+    memory_cap: Option<(u64, usize)>,
+    /// Maximum number of distinct k-mers of --max-distinct
+    max_distinct: Option<usize>,
+    /// Directory of the runs of --disk, and memory limit of the k-mers buffered before a run is written
+    disk: Option<(String, usize)>,
+    /// Summary TSV to which a row is appended
+    append_summary: Option<String>,
+    /// Sample name of the summary row
+    name: String,
+    minimizer: Option<usize>,
+    /// Nucleotides counted at each end of the records with --ends-only
+    ends_only: Option<usize>,
+    sample: Option<f64>,
+    /// Positions between the windows read with --stride
+    stride: usize,
+    compare: Option<String>,
+    set_op: Option<SetOp>,
+    format: Format,
+}
 
-fn process_fasta_parallel(filename: &str, k: usize, reserve_size: usize, max_threads: usize) -> io::Result<(usize, usize, usize, usize)> {
-    ThreadPoolBuilder::new().num_threads(max_threads).build_global().unwrap();
-    let reader = initialize_reader(&filename).unwrap();
+/// Exit codes of the failures, 0 being that of success. Listed in the README.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Exit {
+    /// Other errors, such as an output that cannot be written
+    Failure = 1,
+    /// Invalid arguments, as for the errors reported by clap
+    Arguments = 2,
+    /// An input file that cannot be opened, read or parsed
+    Input = 3,
+    /// Counting stopped at --memory-limit
+    MemoryLimit = 4,
+    /// No valid k-mer found with --strict
+    NoValidKmer = 5,
+    /// Counting stopped by Ctrl-C, as for a process killed by SIGINT
+    Interrupted = 130,
+}
 
-    let kmers = Arc::new(DashSet::with_capacity(reserve_size));
-    let total_nucleotides = Arc::new(AtomicUsize::new(0));
-    let nb_total_kmers = Arc::new(AtomicUsize::new(0));
-    let nb_valid_kmers = Arc::new(AtomicUsize::new(0));
-    
-    reader.for_each(|record|{ 
-            let seq = record.seq();
-
-            let local_kmers = DashSet::new();
-            let mut local_valid_kmers = 0;
-
-            nb_total_kmers.fetch_add(seq.len().saturating_sub(k) + 1, Ordering::Relaxed);
-            total_nucleotides.fetch_add(seq.len(), Ordering::Relaxed);
-            
-            for window in seq.windows(k) {
-                if !window.contains(&b'N') {
-                    if let Some(compact_kmer) = kmer_to_u64(window) {
-                        local_kmers.insert(compact_kmer);
-                        local_valid_kmers += 1;
-                    }
+impl Exit {
+    fn exit(self) -> ! {
+        process::exit(self as i32)
+    }
+
+    /// Exit code of an error made by `failure`, `Failure` for the others
+    fn of(error: &io::Error) -> Self {
+        error.get_ref().and_then(|error| error.downcast_ref::<Failed>()).map_or(Exit::Failure, |failed| failed.exit)
+    }
+}
+
+/// Message of an error, with the exit code it leads to
+#[derive(Debug)]
+struct Failed {
+    exit: Exit,
+    message: String,
+}
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failed {}
+
+/// Error of `kind` leading to the exit code `exit`
+fn failure(exit: Exit, kind: io::ErrorKind, message: String) -> io::Error {
+    io::Error::new(kind, Failed { exit, message })
+}
+
+/// Error `e` of the input at `path`, prefixed with it
+fn input_error(path: &str, e: io::Error) -> io::Error {
+    failure(Exit::Input, e.kind(), format!("{}: {}", path, e))
+}
+
+/// Error `e` of counting the input at `path`: invalid settings are argument errors, the others input errors
+fn count_error(path: &str, e: KmerError) -> io::Error {
+    match e {
+        KmerError::InvalidK { .. } | KmerError::InvalidSettings(_) => failure(Exit::Arguments, io::ErrorKind::InvalidInput, e.to_string()),
+        e => input_error(path, e.into()),
+    }
+}
+
+/// Progress bar of an input file on stderr, driven by the bytes read from it.
+/// Stdin has no known size and gets a spinner instead.
+fn progress_bar(path: &str) -> io::Result<ProgressBar> {
+    let progress = if path == "-" {
+        let spinner = ProgressBar::new_spinner()
+            .with_style(ProgressStyle::with_template("{spinner} {bytes} read ({bytes_per_sec})").unwrap());
+        spinner.enable_steady_tick(Duration::from_millis(100));
+        spinner
+    } else {
+        ProgressBar::new(std::fs::metadata(path)?.len()).with_style(
+            ProgressStyle::with_template("{msg} [{bar:40}] {bytes}/{total_bytes} ({eta})")
+                .unwrap()
+                .progress_chars("=> "),
+        )
+    };
+    progress.set_message(path.to_string());
+    Ok(progress)
+}
+
+/// Opens an input file ('-' for stdin), decompressing it if needed.
+/// The bytes read, before decompression, advance `progress`.
+fn open_input(path: &str, progress: Option<&ProgressBar>) -> io::Result<Box<dyn BufRead>> {
+    Ok(open_compressed(path, progress)?.0)
+}
+
+/// Same as `open_input`, also returning the compression found
+fn open_compressed(path: &str, progress: Option<&ProgressBar>) -> io::Result<(Box<dyn BufRead>, CompressionFormat)> {
+    let input: Box<dyn Read> = match (path, progress) {
+        ("-", Some(progress)) => Box::new(progress.wrap_read(io::stdin().lock())),
+        ("-", None) => Box::new(io::stdin().lock()),
+        (_, Some(progress)) => Box::new(progress.wrap_read(File::open(path)?)),
+        (_, None) => Box::new(File::open(path)?),
+    };
+    // compression (gzip, bzip2, xz, zstd) is detected from the magic bytes, not the extension
+    decompress(BufReader::new(input))
+}
+
+/// Opens a sequence file as `open_input` does, logging its compression and format, both found from its content
+fn open_sequences(path: &str, progress: Option<&ProgressBar>) -> io::Result<Box<dyn BufRead>> {
+    let (mut input, compression) = open_compressed(path, progress)?;
+    let compression = match compression {
+        CompressionFormat::Gzip => ", gzip-compressed",
+        CompressionFormat::Bzip => ", bzip2-compressed",
+        CompressionFormat::Lzma => ", xz-compressed",
+        CompressionFormat::Zstd => ", zstd-compressed",
+        CompressionFormat::No => "",
+    };
+    match detect_format(&mut input)? {
+        Some(format) => log::info!("Format of {}: {}{}", path, format, compression),
+        None => log::info!("{} is empty", path),
+    }
+    Ok(input)
+}
+
+/// Largest reserve chosen automatically, the former default
+const MAX_AUTO_RESERVE: usize = 3_000_000_000;
+/// Assumed ratio of decompressed to compressed sizes, about that of gzipped DNA
+const COMPRESSION_RATIO: u64 = 4;
+
+/// Initial capacity of the k-mer tables when --reserve is not given: half the bytes of the input files,
+/// a rough upper bound of their number of k-mers, at most 4^k and `MAX_AUTO_RESERVE`.
+/// Compressed and .2bit files count for `COMPRESSION_RATIO` times their size. Stdin and unreadable files count for nothing:
+/// the tables grow as needed.
+fn auto_reserve(paths: &[String], k: usize) -> usize {
+    let bytes: u64 = paths
+        .iter()
+        .filter(|path| path.as_str() != "-")
+        .filter_map(|path| {
+            let size = std::fs::metadata(path).ok()?.len();
+            let (mut input, format) = niffler::sniff(Box::new(File::open(path).ok()?)).ok()?;
+            let mut signature = [0; 4];
+            // .2bit files store 4 nucleotides per byte
+            let packed = input.read_exact(&mut signature).is_ok() && twobit::is_two_bit(&signature);
+            Some(if format != niffler::compression::Format::No || packed { size * COMPRESSION_RATIO } else { size })
+        })
+        .sum();
+    let max_kmers = 1usize.checked_shl(2 * k as u32).unwrap_or(usize::MAX);
+    usize::try_from(bytes / 2).unwrap_or(usize::MAX).min(max_kmers).min(MAX_AUTO_RESERVE)
+}
+
+/// Reserve of each of `tables` k-mer tables of entries of `entry_bytes` bytes, capped with a warning so that they
+/// take at most half of the memory available: a larger reserve would fail, or swap, before any k-mer is counted.
+/// The tables still grow as needed.
+fn cap_reserve(reserve: usize, entry_bytes: usize, tables: usize) -> usize {
+    let Some(available) = memory::available_memory() else {
+        return reserve;
+    };
+    let bytes = memory::table_bytes(reserve, entry_bytes).saturating_mul(tables as u64);
+    if bytes <= available / 2 {
+        return reserve;
+    }
+    let capped = memory::max_table_entries(available / 2 / tables as u64, entry_bytes);
+    log::warn!(
+        "a reserve of {} k-mers would take {}, more than half of the {} of memory available: reserving {} instead \
+        (lower --reserve to silence this warning)",
+        reserve,
+        memory::format_bytes(bytes),
+        memory::format_bytes(available),
+        capped
+    );
+    capped
+}
+
+/// Prints the diagnostics up to `level` to stderr, warnings and errors with their prefix
+fn init_logger(level: LevelFilter) {
+    env_logger::Builder::new()
+        .filter_level(level)
+        .format(|buf, record| match record.level() {
+            Level::Error => writeln!(buf, "Error: {}", record.args()),
+            Level::Warn => writeln!(buf, "Warning: {}", record.args()),
+            _ => writeln!(buf, "{}", record.args()),
+        })
+        .init();
+}
+
+/// Reads the k-mers of --exclude or --include, one per line, warning about the lines that are not k-mers of size `k`
+fn load_kmer_list(path: &str, k: usize) -> io::Result<Vec<u128>> {
+    let mut kmers = Vec::new();
+    let mut invalid = 0;
+    for line in open_input(path, None)?.lines() {
+        let line = line?;
+        let kmer = line.trim();
+        if kmer.is_empty() {
+            continue;
+        }
+        match kmer_to_u128(kmer.as_bytes()).filter(|_| kmer.len() == k) {
+            Some(encoded) => kmers.push(encoded),
+            None => invalid += 1,
+        }
+    }
+    if invalid > 0 {
+        log::warn!("skipped {} lines of {} that are not {}-mers of A, C, G, T", invalid, path, k);
+    }
+    Ok(kmers)
+}
+
+/// Reads the paths of the input files listed in the file of filenames of --fofn, one per line,
+/// leaving out blank lines and # comments
+fn load_fofn(path: &str) -> io::Result<Vec<String>> {
+    let mut paths = Vec::new();
+    for line in open_input(path, None)?.lines() {
+        let line = line?;
+        let file = line.trim();
+        if !file.is_empty() && !file.starts_with('#') {
+            paths.push(file.to_string());
+        }
+    }
+    Ok(paths)
+}
+
+/// Counts the k-mers of an input, and of its `mate` input of paired-end reads if given,
+/// writing the statistics of each record to `per_record` if given: as TSV, or as JSON lines flushed one by one
+fn count_input<T: Kmer, S: KmerHashing>(
+    counter: &KmerCounter,
+    input: Box<dyn BufRead>,
+    mate: Option<Box<dyn BufRead>>,
+    kmers: KmerStore<T, S>,
+    per_record: Option<&mut Box<dyn Write + Send>>,
+    json_lines: bool,
+) -> Result<KmerStats, KmerError> {
+    let Some(out) = per_record else {
+        return match mate {
+            Some(mate) => counter.count_reader_pair(input, mate, kmers),
+            None => counter.count_reader(input, kmers),
+        };
+    };
+    let mut written = Ok(());
+    let on_record = |record: RecordStats| {
+        if written.is_ok() && json_lines {
+            let line = json!({
+                "id": record.id,
+                "length": record.length,
+                "total_kmers": record.total_kmers,
+                "valid_kmers": record.valid_kmers,
+                "distinct_kmers": record.distinct.unwrap_or(0),
+            });
+            written = writeln!(out, "{}", line).and_then(|()| out.flush());
+        } else if written.is_ok() {
+            written = writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}",
+                record.id,
+                record.length,
+                record.total_kmers,
+                record.valid_kmers,
+                record.distinct.unwrap_or(0)
+            );
+        }
+    };
+    let stats = match mate {
+        Some(mate) => counter.count_reader_pair_with(input, mate, kmers, on_record)?,
+        None => counter.count_reader_with(input, kmers, on_record)?,
+    };
+    written?;
+    Ok(stats)
+}
+
+/// Name of the counted items in the report: k-mers, or their minimizers
+fn kmer_name(options: &Options) -> String {
+    kmer_name_of_size(options, options.k)
+}
+
+/// Same as `kmer_name`, for k-mers of size `k`
+fn kmer_name_of_size(options: &Options, k: usize) -> String {
+    let name = match options.minimizer {
+        Some(w) => format!("{}-mer minimizers (w = {})", k, w),
+        None => format!("{}-mers", k),
+    };
+    match options.ends_only {
+        Some(end) => format!("{} of the record ends ({} nucleotides)", name, end),
+        None => name,
+    }
+}
+
+/// Adds the estimated memory of the k-mer tables holding `entries` entries of `entry_bytes` bytes,
+/// if --report-memory is set
+fn add_table_memory(report: &mut Report, options: &Options, entries: usize, entry_bytes: usize) {
+    if !options.report_memory {
+        return;
+    }
+    let bytes = memory::table_bytes(entries, entry_bytes);
+    report.add_formatted(
+        "table_memory",
+        "Estimated k-mer table memory",
+        format!(
+            "{} ({} entries of {} bytes, plus hash table overhead; --reserve {} avoids resizing)",
+            memory::format_bytes(bytes),
+            entries,
+            entry_bytes,
+            entries
+        ),
+        bytes,
+    );
+}
+
+/// Least fraction of the possible k-mers found for the saturation to be reported
+const MIN_REPORTED_SATURATION: f64 = 0.01;
+
+/// Adds the number of distinct k-mers that can be counted, and the fraction of them found, if it is at least
+/// `MIN_REPORTED_SATURATION`: k is then too small for the distinct k-mers to tell inputs apart
+fn add_saturation(report: &mut Report, options: &Options, distinct: usize) {
+    // the k-mers of other alphabets are only counted on the forward strand
+    let max = if options.alphabet.is_dna() {
+        options.strand.max_kmers(options.k)
+    } else {
+        (options.alphabet.symbols().len() as u128).checked_pow(options.k as u32)
+    };
+    let Some(max) = max else {
+        return;
+    };
+    let saturation = distinct as f64 / max as f64;
+    if saturation < MIN_REPORTED_SATURATION {
+        return;
+    }
+    report.add("max_distinct_kmers", format!("Possible distinct {}", kmer_name(options)), max as u64);
+    report.add_formatted("saturation", "Saturation", format!("{:.2}%", 100.0 * saturation), saturation);
+}
+
+/// Adds the peak memory of the process, if the platform reports it
+fn add_peak_memory(report: &mut Report) {
+    match memory::peak_rss() {
+        Some(bytes) => report.add_formatted("peak_memory", "Peak memory", memory::format_bytes(bytes), bytes),
+        None => report.add_formatted("peak_memory", "Peak memory", "NA (not available on this platform)".to_string(), Value::Null),
+    }
+}
+
+/// Adds the GC content, computed over A, C, G, T nucleotides only
+fn add_gc(report: &mut Report, stats: &KmerStats) {
+    match stats.gc_content() {
+        Some(gc) => report.add_formatted(
+            "gc_content",
+            "GC content",
+            format!("{:.2}% (of {} A/C/G/T nucleotides, N and other ambiguous bases excluded)", 100.0 * gc, stats.acgt_nucleotides),
+            gc,
+        ),
+        None => report.add_formatted("gc_content", "GC content", "NA (no A/C/G/T nucleotide)".to_string(), Value::Null),
+    }
+}
+
+fn add_n_nucleotides(report: &mut Report, stats: &KmerStats) {
+    let fraction = if stats.total_nucleotides > 0 { stats.n_nucleotides as f64 / stats.total_nucleotides as f64 } else { 0.0 };
+    report.add_formatted(
+        "n_nucleotides",
+        "Ambiguous (N) bases",
+        format!("{} ({:.2}%)", stats.n_nucleotides, 100.0 * fraction),
+        stats.n_nucleotides,
+    );
+}
+
+/// Adds the fraction of valid k-mers
+fn add_validity_ratio(report: &mut Report, stats: &KmerStats) {
+    match stats.validity_ratio() {
+        Some(ratio) => report.add_formatted("validity_ratio", "K-mer validity ratio", format!("{:.2}% (valid k-mers of all the windows)", 100.0 * ratio), ratio),
+        None => report.add_formatted("validity_ratio", "K-mer validity ratio", "NA (no k-mer window)".to_string(), Value::Null),
+    }
+}
+
+/// Adds the nucleotide and k-mer counts, and the distinct count and its saturation if `distinct_label` is given
+fn add_stats(report: &mut Report, stats: &KmerStats, distinct_label: Option<String>, options: &Options) {
+    report.add("total_nucleotides", "Total nucleotides", stats.total_nucleotides);
+    add_n_nucleotides(report, stats);
+    if options.gc {
+        add_gc(report, stats);
+    }
+    report.add("total_kmers", "Total k-mers", stats.total_kmers);
+    report.add("valid_kmers", "Valid k-mers", stats.valid_kmers);
+    add_validity_ratio(report, stats);
+    report.add("n_kmers", "K-mers dropped for an N", stats.n_kmers);
+    report.add("other_invalid_kmers", "K-mers dropped for other reasons", stats.other_invalid_kmers());
+    report.add("short_records", "Records too short for any k-mer", stats.short_records);
+    match distinct_label {
+        Some(label) => {
+            report.add("distinct_kmers", label, stats.distinct);
+            add_saturation(report, options, stats.distinct);
+        }
+        None => report.add_json("distinct_kmers", Value::Null),
+    }
+}
+
+/// Writes k-mers to `path`, one per line, sorted if requested.
+/// The numeric order of the 2-bit encodings is the lexicographic order of the k-mers.
+fn write_kmer_set<T: Kmer>(kmers: impl Iterator<Item = T>, options: &Options, path: &str) -> io::Result<()> {
+    let out = create_kmer_output(path, options)?;
+    if options.sort {
+        let mut kmers: Vec<T> = kmers.collect();
+        kmers.sort_unstable();
+        write_kmer_iter(kmers.into_iter(), options.k, out)
+    } else {
+        write_kmer_iter(kmers, options.k, out)
+    }
+}
+
+/// Prints the estimated Jaccard index and Mash distance of the k-mer sets of two sketch files
+fn compare_sketches(paths: &[String], format: Format) -> io::Result<()> {
+    let sketches = paths
+        .iter()
+        .map(|path| {
+            Sketch::read(open_input(path, None)?).map_err(|e| input_error(path, e))
+        })
+        .collect::<io::Result<Vec<Sketch>>>()?;
+    let jaccard = sketches[0].jaccard(&sketches[1])?;
+    let distance = minhash::mash_distance(jaccard, sketches[0].k);
+    let mut report = Report::default();
+    report.add("k", "k", sketches[0].k);
+    report.add("sketch_size", "Sketch size", sketches[0].size.min(sketches[1].size));
+    report.add_formatted("jaccard", "Estimated Jaccard index", format!("{:.6}", jaccard), jaccard);
+    report.add_formatted("mash_distance", "Mash distance", format!("{:.6}", distance), distance);
+    report.print(format);
+    Ok(())
+}
+
+/// Checks that each input file opens, is in a recognized format and starts with a record of DNA,
+/// and prints its format and the length of its first record, without counting
+fn dry_run(options: &Options) -> io::Result<()> {
+    let mut report = Report::default();
+    for path in options.fasta_files.iter().chain(&options.pair) {
+        let record = open_input(path, None)
+            .and_then(preview_input)
+            .and_then(|preview| {
+                let format = preview.format.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "empty input"))?;
+                let record = preview.first_record.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no record"))?;
+                if let Some(&n) = record.seq().iter().find(|&&n| iupac_nucleotides(n.to_ascii_uppercase()).is_none()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("the first record is not DNA: it contains '{}'", n.escape_ascii()),
+                    ));
                 }
-            }
+                Ok((format, record))
+            })
+            .map_err(|e| input_error(path, e));
+        let (format, record) = record?;
+        let length = record.seq().len();
+        if length < options.k {
+            log::warn!("the first record of {} is shorter than k ({} nucleotides)", path, length);
+        }
+        let mut file_report = Report::default();
+        file_report.add("format", "Format", format.to_string());
+        file_report.add_json("first_record_id", String::from_utf8_lossy(record.id()).into_owned());
+        file_report.add_formatted(
+            "first_record_length",
+            "First record",
+            format!("{} ({} nucleotides)", String::from_utf8_lossy(record.id()), length),
+            length,
+        );
+        report.add_file(path, file_report);
+    }
+    report.print(options.format);
+    Ok(())
+}
 
-            nb_valid_kmers.fetch_add(local_valid_kmers, Ordering::Relaxed);
-            for kmer in local_kmers.iter() {
-                kmers.insert(*kmer);
-            }
-        });
+/// Loads the binary k-mer dumps of --input-binary, passing each k-mer to `insert`
+fn load_binary<T: Kmer>(options: &Options, insert: impl Fn(T), report: &mut Report) -> io::Result<()> {
+    if options.input_binary.is_empty() {
+        return Ok(());
+    }
+    let mut loaded = 0;
+    for path in &options.input_binary {
+        // binary files may be compressed too
+        loaded += open_input(path, None)
+            .and_then(|input| read_binary_with(input, options.k, options.strand, &insert))
+            .map_err(|e| input_error(path, e))?;
+    }
+    report.add("loaded_kmers", "K-mers loaded from binary files", loaded);
+    Ok(())
+}
+
+/// Writes the `len` distinct k-mers of the input files, iterated by `kmers`, to --output or --output-binary
+fn write_set_outputs<T: Kmer, I: Iterator<Item = T>>(options: &Options, len: usize, kmers: impl Fn() -> I, write_text: bool) -> io::Result<()> {
+    if let (Some(path), true) = (&options.output, write_text) {
+        write_kmer_set(kmers(), options, path)?;
+    }
+    if let Some(path) = &options.output_binary {
+        write_binary_iter(len as u64, kmers(), options.k, options.strand, create_output(path, options.compression)?)?;
+    }
+    Ok(())
+}
 
-    Ok((
-        kmers.len(),
-        total_nucleotides.load(Ordering::Relaxed),
-        nb_total_kmers.load(Ordering::Relaxed),
-        nb_valid_kmers.load(Ordering::Relaxed),
-    ))
+/// Counts the k-mers of a file ('-' for stdin), together with its `mate` file of paired-end reads if given,
+/// with a progress bar of the first one if requested.
+/// Errors are prefixed with the path.
+fn count_path<T: Kmer, S: KmerHashing>(
+    counter: &KmerCounter,
+    options: &Options,
+    path: &str,
+    mate: Option<&str>,
+    kmers: KmerStore<T, S>,
+    per_record: Option<&mut Box<dyn Write + Send>>,
+) -> io::Result<KmerStats> {
+    match mate {
+        Some(mate) => log::info!("Reading {} paired with {}", path, mate),
+        None => log::info!("Reading {}", path),
+    }
+    let progress = options.progress.then(|| progress_bar(path)).transpose()?;
+    let stats = open_sequences(path, progress.as_ref()).map_err(KmerError::from).and_then(|input| {
+        let mate = mate.map(|mate| open_sequences(mate, None).map_err(|e| input_error(mate, e))).transpose()?;
+        count_input(counter, input, mate, kmers, per_record, options.format == Format::JsonLines)
+    });
+    if let Some(progress) = progress {
+        progress.finish_and_clear();
+    }
+    let stats = stats.map_err(|e| count_error(path, e))?;
+    log_file_stats(path, &stats);
+    Ok(stats)
 }
 
+/// Logs the records and k-mers read from a file
+fn log_file_stats(path: &str, stats: &KmerStats) {
+    log::info!("Processed {} records of {}", stats.records, path);
+    log::debug!(
+        "{}: {} nucleotides, {} k-mers, {} valid",
+        path,
+        stats.total_nucleotides,
+        stats.total_kmers,
+        stats.valid_kmers
+    );
+}
 
+/// Warns when nucleotides were read but none of them made a valid k-mer, which fails with --strict
+fn check_valid_kmers(options: &Options, k: usize, stats: &KmerStats) -> io::Result<()> {
+    if stats.valid_kmers > 0 || stats.total_nucleotides == 0 {
+        return Ok(());
+    }
+    let reason = if stats.total_kmers == 0 {
+        format!("k ({}) is larger than every sequence ({} records): check -k", k, stats.short_records)
+    } else {
+        "the input may not be DNA: only A, C, G and T (in any case, unless --case-sensitive) make valid k-mers".to_string()
+    };
+    if options.strict {
+        return Err(failure(Exit::NoValidKmer, io::ErrorKind::InvalidData, format!("no valid k-mer found, {}", reason)));
+    }
+    log::warn!("no valid k-mer found, {}", reason);
+    Ok(())
+}
 
-fn process_fasta_parallel_only_count(filename: &str, k: usize, max_threads: usize) -> io::Result<(usize, usize, usize)> {
-    ThreadPoolBuilder::new().num_threads(max_threads).build_global().unwrap();
-    let reader = initialize_reader(&filename).unwrap();
+/// Warns about the records left out by the length filters or malformed, and reports the latter with --skip-invalid
+/// and the duplicates with --dedup-records, and the records counted with --id-regex
+fn report_skipped_records(options: &Options, stats: &KmerStats, report: &mut Report) {
+    if stats.skipped_records > 0 {
+        log::warn!("skipped {} records outside the length range", stats.skipped_records);
+    }
+    if stats.invalid_records > 0 {
+        log::warn!("skipped {} malformed records", stats.invalid_records);
+    }
+    if options.skip_invalid {
+        report.add("invalid_records", "Malformed records skipped", stats.invalid_records);
+    }
+    if options.dedup_records {
+        report.add("duplicate_records", "Duplicate records removed", stats.duplicate_records);
+    }
+    if options.id_regex {
+        let matched = stats.records - stats.invalid_records - stats.unmatched_records;
+        report.add("matched_records", "Records matching the id pattern", matched);
+    }
+}
 
-    let total_nucleotides = Arc::new(AtomicUsize::new(0));
-    let nb_total_kmers = Arc::new(AtomicUsize::new(0));
-    let nb_valid_kmers = Arc::new(AtomicUsize::new(0));
-    
-    reader.for_each(|record|{ 
-            let seq = record.seq();
-
-            let mut local_valid_kmers = 0;
-
-            nb_total_kmers.fetch_add(seq.len().saturating_sub(k) + 1, Ordering::Relaxed);
-            total_nucleotides.fetch_add(seq.len(), Ordering::Relaxed);
-            
-            for window in seq.windows(k) {
-                if !window.contains(&b'N') {
-                    if let Some(_) = kmer_to_u64(window) {
-                        local_valid_kmers += 1;
+/// Reports the most frequent k-mers of --top, with their numbers of occurrences
+fn add_top_kmers<T: Kmer>(report: &mut Report, options: &Options, top: &[(T, u64)]) {
+    for (i, &(kmer, count)) in top.iter().enumerate() {
+        report.add_text(format!("Top {} #{}", kmer_name(options), i + 1), format!("{} ({} occurrences)", decode(kmer, options), count));
+    }
+    let top: Vec<Value> = top.iter().map(|&(kmer, count)| json!({"kmer": decode(kmer, options), "count": count})).collect();
+    report.add_json("top_kmers", top);
+}
+
+/// Reports the coverage peak of the k-mer `histogram` and the genome size estimated from it,
+/// the number of k-mer `occurrences` divided by the coverage
+fn add_genome_size(report: &mut Report, histogram: &[u64], occurrences: u64) {
+    let Some(peak) = coverage_peak(histogram) else {
+        log::warn!("no coverage peak found in the k-mer histogram, the genome size cannot be estimated");
+        report.add_formatted("coverage_peak", "Coverage peak", "NA (no peak after the errors)".to_string(), Value::Null);
+        report.add_formatted("estimated_genome_size", "Estimated genome size (rough)", "NA".to_string(), Value::Null);
+        return;
+    };
+    report.add("coverage_peak", "Coverage peak", peak);
+    report.add("estimated_genome_size", "Estimated genome size (rough: k-mers / coverage peak)", occurrences / peak as u64);
+}
+
+/// Flag of `KmerCounter::interrupt`, set by the first Ctrl-C
+static INTERRUPT: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+/// SIGINT handler: the first Ctrl-C stops reading, so that the counts so far are printed, the second one quits.
+/// Only async-signal-safe calls are made: an atomic swap, write and _exit.
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    if INTERRUPT.get().is_some_and(|interrupt| !interrupt.swap(true, Ordering::Relaxed)) {
+        let message = b"Interrupted: counting stops after the records read, Ctrl-C again to quit\n";
+        // SAFETY: write and _exit are async-signal-safe
+        unsafe {
+            libc::write(libc::STDERR_FILENO, message.as_ptr().cast(), message.len());
+        }
+    } else {
+        unsafe { libc::_exit(Exit::Interrupted as libc::c_int) }
+    }
+}
+
+/// Installs the Ctrl-C handler, returning the flag it sets
+fn handle_interrupt() -> Option<Arc<AtomicBool>> {
+    let interrupt = INTERRUPT.get_or_init(|| Arc::new(AtomicBool::new(false)));
+    #[cfg(unix)]
+    // SAFETY: the handler only makes async-signal-safe calls
+    unsafe {
+        libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+    cfg!(unix).then(|| Arc::clone(interrupt))
+}
+
+/// Whether counting stopped because the k-mer table reached --memory-limit
+fn memory_limit_reached(options: &Options, stats: &KmerStats) -> bool {
+    stats.limit_reached && options.memory_cap.is_some_and(|(_, entries)| stats.distinct >= entries)
+}
+
+/// Reports that counting was stopped by Ctrl-C, if it was
+fn report_interrupted(stats: &KmerStats, report: &mut Report) {
+    if stats.interrupted {
+        report.add_formatted("interrupted", "Interrupted", "yes, the counts are those of the beginning of the input".to_string(), true);
+    }
+}
+
+/// Reports the statistics of the record lengths with --length-stats
+fn report_length_stats(counter: &KmerCounter, report: &mut Report) {
+    if let Some(lengths) = counter.length_stats() {
+        report.add_formatted(
+            "record_lengths",
+            "Record lengths",
+            format!("min {}, max {}, mean {:.1}, N50 {}", lengths.min, lengths.max, lengths.mean, lengths.n50),
+            json!({"min": lengths.min, "max": lengths.max, "mean": lengths.mean, "n50": lengths.n50}),
+        );
+    }
+}
+
+/// Writes the snapshots of --snapshot-interval to --snapshot-output: the megabases counted and the distinct k-mers then
+fn write_snapshots(counter: &KmerCounter, options: &Options) -> io::Result<()> {
+    if let (Some(path), Some(snapshots)) = (&options.snapshot_output, counter.snapshots()) {
+        let mut out = create_output(path, options.compression)?;
+        for (nucleotides, distinct) in snapshots {
+            writeln!(out, "{}\t{}", nucleotides as f64 / 1e6, distinct)?;
+        }
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Writes a checkpoint to `path` through a temporary file, so that a crash never leaves it incomplete
+fn write_checkpoint<T: Kmer, S: KmerHashing>(options: &Options, path: &str, kmers: &ShardedSet<T, S>, checkpoint: &Checkpoint) -> io::Result<()> {
+    let temporary = format!("{}.tmp", path);
+    let out = BufWriter::new(File::create(&temporary)?);
+    checkpoint.write(kmers.len() as u64, kmers.iter(), options.k, options.strand, out)?;
+    std::fs::rename(&temporary, path)?;
+    log::info!("Checkpoint after {} files written to {}", checkpoint.files.len(), path);
+    Ok(())
+}
+
+/// Writes the distinct k-mers back to the file they were loaded from with --save, through a temporary file
+/// compressed alike, so that a crash never leaves the set incomplete
+fn save_set<T: Kmer, S: KmerHashing>(options: &Options, kmers: &ShardedSet<T, S>) -> io::Result<()> {
+    let path = &options.input_binary[0];
+    let temporary = match path.strip_suffix(".gz") {
+        Some(stem) => format!("{}.tmp.gz", stem),
+        None => format!("{}.tmp", path),
+    };
+    write_binary_iter(kmers.len() as u64, kmers.iter(), options.k, options.strand, create_output(&temporary, options.compression)?)?;
+    std::fs::rename(&temporary, path)?;
+    log::info!("{} distinct k-mers saved to {}", kmers.len(), path);
+    Ok(())
+}
+
+/// Counts the input files into the same store on `threads` threads, each file by a single thread.
+/// Returns the statistics of each file, in order.
+fn count_files_parallel<T: Kmer, S: KmerHashing>(counter: &KmerCounter, options: &Options, threads: usize, kmers: KmerStore<T, S>) -> io::Result<Vec<KmerStats>> {
+    let file_counter = counter.clone().threads(1);
+    let files = &options.fasta_files;
+    let next = AtomicUsize::new(0);
+    // Plain threads rather than a rayon pool: a rayon thread waiting for the pool of a count could start counting
+    // another file meanwhile, on top of its stack. Each thread takes the next file until none is left, or until
+    // an error stops them all.
+    let mut counted: Vec<(usize, io::Result<KmerStats>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(files.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut counted = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= files.len() {
+                            return counted;
+                        }
+                        let stats = count_path(&file_counter, options, &files[i], options.pair.get(i).map(String::as_str), kmers, None);
+                        if stats.is_err() {
+                            next.store(files.len(), Ordering::Relaxed);
+                        }
+                        counted.push((i, stats));
                     }
-                }
+                })
+            })
+            .collect();
+        workers.into_iter().flat_map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))).collect()
+    });
+    counted.sort_unstable_by_key(|(i, _)| *i);
+    counted.into_iter().map(|(_, stats)| stats).collect()
+}
+
+/// Counts the input file `i` into a set of its own, then adds its k-mers to `set`. Keeps in `core` the k-mers found
+/// in every file counted so far. Returns the statistics of the file, with its own distinct k-mers.
+fn count_own_set<T: Kmer, S: KmerHashing>(
+    counter: &KmerCounter,
+    options: &Options,
+    i: usize,
+    set: &ShardedSet<T, S>,
+    core: &mut Option<Vec<T>>,
+    per_record: Option<&mut Box<dyn Write + Send>>,
+) -> io::Result<KmerStats> {
+    let file_set = ShardedSet::with_capacity_and_hasher(options.shards, options.k, options.reserve_size, set.hasher());
+    let (path, mate) = (&options.fasta_files[i], options.pair.get(i).map(String::as_str));
+    let stats = count_path(counter, options, path, mate, KmerStore::Sharded(&file_set), per_record)?;
+    file_set.iter().for_each(|kmer| {
+        set.insert(kmer);
+    });
+    match core {
+        Some(core) => core.retain(|kmer| file_set.contains(kmer)),
+        None => *core = Some(file_set.iter().collect()),
+    }
+    Ok(stats)
+}
+
+/// Counts the k-mers of all input files into the same store.
+/// With --resume, the store starts with the k-mers of the checkpoint and the files it covers are not read again;
+/// with --checkpoint, a checkpoint is written after each file.
+/// With several files, adds the stats of each of them to the report; returns the combined stats.
+/// With --per-file-distinct, also reports the distinct k-mers of each file and those of all the files.
+fn count_files<T: Kmer, S: KmerHashing>(counter: &KmerCounter, options: &Options, kmers: KmerStore<T, S>, report: &mut Report) -> io::Result<KmerStats> {
+    let several_files = options.fasta_files.len() > 1;
+    let start = Instant::now();
+    let mut combined = KmerStats::default();
+    let mut per_record = options.per_record.as_deref().map(|path| create_output(path, options.compression)).transpose()?;
+    if let (Some(out), false) = (per_record.as_mut(), options.format == Format::JsonLines) {
+        writeln!(out, "record_id\tlength\ttotal_kmers\tvalid_kmers\tdistinct_kmers")?;
+    }
+    let mut resumed = 0;
+    if let (Some(path), KmerStore::Sharded(set)) = (&options.resume, kmers) {
+        let checkpoint = Checkpoint::read(open_input(path, None)?, options.k, options.strand, |kmer| {
+            set.insert(kmer);
+        })
+        .map_err(|e| input_error(path, e))?;
+        if !options.fasta_files.starts_with(&checkpoint.files) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: checkpoint of {}, not the first input files", path, checkpoint.files.join(", ")),
+            ));
+        }
+        resumed = checkpoint.files.len();
+        combined = checkpoint.stats;
+        report.add("resumed_files", "Files counted before resuming", resumed);
+    }
+    // with --file-parallel, the files are all counted first, in parallel, then reported in order
+    let mut counted = match options.file_parallel {
+        Some(threads) => count_files_parallel(counter, options, threads, kmers)?,
+        None => Vec::new(),
+    }
+    .into_iter();
+    // k-mers of every file so far, with --per-file-distinct
+    let mut core = None;
+    for (i, fasta_file) in options.fasta_files.iter().enumerate().skip(resumed) {
+        if combined.limit_reached || combined.interrupted {
+            break;
+        }
+        let distinct_before = kmers.len();
+        let stats = match counted.next() {
+            Some(stats) => stats,
+            None => match (options.per_file_distinct, kmers) {
+                (true, KmerStore::Sharded(set)) => count_own_set(counter, options, i, set, &mut core, per_record.as_mut())?,
+                _ => count_path(counter, options, fasta_file, options.pair.get(i).map(String::as_str), kmers, per_record.as_mut())?,
+            },
+        };
+        if several_files {
+            let mut file_report = Report::default();
+            file_report.add("total_nucleotides", "Total nucleotides", stats.total_nucleotides);
+            if options.gc {
+                add_gc(&mut file_report, &stats);
+            }
+            file_report.add("total_kmers", "Total k-mers", stats.total_kmers);
+            file_report.add("valid_kmers", "Valid k-mers", stats.valid_kmers);
+            if options.per_file_distinct {
+                file_report.add("distinct_kmers", format!("Distinct {}", kmer_name(options)), stats.distinct);
+            }
+            // the k-mers on disk are only counted by merging them at the end
+            if !options.only_count && options.disk.is_none() && options.file_parallel.is_none() {
+                file_report.add(
+                    "new_distinct_kmers",
+                    format!("New distinct {}", kmer_name(options)),
+                    kmers.len() - distinct_before,
+                );
+            }
+            report.add_file(fasta_file, file_report);
+        }
+        combined.accumulate(&stats);
+        // the checkpoint covers whole files only
+        if let (Some(path), KmerStore::Sharded(set), false) = (&options.checkpoint, kmers, stats.interrupted) {
+            let checkpoint = Checkpoint { files: options.fasta_files[..=i].to_vec(), stats: combined };
+            write_checkpoint(options, path, set, &checkpoint)?;
+        }
+    }
+    combined.distinct = kmers.len();
+    if let Some(core) = core {
+        report.add("core_kmers", format!("Distinct {} shared by all the files", kmer_name(options)), core.len());
+    }
+    if options.stride > 1 {
+        report.add_formatted(
+            "stride",
+            "Stride",
+            format!("{} (one window in {} is read, the distinct k-mers are a sample of those of the input)", options.stride, options.stride),
+            options.stride,
+        );
+    }
+    if let Some(fraction) = options.sample {
+        report.add_formatted(
+            "sample_fraction",
+            "Sampled records",
+            format!("{}% (the counts are those of the sample, not of the whole input)", 100.0 * fraction),
+            fraction,
+        );
+    }
+    if memory_limit_reached(options, &combined) {
+        let (limit, _) = options.memory_cap.unwrap();
+        report.add_formatted(
+            "memory_limit_reached",
+            "Stopped at the memory limit",
+            format!("yes ({}), the counts are those of part of the input", memory::format_bytes(limit)),
+            true,
+        );
+    } else if options.memory_cap.is_some() {
+        report.add_json("memory_limit_reached", false);
+    }
+    if combined.limit_reached && !memory_limit_reached(options, &combined) {
+        report.add_formatted(
+            "max_distinct_reached",
+            "Stopped at the maximum number of distinct k-mers",
+            "yes, the counts are those of a sample of the input".to_string(),
+            true,
+        );
+    } else if options.max_distinct.is_some() {
+        report.add_json("max_distinct_reached", false);
+    }
+    report_interrupted(&combined, report);
+    check_valid_kmers(options, options.k, &combined)?;
+    if options.exclude {
+        report.add("excluded_kmers", "Excluded k-mer occurrences", combined.excluded_kmers);
+    }
+    if options.low_complexity {
+        report.add("low_complexity_kmers", "Low-complexity k-mers", combined.low_complexity_kmers);
+    }
+    report_skipped_records(options, &combined, report);
+    report_length_stats(counter, report);
+    write_snapshots(counter, options)?;
+    if let Some(mut out) = per_record {
+        out.flush()?;
+    }
+    if options.timing {
+        let seconds = start.elapsed().as_secs_f64();
+        eprintln!("Counting time: {:.3} s", seconds);
+        eprintln!("Throughput: {:.2} Mbp/s", combined.total_nucleotides as f64 / 1e6 / seconds);
+    }
+    Ok(combined)
+}
+
+/// Writes the number of occurrences of each target k-mer of --include, in the order of the file, to --output
+/// or stdout. Adds the number of targets found to the report.
+fn write_targets<T: Kmer, S: KmerHashing>(options: &Options, counts: &DashMap<T, u64, S>, targets: &[u128], report: &mut Report) -> io::Result<()> {
+    let counts: HashMap<u128, u64> = counts.iter().map(|entry| ((*entry.key()).into(), *entry.value())).collect();
+    let mut out = create_output(options.output.as_deref().unwrap_or("-"), options.compression)?;
+    let mut found = 0;
+    for &target in targets {
+        let counted = match options.strand {
+            Strand::Canonical => target.canonical(options.k),
+            _ => target,
+        };
+        let count = counts.get(&counted).copied().unwrap_or(0);
+        found += (count > 0) as usize;
+        writeln!(out, "{}\t{}", target.decode(options.k), count)?;
+    }
+    out.flush()?;
+    report.add("targets", "Target k-mers", targets.len());
+    report.add("found_targets", "Target k-mers found", found);
+    Ok(())
+}
+
+/// Sizes of a value of --kmer-sizes: a k, or a range START:END:STEP (STEP 1 if left out) of k up to END included
+fn parse_kmer_sizes(sizes: &str) -> Option<Vec<usize>> {
+    let bounds: Vec<usize> = sizes.split(':').map(|bound| bound.parse().ok()).collect::<Option<_>>()?;
+    match bounds[..] {
+        [k] => Some(vec![k]),
+        [start, end] => (start <= end).then(|| (start..=end).collect()),
+        [start, end, step] => (start <= end && step > 0).then(|| (start..=end).step_by(step).collect()),
+        _ => None,
+    }
+}
+
+/// Counts the distinct k-mers of all input files for each of --kmer-sizes, reading the input once.
+/// Adds the number of distinct k-mers per k to the report. Returns whether counting was interrupted.
+fn count_kmer_sizes<T: Kmer, S: KmerHashing>(counter: &KmerCounter, options: &Options, hasher: S, report: &mut Report) -> io::Result<bool> {
+    let start = Instant::now();
+    // one set per k: the memory adds up
+    let sets: Vec<ShardedSet<T, S>> = options
+        .kmer_sizes
+        .iter()
+        .map(|&k| ShardedSet::with_capacity_and_hasher(options.shards, k, options.reserve_size, hasher.clone()))
+        .collect();
+    let stores: Vec<KmerStore<T, S>> = sets.iter().map(KmerStore::Sharded).collect();
+    let mut combined = vec![KmerStats::default(); stores.len()];
+    for path in &options.fasta_files {
+        if combined[0].interrupted {
+            break;
+        }
+        log::info!("Reading {}", path);
+        let progress = options.progress.then(|| progress_bar(path)).transpose()?;
+        let stats = open_sequences(path, progress.as_ref())
+            .map_err(KmerError::from)
+            .and_then(|input| counter.count_reader_ks(input, &options.kmer_sizes, &stores));
+        if let Some(progress) = progress {
+            progress.finish_and_clear();
+        }
+        let stats = stats.map_err(|e| count_error(path, e))?;
+        log_file_stats(path, &stats[0]);
+        combined.iter_mut().zip(&stats).for_each(|(combined, stats)| combined.accumulate(stats));
+    }
+    for (&k, stats) in options.kmer_sizes.iter().zip(&combined) {
+        check_valid_kmers(options, k, stats)?;
+    }
+    if options.timing {
+        let seconds = start.elapsed().as_secs_f64();
+        eprintln!("Counting time: {:.3} s", seconds);
+        eprintln!("Throughput: {:.2} Mbp/s", combined[0].total_nucleotides as f64 / 1e6 / seconds);
+    }
+
+    report_interrupted(&combined[0], report);
+    report_skipped_records(options, &combined[0], report);
+    report_length_stats(counter, report);
+    report.add("total_nucleotides", "Total nucleotides", combined[0].total_nucleotides);
+    add_n_nucleotides(report, &combined[0]);
+    if options.gc {
+        add_gc(report, &combined[0]);
+    }
+    let mut per_k = Vec::new();
+    for ((&k, set), stats) in options.kmer_sizes.iter().zip(&sets).zip(&combined) {
+        if options.low_complexity {
+            report.add_text(format!("Low-complexity {}", kmer_name_of_size(options, k)), stats.low_complexity_kmers);
+        }
+        report.add_text(format!("Number of distinct {}", kmer_name_of_size(options, k)), set.len());
+        let mut counts = json!({
+            "k": k,
+            "total_kmers": stats.total_kmers,
+            "valid_kmers": stats.valid_kmers,
+            "validity_ratio": stats.validity_ratio(),
+            "n_kmers": stats.n_kmers,
+            "other_invalid_kmers": stats.other_invalid_kmers(),
+            "short_records": stats.short_records,
+            "distinct_kmers": set.len(),
+        });
+        if options.low_complexity {
+            counts["low_complexity_kmers"] = json!(stats.low_complexity_kmers);
+        }
+        per_k.push(counts);
+    }
+    report.add_json("kmer_sizes", per_k);
+    if let Some(path) = &options.output {
+        let mut out = create_output(path, options.compression)?;
+        writeln!(out, "k\ttotal_kmers\tvalid_kmers\tdistinct_kmers")?;
+        for ((&k, set), stats) in options.kmer_sizes.iter().zip(&sets).zip(&combined) {
+            writeln!(out, "{}\t{}\t{}\t{}", k, stats.total_kmers, stats.valid_kmers, set.len())?;
+        }
+        out.flush()?;
+    }
+    add_table_memory(report, options, sets.iter().map(ShardedSet::len).sum(), T::BYTES);
+    Ok(combined[0].interrupted)
+}
+
+/// Counts the distinct k-mers of the input files with 1, 2, 4... threads up to `max_threads`, and prints the time
+/// of each count and its speedup over one thread. The files are decompressed into memory first, so that only
+/// the parsing and counting are timed, not reading them.
+fn benchmark<T: Kmer, S: KmerHashing>(counter: &KmerCounter, options: &Options, hasher: S, max_threads: usize) -> io::Result<()> {
+    let inputs = options
+        .fasta_files
+        .iter()
+        .map(|path| {
+            let mut input = Vec::new();
+            open_sequences(path, None)?.read_to_end(&mut input).map_err(|e| input_error(path, e))?;
+            Ok(input)
+        })
+        .collect::<io::Result<Vec<Vec<u8>>>>()?;
+    let mut thread_counts: Vec<usize> = (0..).map(|i| 1 << i).take_while(|&threads| threads < max_threads).collect();
+    thread_counts.push(max_threads);
+
+    let mut report = Report::default();
+    report.add_json("k", options.k);
+    report.add("strand", "Strand", options.strand.name());
+    let mut rounds = Vec::new();
+    let mut single_thread = None;
+    let mut distinct = 0;
+    for threads in thread_counts {
+        let counter = counter.clone().threads(threads);
+        let kmers = ShardedSet::<T, S>::with_capacity_and_hasher(options.shards, options.k, options.reserve_size, hasher.clone());
+        let start = Instant::now();
+        for (input, path) in inputs.iter().zip(&options.fasta_files) {
+            counter.count_reader(&input[..], KmerStore::Sharded(&kmers)).map_err(|e| count_error(path, e))?;
+        }
+        let seconds = start.elapsed().as_secs_f64();
+        distinct = kmers.len();
+        let speedup = *single_thread.get_or_insert(seconds) / seconds;
+        report.add_text(format!("{} thread{}", threads, if threads > 1 { "s" } else { "" }), format!("{:.3} s, speedup {:.2}", seconds, speedup));
+        rounds.push(json!({"threads": threads, "seconds": seconds, "speedup": speedup}));
+    }
+    report.add("distinct_kmers", format!("Number of distinct {}", kmer_name(options)), distinct);
+    report.add_json("benchmark", rounds);
+    report.print(options.format);
+    Ok(())
+}
+
+/// Counts the k-mers of the input, encoded with `T`, and prints the results
+fn run<T: Kmer, S: KmerHashing>(counter: &KmerCounter, options: &Options, hasher: S) -> io::Result<()> {
+    if let Some(max_threads) = options.benchmark {
+        return benchmark::<T, S>(counter, options, hasher, max_threads);
+    }
+    let k = options.k;
+    let mut report = Report::default();
+    // with several k, each of them comes with its counts
+    report.add_json("k", if options.kmer_sizes.is_empty() { Value::from(k) } else { Value::Null });
+    report.add_json("canonical", options.strand == Strand::Canonical);
+    report.add("strand", "Strand", options.strand.name());
+
+    let mut interrupted = false;
+    let stats = if !options.kmer_sizes.is_empty() {
+        interrupted = count_kmer_sizes::<T, S>(counter, options, hasher, &mut report)?;
+        None
+    } else if options.only_count {
+        let stats = count_files::<T, S>(counter, options, KmerStore::None, &mut report)?;
+        add_stats(&mut report, &stats, None, options);
+        Some(stats)
+    } else if let Some(precision) = options.hll_precision {
+        let hll = HyperLogLog::new(precision);
+        let stats = count_files::<T, S>(counter, options, KmerStore::Hll(&hll), &mut report)?;
+        add_stats(&mut report, &stats, Some(format!("Number of distinct {} (approximate)", kmer_name(options))), options);
+        report.add_formatted(
+            "distinct_relative_error",
+            "Expected relative error",
+            format!("{:.2}%", 100.0 * hll.relative_error()),
+            hll.relative_error(),
+        );
+        Some(stats)
+    } else if let Some((size, num_hashes)) = options.bloom {
+        let bloom = BloomFilter::new(size, num_hashes);
+        let stats = count_files::<T, S>(counter, options, KmerStore::Bloom(&bloom), &mut report)?;
+        add_stats(&mut report, &stats, Some(format!("Number of distinct {} (Bloom filter, lower bound)", kmer_name(options))), options);
+        report.add_formatted(
+            "bloom_false_positive_rate",
+            "Bloom filter false positive rate",
+            format!("{:.3e}", bloom.false_positive_rate()),
+            bloom.false_positive_rate(),
+        );
+        if bloom.fill_ratio() > 0.5 {
+            log::warn!(
+                "the Bloom filter is {:.0}% full, the distinct count is likely underestimated. \
+                Consider a larger --bloom-size",
+                100.0 * bloom.fill_ratio()
+            );
+        }
+        if let Some(path) = &options.bloom_output {
+            bloom.write(k, options.strand, create_output(path, options.compression)?)?;
+        }
+        Some(stats)
+    } else if let Some(path) = &options.query {
+        // Bloom files may be compressed too
+        let bloom = open_input(path, None)
+            .and_then(|input| BloomFilter::read(input, k, options.strand))
+            .map_err(|e| input_error(path, e))?;
+        let kmers = ShardedSet::<T, S>::with_capacity_and_hasher(options.shards, k, options.reserve_size, hasher.clone());
+        let stats = count_files(counter, options, KmerStore::Sharded(&kmers), &mut report)?;
+        add_stats(&mut report, &stats, Some(format!("Number of distinct {}", kmer_name(options))), options);
+        let found = kmers.iter().filter(|kmer| bloom.contains_hash(kmer.hash64())).count();
+        let fraction = if stats.distinct == 0 { 0.0 } else { found as f64 / stats.distinct as f64 };
+        report.add("query_found_kmers", "Distinct k-mers found in the Bloom filter", found);
+        report.add_formatted("query_fraction", "Fraction found in the Bloom filter", format!("{:.4}", fraction), fraction);
+        report.add_formatted(
+            "bloom_false_positive_rate",
+            "Bloom filter false positive rate",
+            format!("{:.3e}", bloom.false_positive_rate()),
+            bloom.false_positive_rate(),
+        );
+        Some(stats)
+    } else if let Some(size) = options.sketch {
+        let minhash = MinHash::new(size);
+        let stats = count_files::<T, S>(counter, options, KmerStore::Sketch(&minhash), &mut report)?;
+        if let Some(path) = &options.output {
+            let sketch = Sketch { k, strand: options.strand, size, hashes: minhash.hashes() };
+            sketch.write(create_output(path, options.compression)?)?;
+        }
+        add_stats(&mut report, &stats, Some(format!("Number of distinct {} (estimated from the sketch)", kmer_name(options))), options);
+        Some(stats)
+    } else if let Some((dir, memory_limit)) = &options.disk {
+        let kmers = ExternalSet::<T>::new(dir, *memory_limit, options.shards)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", dir, e)))?;
+        let mut stats = count_files::<T, S>(counter, options, KmerStore::External(&kmers), &mut report)?;
+        // the first write error is kept, to be reported once merging is done
+        let mut out = options.output.as_deref().map(|path| create_kmer_output(path, options)).transpose()?;
+        let mut written = Ok(());
+        stats.distinct = kmers.merge(|kmer| {
+            if let (Some(out), true) = (out.as_mut(), written.is_ok()) {
+                written = writeln!(out, "{}", kmer.decode(k));
             }
+        })?;
+        written?;
+        if let Some(mut out) = out {
+            out.flush()?;
+        }
+        add_stats(&mut report, &stats, Some(format!("Number of distinct {}", kmer_name(options))), options);
+        report.add("disk_runs", "Sorted runs written to disk", kmers.runs());
+        Some(stats)
+    } else if let Some(other) = &options.compare {
+        let kmers = DashSet::<T, S>::with_capacity_and_hasher(options.reserve_size, hasher.clone());
+        load_binary(options, |kmer| {
+            kmers.insert(kmer);
+        }, &mut report)?;
+        let stats = count_files(counter, options, KmerStore::Set(&kmers), &mut report)?;
+        write_set_outputs(options, kmers.len(), || kmers.iter().map(|kmer| *kmer), options.set_op.is_none())?;
+        add_stats(&mut report, &stats, Some(format!("Number of distinct {}", kmer_name(options))), options);
+        add_table_memory(&mut report, options, stats.distinct, T::BYTES);
 
-            nb_valid_kmers.fetch_add(local_valid_kmers, Ordering::Relaxed);
-            
+        // the k-mers of the other file are not all stored: only those absent from the input files are
+        let shared = DashSet::<T, S>::with_hasher(hasher.clone());
+        let others = DashSet::<T, S>::with_hasher(hasher.clone());
+        let compare_store = KmerStore::Compare { reference: &kmers, shared: &shared, others: &others };
+        let other_stats = count_path(counter, options, other, None, compare_store, None)?;
+        if let (Some(path), Some(set_op)) = (&options.output, options.set_op) {
+            match set_op {
+                SetOp::Intersection => write_kmer_set(shared.iter().map(|kmer| *kmer), options, path)?,
+                SetOp::Union => write_kmer_set(kmers.iter().chain(others.iter()).map(|kmer| *kmer), options, path)?,
+                SetOp::AOnly => {
+                    let a_only = kmers.iter().map(|kmer| *kmer).filter(|kmer| !shared.contains(kmer));
+                    write_kmer_set(a_only, options, path)?
+                }
+                SetOp::BOnly => write_kmer_set(others.iter().map(|kmer| *kmer), options, path)?,
+            }
+        }
+        let union = kmers.len() + others.len();
+        report.add("compared_distinct_kmers", format!("Number of distinct {} of {}", kmer_name(options), other), other_stats.distinct);
+        report.add("intersection", "Intersection", shared.len());
+        report.add("union", "Union", union);
+        if union > 0 {
+            let jaccard = shared.len() as f64 / union as f64;
+            report.add_formatted("jaccard", "Jaccard index", format!("{:.6}", jaccard), jaccard);
+        } else {
+            report.add_formatted("jaccard", "Jaccard index", "NA (no k-mer)".to_string(), Value::Null);
+        }
+        Some(stats)
+    } else if let Some(path) = &options.membership {
+        let memberships = DashMap::<T, u64, S>::with_capacity_and_hasher(options.reserve_size, hasher.clone());
+        let stats = count_files(counter, options, KmerStore::Membership(&memberships), &mut report)?;
+        let records = stats.records - stats.invalid_records;
+        let out = create_kmer_output(path, options)?;
+        let entries = memberships.iter().map(|entry| (*entry.key(), *entry.value()));
+        if options.sort {
+            let mut entries: Vec<(T, u64)> = entries.collect();
+            entries.sort_unstable();
+            write_kmer_membership_iter(entries.into_iter(), k, records, out)?;
+        } else {
+            write_kmer_membership_iter(entries, k, records, out)?;
+        }
+        add_stats(&mut report, &stats, Some(format!("Number of distinct {}", kmer_name(options))), options);
+        report.add("membership_records", "Records of the membership bitvectors", records);
+        add_table_memory(&mut report, options, stats.distinct, T::BYTES + size_of::<u64>());
+        Some(stats)
+    } else if options.frequencies {
+        let counts = DashMap::<T, u64, S>::with_capacity_and_hasher(options.reserve_size, hasher.clone());
+        let stats = count_files(counter, options, KmerStore::Map(&counts), &mut report)?;
+        let min_count = options.min_count.unwrap_or(0);
+        if let Some(targets) = &options.include {
+            write_targets(options, &counts, targets, &mut report)?;
+        } else if let Some(path) = &options.output {
+            let out = create_kmer_output(path, options)?;
+            let entries = counts
+                .iter()
+                .map(|entry| (*entry.key(), *entry.value()))
+                .filter(|&(_, count)| count >= min_count);
+            let mut entries: Box<dyn Iterator<Item = (T, u64)>> = Box::new(entries);
+            if options.sort {
+                let mut sorted: Vec<(T, u64)> = entries.collect();
+                sorted.sort_unstable();
+                entries = Box::new(sorted.into_iter());
+            }
+            match options.jellyfish_dump {
+                Some(layout) => write_jellyfish_dump_iter(entries, k, layout, out)?,
+                None => write_kmer_count_iter(entries, k, out)?,
+            }
+        }
+        let histogram = (options.histogram.is_some() || options.estimate_genome_size).then(|| kmer_histogram(&counts, options.histo_max));
+        if let (Some(path), Some(histogram)) = (&options.histogram, &histogram) {
+            let out = create_output(path, options.compression)?;
+            if options.jellyfish_histo {
+                write_jellyfish_histo(histogram, out)?;
+            } else {
+                write_histogram(histogram, out)?;
+            }
+        }
+        let multiplicity = multiplicity_stats(&counts);
+        add_stats(&mut report, &stats, Some(format!("Number of distinct {}", kmer_name(options))), options);
+        report.add("max_multiplicity", "Max multiplicity", multiplicity.max);
+        report.add_formatted("mean_multiplicity", "Mean multiplicity", format!("{:.2}", multiplicity.mean), multiplicity.mean);
+        report.add_formatted(
+            "singletons",
+            format!("Singleton {}", kmer_name(options)),
+            format!("{} ({:.2}% of distinct)", multiplicity.singletons, 100.0 * multiplicity.singleton_fraction),
+            multiplicity.singletons,
+        );
+        report.add_json("singleton_fraction", multiplicity.singleton_fraction);
+        if let (true, Some(histogram)) = (options.estimate_genome_size, &histogram) {
+            add_genome_size(&mut report, histogram, multiplicity.occurrences);
+        }
+        if let Some(n) = options.top {
+            add_top_kmers(&mut report, options, &top_kmers(&counts, n));
+        }
+        if let Some(min_count) = options.min_count {
+            let solid = counts.iter().filter(|entry| *entry.value() >= min_count).count();
+            report.add("solid_kmers", format!("Number of distinct {} occurring at least {} times", kmer_name(options), min_count), solid);
+        }
+        add_table_memory(&mut report, options, stats.distinct, T::BYTES + size_of::<u64>());
+        Some(stats)
+    } else {
+        let kmers = ShardedSet::<T, S>::with_capacity_and_hasher(options.shards, k, options.reserve_size, hasher);
+        load_binary(options, |kmer| {
+            kmers.insert(kmer);
+        }, &mut report)?;
+        let loaded = kmers.len();
+        // the first write error is kept, to be reported once counting is done
+        let stream = options
+            .stream_output
+            .as_deref()
+            .map(|path| create_kmer_output(path, options))
+            .transpose()?
+            .map(|out| Mutex::new((out, Ok(()))));
+        let on_new = stream.as_ref().map(|stream| {
+            move |kmer: T| {
+                let (out, written) = &mut *stream.lock().unwrap();
+                if written.is_ok() {
+                    *written = writeln!(out, "{}", kmer.decode(k));
+                }
+            }
         });
+        let store = match &on_new {
+            Some(on_new) => KmerStore::Streamed { kmers: &kmers, on_new },
+            None => KmerStore::Sharded(&kmers),
+        };
+        let stats = count_files(counter, options, store, &mut report)?;
+        if let Some(stream) = stream {
+            let (mut out, written) = stream.into_inner().unwrap();
+            written?;
+            out.flush()?;
+        }
+        write_set_outputs(options, kmers.len(), || kmers.iter(), true)?;
+        if options.save {
+            save_set(options, &kmers)?;
+        }
+        add_stats(&mut report, &stats, Some(format!("Number of distinct {}", kmer_name(options))), options);
+        if !options.input_binary.is_empty() {
+            report.add("loaded_distinct_kmers", format!("Distinct {} loaded", kmer_name(options)), loaded);
+            report.add("new_distinct_kmers", format!("New distinct {} of the input files", kmer_name(options)), stats.distinct - loaded);
+        }
+        add_table_memory(&mut report, options, stats.distinct, T::BYTES);
+        Some(stats)
+    };
+
+    if options.report_memory {
+        add_peak_memory(&mut report);
+    }
+    report.print(options.format);
+    if let (Some(path), Some(stats)) = (&options.append_summary, stats) {
+        append_summary(options, path, &stats)?;
+    }
+    if let (Some(stats), Some((limit, _))) = (stats, options.memory_cap) {
+        if memory_limit_reached(options, &stats) {
+            eprintln!(
+                "Error: memory limit of {} reached at {} distinct k-mers, the counts printed are those of part of the input",
+                memory::format_bytes(limit),
+                stats.distinct
+            );
+            Exit::MemoryLimit.exit();
+        }
+    }
+    if interrupted || stats.is_some_and(|stats| stats.interrupted) {
+        eprintln!("Interrupted: the counts printed are those of the beginning of the input");
+        Exit::Interrupted.exit();
+    }
+    Ok(())
+}
+
+/// Memory of the k-mers buffered by --disk without --memory-limit
+const DISK_BUFFER_BYTES: usize = 1 << 30;
+
+/// Columns of the summary TSV of --append-summary
+const SUMMARY_HEADER: &str = "sample\tk\ttotal_nucleotides\ttotal_kmers\tvalid_kmers\tdistinct_kmers\tgc_content";
+
+/// Appends a row of the statistics of the run to the summary TSV at `path`, after the header if the file is new.
+/// The file is locked while it is written, so that concurrent runs can share it.
+fn append_summary(options: &Options, path: &str, stats: &KmerStats) -> io::Result<()> {
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    file.lock()?;
+    let mut row = String::new();
+    // checked under the lock, for another run may have just created the file
+    if file.metadata()?.len() == 0 {
+        row = format!("{}\n", SUMMARY_HEADER);
+    }
+    let na = || "NA".to_string();
+    row += &format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+        options.name,
+        options.k,
+        stats.total_nucleotides,
+        stats.total_kmers,
+        stats.valid_kmers,
+        if options.only_count { na() } else { stats.distinct.to_string() },
+        stats.gc_content().map_or_else(na, |gc| gc.to_string())
+    );
+    // a single write, in case the file is shared over a file system without locks
+    file.write_all(row.as_bytes())
+}
 
-    Ok((
-        total_nucleotides.load(Ordering::Relaxed),
-        nb_total_kmers.load(Ordering::Relaxed),
-        nb_valid_kmers.load(Ordering::Relaxed),
-    ))
+/// Sample name of --append-summary by default: the name of the first input file without its extensions
+/// (sample for sample.fa.gz), stdin for '-'
+fn default_sample_name(path: &str) -> String {
+    if path == "-" {
+        return "stdin".to_string();
+    }
+    let name = Path::new(path).file_name().map_or_else(|| path.to_string(), |name| name.to_string_lossy().into_owned());
+    let name = [".gz", ".bz2", ".xz", ".zst"].iter().find_map(|extension| name.strip_suffix(extension)).unwrap_or(&name);
+    match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem.to_string(),
+        _ => name.to_string(),
+    }
 }
 
+/// Options without checkpoints: the counting modes other than a set of distinct k-mers, which is all they save,
+/// and --dedup-records and --length-stats, whose sequences seen and record lengths are not saved
+const CHECKPOINT_CONFLICTS: [&str; 20] = [
+    "only_count", "frequencies", "histogram", "min_count", "include", "approximate", "bloom", "sketch", "compare",
+    "kmer_sizes", "stream_output", "input_binary", "dedup_records", "length_stats",
+    "disk", "estimate_genome_size", "top", "membership", "query", "snapshot_interval",
+];
 
 fn main() {
     let matches = Command::new("Unique Kmer Counter")
@@ -130,25 +1548,67 @@ fn main() {
                 .long("kmer-size")
                 .value_name("K")
                 .help("Sets the k-mer size")
-                .required(true)
+                .required_unless_present_any(["spaced", "kmer_sizes", "compare_sketches"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("kmer_sizes")
+                .long("kmer-sizes")
+                .value_name("K1,K2,...")
+                .visible_alias("k-range")
+                .help("Counts the distinct k-mers for each of several k in a single pass over the input, \
+                e.g. 15,17,19,21,23, or a range START:END:STEP of k from START to END included, e.g. --k-range 15:23:2. \
+                One set is kept per k, each of --reserve size: the memory adds up. \
+                With --output, also writes a TSV table of the counts per k (k, total_kmers, valid_kmers, distinct_kmers)")
+                .conflicts_with_all([
+                    "k", "spaced", "only_count", "frequencies", "histogram", "approximate", "bloom",
+                    "output_binary", "input_binary", "compare", "per_record", "max_distinct",
+                ])
+                .value_delimiter(',')
+                .action(ArgAction::Append)
                 .num_args(1),
         )
         .arg(
             Arg::new("fasta_file")
                 .short('f')
                 .long("input-file")
-                .help("Sets the input FASTA file")
-                .required(true)
+                .help("Sets the input FASTA/FASTQ file, possibly compressed, .2bit file or file of raw sequences, one per line ('-' for stdin). Repeat the option or give a comma-separated list \
+                to count the k-mers of several files together")
+                .required_unless_present_any(["input_binary", "compare_sketches", "fofn"])
+                .action(ArgAction::Append)
+                .value_delimiter(',')
                 .num_args(1),
         )
         .arg(
-            Arg::new("reserve_size")
-                .short('r')
-                .long("reserve")
+            Arg::new("fofn")
+                .long("fofn")
+                .value_name("FILE")
+                .help("Counts the input files listed in FILE ('-' for stdin), one path per line, after those of --input-file, \
+                as if given with it. Blank lines and lines starting with # are ignored")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("pair")
+                .long("pair")
+                .value_name("R2_FILE")
+                .help("Counts the paired-end reads of R2_FILE with those of the input file (R1) as one sample, \
+                reading their records alternately: the counts are those of the concatenation of both files. \
+                With several input files, give one R2 file for each, in the same order. \
+                Warns if the files of a pair have different numbers of records")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .conflicts_with("kmer_sizes")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("reserve_size")
+                .short('r')
+                .long("reserve")
                 .value_name("RESERVE")
                 .help("Sets the initial reserve size for the HashSet. \
+                By default, estimated from the size of the input files: half their (decompressed) bytes, \
+                at most 4^k and 3000000000. Capped with a warning to the k-mer tables that fit in half of the memory available. \
                 Useless with the only_count option")
-                .default_value("3000000000")
                 .num_args(1),
         )
         .arg(
@@ -158,77 +1618,1282 @@ fn main() {
             .num_args(0) 
             .help("Only count the number of kmers and nucleotides")
         )
+        .arg(
+            Arg::new("canonical")
+            .short('C')
+            .long("canonical")
+            .num_args(0)
+            .conflicts_with("strand")
+            .help("Count canonical k-mers (a k-mer and its reverse complement are counted once). \
+            Same as --strand canonical")
+        )
+        .arg(
+            Arg::new("strand")
+                .long("strand")
+                .value_name("STRAND")
+                .help("Strands on which k-mers are counted: forward only, canonical (a k-mer and its reverse complement \
+                are counted once) or both (a k-mer and its reverse complement are counted as two k-mers, \
+                palindromes once)")
+                .value_parser(["forward", "canonical", "both"])
+                .default_value("forward")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .help("Writes the distinct k-mers, one per line, to FILE ('-' for stdout). \
+                The order is unspecified. Useless with the only_count option. \
+                This and the other output files are gzip-compressed when their name ends in .gz")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("compression_level")
+                .long("compression-level")
+                .value_name("LEVEL")
+                .help("Compression level of the .gz outputs, from 0 (none, fastest) to 9 (smallest)")
+                .default_value("6")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("stream_output")
+                .long("stream-output")
+                .value_name("FILE")
+                .help("Writes each distinct k-mer to FILE ('-' for stdout) as soon as it is first found, \
+                so that it can be consumed while counting. The order is unspecified and varies between runs. \
+                K-mers loaded with --input-binary are not written. With '-', the statistics follow the k-mers")
+                .conflicts_with_all([
+                    "only_count", "frequencies", "histogram", "approximate", "bloom", "compare", "kmer_sizes",
+                ])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("output_binary")
+                .long("output-binary")
+                .value_name("FILE")
+                .help("Writes the distinct k-mers to FILE ('-' for stdout) in a compact binary format, \
+                to be reloaded with --input-binary")
+                .conflicts_with_all(["only_count", "frequencies", "histogram", "approximate", "bloom"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("input_binary")
+                .long("input-binary")
+                .visible_alias("load")
+                .value_name("FILE")
+                .help("Loads the k-mers of a file written by --output-binary, as if they were counted from an input file, \
+                and reports the new distinct k-mers of the input files. \
+                The file must have been written with the same k and strand. Can be repeated")
+                .action(ArgAction::Append)
+                .conflicts_with_all(["only_count", "frequencies", "histogram", "approximate", "bloom"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("save")
+            .long("save")
+            .num_args(0)
+            .requires("input_binary")
+            .conflicts_with("compare")
+            .help("Writes the distinct k-mers, loaded and counted, back to the file of --load, replacing it once \
+            complete: to build a k-mer database incrementally, a sample at a time. Needs a single loaded file")
+        )
+        .arg(
+            Arg::new("sort")
+            .long("sort")
+            .num_args(0)
+            .help("Sorts the k-mers written with --output in lexicographic order. \
+            The k-mers are first copied to a sorted array: needs up to twice the memory")
+        )
+        .arg(
+            Arg::new("spaced")
+                .long("spaced")
+                .value_name("MASK")
+                .help("Counts spaced k-mers: in each window of the length of MASK (e.g. 1101011), only the nucleotides \
+                at the 1 positions make the k-mer. k is the number of 1s. Nucleotides at 0 positions may be ambiguous")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("minimizer")
+                .long("minimizer")
+                .value_name("W")
+                .help("Counts minimizers instead of k-mers: the smallest (canonical with --canonical) k-mer \
+                of each window of W consecutive valid k-mers")
+                .conflicts_with("expand_iupac")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("alphabet")
+                .long("alphabet")
+                .value_name("SYMBOLS")
+                .help("Symbols of the sequences, in the order of their 2-bit codes, which is that of --sort: 2 to 4 of them, \
+                e.g. HP for a hydrophobic/polar amino acid alphabet. Letters match whatever their case. K-mers are written \
+                with these symbols, and only counted on the forward strand [default: ACGT, with U read as T]")
+                .conflicts_with_all(["canonical", "expand_iupac", "include", "exclude"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("case_sensitive")
+            .long("case-sensitive")
+            .num_args(0)
+            .help("Ignores k-mers containing lowercase (soft-masked) nucleotides. \
+            By default lowercase nucleotides are counted as uppercase ones")
+        )
+        .arg(
+            Arg::new("expand_iupac")
+            .long("expand-iupac")
+            .num_args(0)
+            .help("Counts every ACGT resolution of the k-mers containing IUPAC ambiguity codes (R, Y, N...). \
+            By default such k-mers are ignored")
+        )
+        .arg(
+            Arg::new("max_ambiguous")
+                .long("max-ambiguous")
+                .value_name("N")
+                .help("With --expand-iupac, ignores k-mers containing more than N ambiguous positions")
+                .default_value("2")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("n_policy")
+                .long("n-policy")
+                .value_name("POLICY")
+                .help("What becomes of the ambiguous bases (N, R, Y...): skip leaves them, making the k-mers containing them \
+                invalid, a replaces them with A and random with one of the nucleotides they stand for, chosen from \
+                the record id, the position and --seed. With a or random, every window of A, C, G, T and ambiguous bases \
+                is counted, as by tools that do not drop Ns")
+                .value_parser(["skip", "a", "random"])
+                .default_value("skip")
+                .conflicts_with("expand_iupac")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("min_quality")
+                .short('q')
+                .long("min-quality")
+                .value_name("Q")
+                .help("Ignores k-mers containing a base of Phred quality below Q (FASTQ input only)")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("min_length")
+                .long("min-length")
+                .value_name("L")
+                .help("Skips the records shorter than L nucleotides")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("max_length")
+                .long("max-length")
+                .value_name("L")
+                .help("Skips the records longer than L nucleotides")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("trim_start")
+                .long("trim-start")
+                .value_name("N")
+                .help("Ignores the first N nucleotides of each record (e.g. primers or adapters): they are not counted, \
+                and no k-mer contains them. --min-length and --max-length apply to the untrimmed records")
+                .default_value("0")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("trim_end")
+                .long("trim-end")
+                .value_name("M")
+                .help("Ignores the last M nucleotides of each record, as --trim-start does the first ones")
+                .default_value("0")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("ends_only")
+                .long("ends-only")
+                .value_name("L")
+                .help("Only counts the k-mers of the first and last L nucleotides of each record, after trimming, \
+                e.g. to profile adapters: faster than counting whole reads. No k-mer spans both ends, unless they \
+                overlap; the nucleotide and k-mer counts are those of the ends")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("stride")
+                .long("stride")
+                .value_name("S")
+                .help("Only reads the k-mer windows starting every S positions of each record, from its first one, \
+                for a quick sketch of long sequences. The k-mer counts are those of these windows, \
+                and the distinct k-mers a sample of those of the input, not all of them")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("max_distinct")
+                .long("max-distinct")
+                .value_name("N")
+                .help("Stops reading the input once N distinct k-mers are found. \
+                The counts are then those of a sample of the input, and lower bounds for the whole input")
+                .conflicts_with("only_count")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("hasher")
+                .long("hasher")
+                .value_name("HASHER")
+                .help("Hash function of the k-mer sets: sip (SipHash, randomly keyed) \
+                or mix (a single splitmix64 round seeded by --seed, faster)")
+                .value_parser(["sip", "mix"])
+                .default_value("sip")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("sample")
+                .long("sample")
+                .value_name("FRACTION")
+                .help("Counts a sample of the records: each record is kept with probability FRACTION (0 to 1), \
+                depending on its id and --seed. The counts are then those of the sample")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("id_regex")
+                .long("id-regex")
+                .value_name("PATTERN")
+                .help("Only counts the records whose id matches the regular expression PATTERN anywhere, \
+                e.g. '^chr[0-9XY]+$', and reports their number")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seed of the record sampling, of the mix hasher and of --n-policy random")
+                .default_value("0")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("per_record")
+                .long("per-record")
+                .value_name("FILE")
+                .help("Writes the statistics of each record (record_id, length, total_kmers, valid_kmers, distinct_kmers) \
+                as TSV to FILE ('-' for stdout), or as JSON lines with --format jsonl. The distinct k-mers of each record are counted in a temporary set, \
+                which needs more memory for long records")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("frequencies")
+            .long("frequencies")
+            .num_args(0)
+            .help("Counts the occurrences of each k-mer (about twice the memory). \
+            With --output, writes <kmer>\\t<count> lines")
+        )
+        .arg(
+            Arg::new("histogram")
+                .long("histogram")
+                .value_name("FILE")
+                .help("Writes the k-mer abundance histogram (<multiplicity>\\t<count> lines) to FILE ('-' for stdout). \
+                Implies --frequencies")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("jellyfish_dump")
+                .long("jellyfish-dump")
+                .value_name("LAYOUT")
+                .help("Writes the k-mers of --output with their counts as `jellyfish dump` does, for Jellyfish-based pipelines: \
+                a FASTA record per k-mer with its count as header (fasta), or <kmer> <count> lines as `jellyfish dump -c` \
+                (columns). Implies --frequencies")
+                .value_parser(["fasta", "columns"])
+                .default_missing_value("fasta")
+                .num_args(0..=1)
+                .requires("output")
+                .conflicts_with_all(["only_count", "approximate", "bloom", "sketch", "compare", "kmer_sizes", "include", "disk", "output_binary"]),
+        )
+        .arg(
+            Arg::new("jellyfish_histo")
+                .long("jellyfish-histo")
+                .num_args(0)
+                .help("Writes the histogram of --histogram as `jellyfish histo` does: <multiplicity> <count> lines")
+                .requires("histogram"),
+        )
+        .arg(
+            Arg::new("checkpoint")
+                .long("checkpoint")
+                .value_name("FILE")
+                .help("Writes the distinct k-mers and the statistics to FILE after each input file, \
+                to resume a long count with --resume after a crash. FILE is also a binary k-mer file for --input-binary")
+                .conflicts_with_all(CHECKPOINT_CONFLICTS)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .value_name("FILE")
+                .help("Resumes the count saved by --checkpoint in FILE, skipping the input files it covers, \
+                which must be the first ones. The other settings must be those of the checkpointed run")
+                .conflicts_with_all(CHECKPOINT_CONFLICTS)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("max_homopolymer")
+                .long("max-homopolymer")
+                .value_name("L")
+                .help("Leaves out the low-complexity k-mers, with a run of more than L identical nucleotides \
+                (e.g. AAAAAAAA for L = 7), from the valid and distinct k-mers. Reports their number")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("min_entropy")
+                .long("min-entropy")
+                .value_name("BITS")
+                .help("Leaves out the low-complexity k-mers whose window has a Shannon entropy of its A, C, G, T \
+                composition below BITS, from 0 (homopolymer) to 2 (as many of each), e.g. 1.5. \
+                Counted with those of --max-homopolymer")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .value_name("FILE")
+                .help("Never stores the k-mers listed in FILE, one per line (e.g. adapters or host k-mers), \
+                nor their reverse complements with --canonical or --strand both (see --no-canon-lists). Lines that are not k-mers \
+                of size k are skipped with a warning. Reports the number of excluded occurrences")
+                .conflicts_with("kmer_sizes")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("include")
+                .long("include")
+                .value_name("FILE")
+                .help("Only counts the target k-mers listed in FILE, one per line, as for --exclude. \
+                Writes <kmer>\\t<count> lines for every target, in the order of FILE, to --output or stdout, \
+                and reports the number of targets found. Implies --frequencies")
+                .conflicts_with_all(["kmer_sizes", "only_count", "approximate", "bloom", "compare", "output_binary", "input_binary"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("no_canon_lists")
+                .long("no-canon-lists")
+                .help("Matches the k-mers of --exclude and --include strictly as written, without their reverse complements: \
+                with --canonical, a listed k-mer that is not canonical then never matches")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("min_count")
+                .long("min-count")
+                .value_name("N")
+                .help("Also reports the number of solid k-mers, occurring at least N times, and writes only them \
+                with --output. Implies --frequencies")
+                .conflicts_with_all(["only_count", "approximate", "bloom", "compare", "kmer_sizes", "output_binary", "input_binary"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("estimate_genome_size")
+                .long("estimate-genome-size")
+                .num_args(0)
+                .help("Reports a rough estimate of the genome size from the k-mer histogram of reads: the number of k-mers \
+                divided by the coverage, the multiplicity of the main peak past the k-mers with errors. Implies --frequencies")
+                .conflicts_with_all([
+                    "only_count", "approximate", "bloom", "sketch", "compare", "kmer_sizes", "include", "disk",
+                    "output_binary", "input_binary",
+                ]),
+        )
+        .arg(
+            Arg::new("top")
+                .long("top")
+                .value_name("N")
+                .help("Reports the N most frequent k-mers and their numbers of occurrences, e.g. to spot adapters \
+                or repeats. Implies --frequencies")
+                .conflicts_with_all(["only_count", "approximate", "bloom", "sketch", "compare", "kmer_sizes", "include", "disk"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("membership")
+                .long("membership")
+                .value_name("FILE")
+                .help(format!("Writes to FILE each distinct k-mer and the records it occurs in, tab separated, \
+                e.g. to find the k-mers shared by contigs: a 0/1 bitvector with a character per record, in input order. \
+                Takes a single input file of at most {} records", MAX_MEMBERSHIP_RECORDS))
+                .conflicts_with_all([
+                    "only_count", "frequencies", "histogram", "min_count", "include", "approximate", "bloom", "sketch",
+                    "compare", "kmer_sizes", "stream_output", "output_binary", "input_binary", "disk", "top",
+                    "estimate_genome_size", "pair",
+                ])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("snapshot_interval")
+                .long("snapshot-interval")
+                .value_name("MB")
+                .help("Writes to --snapshot-output the number of distinct k-mers each time MB more megabases are counted, \
+                tab separated after the megabases counted so far: a rarefaction curve, to see the distinct k-mers saturate")
+                .requires("snapshot_output")
+                .conflicts_with_all(["only_count", "kmer_sizes", "disk", "compare"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("snapshot_output")
+                .long("snapshot-output")
+                .value_name("FILE")
+                .help("File of the distinct k-mer counts of --snapshot-interval")
+                .requires("snapshot_interval")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("histo_max")
+                .long("histo-max")
+                .value_name("MAX")
+                .help("Last bucket of the histogram, gathering k-mers occurring MAX times or more")
+                .default_value("10000")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("approximate")
+            .long("approximate")
+            .visible_alias("hll")
+            .num_args(0)
+            .help("Estimates the number of distinct k-mers with HyperLogLog, in constant memory \
+            (2^PRECISION bytes) instead of storing every k-mer. Other counts stay exact")
+        )
+        .arg(
+            Arg::new("hll_precision")
+                .long("hll-precision")
+                .value_name("PRECISION")
+                .help("HyperLogLog precision, from 4 to 18. \
+                The expected relative error is 1.04/sqrt(2^PRECISION), about 0.8% for 14")
+                .default_value("14")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("bloom")
+            .long("bloom")
+            .num_args(0)
+            .conflicts_with("approximate")
+            .help("Counts distinct k-mers with a Bloom filter of --bloom-size bytes instead of storing every k-mer. \
+            False positives make the count a lower bound")
+        )
+        .arg(
+            Arg::new("bloom_size")
+                .long("bloom-size")
+                .value_name("BYTES")
+                .help("Size of the Bloom filter")
+                .default_value("1073741824")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("bloom_hashes")
+                .long("bloom-hashes")
+                .value_name("N")
+                .help("Number of hash functions of the Bloom filter")
+                .default_value("4")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("bloom_output")
+                .long("bloom-output")
+                .value_name("FILE")
+                .help("Writes the Bloom filter of --bloom to FILE, with k and the strand mode, \
+                to look up the k-mers of other inputs with --query")
+                .requires("bloom")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("query")
+                .long("query")
+                .value_name("BLOOM")
+                .help("Prints the fraction of the distinct k-mers of the input found in the Bloom filter of BLOOM, \
+                written by --bloom-output with the same k and strand mode. False positives make it an upper bound")
+                .conflicts_with_all([
+                    "only_count", "frequencies", "histogram", "min_count", "include", "approximate", "bloom", "sketch",
+                    "compare", "kmer_sizes", "output", "stream_output", "output_binary", "input_binary", "disk", "top",
+                    "estimate_genome_size", "membership",
+                ])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("sketch")
+                .long("sketch")
+                .value_name("SIZE")
+                .help("Writes to --output a MinHash sketch of the distinct k-mers: their SIZE smallest hashes, \
+                which estimate the Jaccard index of two k-mer sets with --compare-sketches, e.g. 1000. \
+                Use --canonical for sketches of reads or assemblies of either strand. \
+                The number of distinct k-mers is estimated from the sketch")
+                .requires("output")
+                .conflicts_with_all([
+                    "only_count", "frequencies", "histogram", "min_count", "include", "approximate", "bloom", "compare",
+                    "kmer_sizes", "stream_output", "output_binary", "input_binary", "sort",
+                ])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("disk")
+                .long("disk")
+                .value_name("DIR")
+                .help("Counts the distinct k-mers in external memory, for more than fit in memory: k-mers are buffered \
+                up to --memory-limit, then sorted and written to temporary files in DIR, which are merged at the end. \
+                Slower than in memory, with the same counts. The k-mers of --output come out sorted")
+                .conflicts_with_all([
+                    "only_count", "frequencies", "histogram", "min_count", "include", "approximate", "bloom", "sketch",
+                    "compare", "kmer_sizes", "stream_output", "output_binary", "input_binary", "max_distinct",
+                ])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("memory_limit")
+                .long("memory-limit")
+                .value_name("BYTES")
+                .help("Memory for the k-mers. Without --disk, counting stops when the estimated k-mer table \
+                would exceed it: the counts so far are printed, with an error, and the exit code is 4. \
+                With --disk, the k-mers buffered before they are written to disk [default with --disk: 1073741824]")
+                .conflicts_with_all(["only_count", "approximate", "bloom", "sketch", "kmer_sizes"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("compare_sketches")
+                .long("compare-sketches")
+                .value_names(["A", "B"])
+                .help("Prints the Jaccard index of the k-mer sets of the sketch files A and B written by --sketch, estimated from \
+                their smallest hashes, and the derived Mash distance. Both must have the same k and strand. \
+                No input file is read")
+                .conflicts_with_all(["k", "fasta_file", "fofn", "kmer_sizes", "spaced", "input_binary"])
+                .num_args(2),
+        )
+        .arg(
+            Arg::new("compare")
+                .long("compare")
+                .value_name("FILE2")
+                .help("Compares the distinct k-mers of the input with those of FILE2 ('-' for stdin): \
+                prints the size of their intersection and union, and their Jaccard index")
+                .conflicts_with_all(["only_count", "frequencies", "histogram", "approximate", "bloom"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("set_op")
+                .long("set-op")
+                .value_name("OP")
+                .help("With --compare, writes to --output the k-mers of the intersection, the union, \
+                the input files only (a-only) or FILE2 only (b-only), instead of the k-mers of the input files")
+                .value_parser(["intersection", "union", "a-only", "b-only"])
+                .requires_all(["compare", "output"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Format of the statistics printed on stdout. jsonl prints them as JSON, and the statistics \
+                of --per-record as a JSON object per record, written as soon as it is counted")
+                .value_parser(["text", "json", "jsonl"])
+                .default_value("text")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("stats_sep")
+                .long("stats-sep")
+                .value_name("SEP")
+                .help("Separator of the labels and values of the text statistics: a colon and a space, or a tab")
+                .value_parser(["colon", "tab"])
+                .default_value("colon")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("machine")
+            .long("machine")
+            .num_args(0)
+            .help("Prints the text statistics with the keys of the JSON format instead of labels (e.g. total_nucleotides) \
+            and unrounded values, for shell pipelines. Nested values get dotted keys (e.g. files.0.valid_kmers), \
+            missing ones are left out. Ignored with --format json")
+        )
+        .arg(
+            Arg::new("append_summary")
+                .long("append-summary")
+                .value_name("FILE")
+                .help("Appends a row of statistics (sample, k, total_nucleotides, total_kmers, valid_kmers, distinct_kmers, \
+                gc_content) to the TSV file FILE, written with its header if new, to gather the samples of several runs. \
+                The file is locked while written, so that concurrent runs can share it")
+                .conflicts_with_all(["kmer_sizes", "dry_run"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("name")
+                .long("name")
+                .value_name("NAME")
+                .help("Sample name of the --append-summary row. By default, the name of the first input file \
+                without its extensions")
+                .requires("append_summary")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("gc")
+            .long("gc")
+            .num_args(0)
+            .help("Prints the GC content, over A, C, G, T nucleotides only (N and other ambiguous bases are excluded)")
+        )
+        .arg(
+            Arg::new("timing")
+            .long("timing")
+            .num_args(0)
+            .help("Prints the counting time and throughput to stderr")
+        )
+        .arg(
+            Arg::new("strict")
+            .long("strict")
+            .num_args(0)
+            .help("Fails when nucleotides are read but no valid k-mer is found (e.g. protein input, or k larger \
+            than every sequence), instead of only warning")
+        )
+        .arg(
+            Arg::new("skip_invalid")
+            .long("skip-invalid")
+            .num_args(0)
+            .help("Skips the malformed records (empty sequence, quality of another length than the sequence) \
+            and reports their number, instead of failing on the first one. Records that cannot be parsed still fail")
+        )
+        .arg(
+            Arg::new("dedup_records")
+            .long("dedup-records")
+            .num_args(0)
+            .conflicts_with("compare")
+            .help("Leaves out the records whose sequence is exactly that of a record already counted, in any input file \
+            (e.g. redundant contigs), and reports their number. Sequences are compared by a 128-bit hash, \
+            kept for each distinct sequence")
+        )
+        .arg(
+            Arg::new("length_stats")
+            .long("length-stats")
+            .num_args(0)
+            .conflicts_with("compare")
+            .help("Reports the shortest, longest and mean lengths of the records read, and their N50. \
+            The length of each record is kept until the end")
+        )
+        .arg(
+            Arg::new("benchmark")
+            .long("benchmark")
+            .num_args(0)
+            .conflicts_with_all([
+                "dry_run", "only_count", "frequencies", "histogram", "min_count", "include", "approximate", "bloom", "sketch",
+                "compare", "kmer_sizes", "disk", "membership", "output", "stream_output", "output_binary", "input_binary",
+                "per_record", "checkpoint", "resume", "pair", "top", "estimate_genome_size", "query", "snapshot_interval",
+            ])
+            .help("Counts the distinct k-mers of the input with 1, 2, 4... threads up to --max-threads \
+            (by default the number of cores), and prints the time and speedup of each count, to choose -t. \
+            The input is decompressed into memory first: reading it is not timed")
+        )
+        .arg(
+            Arg::new("dry_run")
+            .long("dry-run")
+            .num_args(0)
+            .help("Checks the arguments and that each input file opens, is in a recognized format and starts \
+            with a record of DNA, then prints the format and the length of the first record of each file \
+            and exits without counting")
+        )
+        .arg(
+            Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .action(ArgAction::Count)
+            .help("Prints diagnostics to stderr: the files read, the number of records and the settings chosen \
+            automatically with -v, and the details of each file with -vv. Results stay on stdout")
+        )
+        .arg(
+            Arg::new("quiet")
+            .long("quiet")
+            .num_args(0)
+            .conflicts_with("verbose")
+            .help("Prints only the results and the fatal errors, without warnings")
+        )
+        .arg(
+            Arg::new("report_memory")
+            .long("report-memory")
+            .num_args(0)
+            .help("Reports the peak memory of the run (Linux only) and an estimate of the memory \
+            of the k-mer tables, to choose --reserve for the next runs")
+        )
+        .arg(
+            Arg::new("progress")
+            .long("progress")
+            .num_args(0)
+            .help("Shows a progress bar on stderr, based on the bytes read from each input file. \
+            Ignored when stderr is not a terminal")
+        )
         .arg(
             Arg::new("max_threads")
                 .short('t')
                 .long("max-threads")
                 .value_name("THREADS")
-                .help("Limits the maximum number of threads")
+                .visible_alias("threads")
+                .help("Number of threads: auto (or 0) for one per core. Warns beyond the number of cores")
+                .default_value("auto")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("file_parallel")
+                .long("file-parallel")
+                .help("Counts the input files in parallel, each of them by a single thread, rather than the records of \
+                one file at a time with all the threads: faster for many small files, slower for a few large ones. \
+                The statistics of each file are still reported in order, without their new distinct k-mers")
+                .conflicts_with_all(["kmer_sizes", "benchmark", "per_record", "checkpoint", "resume", "progress"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("per_file_distinct")
+                .long("per-file-distinct")
+                .help("Also reports the distinct k-mers of each input file, and those shared by all the files (core k-mers). \
+                Each file is counted into a set of its own before it is merged: takes up to twice the memory")
+                .conflicts_with_all([
+                    "only_count", "frequencies", "histogram", "min_count", "include", "top", "estimate_genome_size",
+                    "jellyfish_dump", "approximate", "bloom", "sketch", "compare", "membership", "disk", "kmer_sizes",
+                    "stream_output", "file_parallel", "checkpoint", "resume", "max_distinct", "memory_limit", "benchmark",
+                ])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("in_flight")
+                .long("in-flight")
+                .value_name("N")
+                .help("Maximum number of records read ahead of the counting threads. Bounds the memory of the records \
+                whatever the number of threads; lower it for long records, such as chromosomes")
+                .default_value("4096")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("chunk_size")
+                .long("chunk-size")
+                .value_name("N")
+                .help("Maximum number of nucleotides handed to a thread at once. Longer records are counted in pieces \
+                of that many k-mers, in parallel, except with --minimizer or --per-record")
+                .default_value("4194304")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("shards")
+                .long("shards")
+                .value_name("N")
+                .help("Splits the set of distinct k-mers into N independent sets by k-mer prefix, \
+                rounded up to a power of two, so that threads inserting different prefixes do not contend. \
+                0 means four per thread. Not used with --frequencies, --compare, --approximate or --bloom")
                 .default_value("0")
                 .num_args(1),
         )
         .get_matches();
 
-        let k = matches
-            .get_one::<String>("k")
-            .and_then(|s| s.parse::<usize>().ok())  // Parse safely
-            .unwrap_or_else(|| {
+    init_logger(if matches.get_flag("quiet") {
+        LevelFilter::Error
+    } else {
+        match matches.get_count("verbose") {
+            0 => LevelFilter::Warn,
+            1 => LevelFilter::Info,
+            _ => LevelFilter::Debug,
+        }
+    });
+
+    let format = match matches.get_one::<String>("format").map(String::as_str) {
+        Some("json") => Format::Json,
+        Some("jsonl") => Format::JsonLines,
+        _ => Format::Text {
+            separator: if matches.get_one::<String>("stats_sep").is_some_and(|sep| sep == "tab") { "\t" } else { ": " },
+            machine: matches.get_flag("machine"),
+        },
+    };
+    if let Some(paths) = matches.get_many::<String>("compare_sketches") {
+        let paths: Vec<String> = paths.cloned().collect();
+        if let Err(e) = compare_sketches(&paths, format) {
+            eprintln!("Error comparing sketches: {}", e);
+            Exit::of(&e).exit();
+        }
+        return;
+    }
+
+    let alphabet = matches.get_one::<String>("alphabet").map_or_else(Alphabet::dna, |symbols| {
+        symbols.parse::<Alphabet>().unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            Exit::Arguments.exit();
+        })
+    });
+    let spaced_seed = matches.get_one::<String>("spaced").map(|mask| {
+        mask.parse::<SpacedSeed>().unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            Exit::Arguments.exit();
+        })
+    });
+
+    let kmer_sizes: Vec<usize> = matches
+        .get_many::<String>("kmer_sizes")
+        .into_iter()
+        .flatten()
+        .map(|sizes| parse_kmer_sizes(sizes))
+        .collect::<Option<Vec<_>>>()
+        .unwrap_or_else(|| {
+            eprintln!("Error: kmer_sizes must be a comma-separated list of positive integers or ranges START:END:STEP, with START <= END");
+            Exit::Arguments.exit();
+        })
+        .concat();
+
+        let k = match matches.get_one::<String>("k") {
+            Some(k) => k.parse::<usize>().ok(),  // Parse safely
+            None if !kmer_sizes.is_empty() => kmer_sizes.iter().max().copied(),
+            None => spaced_seed.as_ref().map(SpacedSeed::weight),
+        }
+        .unwrap_or_else(|| {
                 eprintln!("Error: k must be a positive integer");
-                process::exit(1);
+                Exit::Arguments.exit();
         });
 
+    if let Some(seed) = &spaced_seed {
+        if seed.weight() != k {
+            eprintln!("Error: k must be the number of 1 positions of the spaced seed ({})", seed.weight());
+            Exit::Arguments.exit();
+        }
+    }
 
-    if k > 32 {
-        eprintln!("Error: k must be less than or equal to 32");
-        process::exit(1);
+    if k > u128::MAX_K {
+        eprintln!("Error: k must be less than or equal to {}", u128::MAX_K);
+        Exit::Arguments.exit();
     }
 
-    if k < 1 {
+    if k < 1 || kmer_sizes.contains(&0) {
         eprintln!("Error: k must be positive");
-        process::exit(1);
+        Exit::Arguments.exit();
     }
 
-    let fasta_file = matches.get_one::<String>("fasta_file").unwrap();
-    let reserve_size = matches
-        .get_one::<String>("reserve_size")
-        .and_then(|s| s.parse::<usize>().ok())  // Parse safely
-        .unwrap_or_else(|| {
-            eprintln!("Error: reserve_size must be a positive integer");
-            process::exit(1);
+    let mut fasta_files: Vec<String> = matches.get_many::<String>("fasta_file").into_iter().flatten().cloned().collect();
+    if let Some(path) = matches.get_one::<String>("fofn") {
+        let listed = load_fofn(path).unwrap_or_else(|e| {
+            eprintln!("Error reading {}: {}", path, e);
+            Exit::Input.exit();
         });
+        if listed.is_empty() {
+            eprintln!("Error: {} lists no input file", path);
+            Exit::Input.exit();
+        }
+        fasta_files.extend(listed);
+    }
+    let pair: Vec<String> = matches.get_many::<String>("pair").into_iter().flatten().cloned().collect();
+    if !pair.is_empty() && pair.len() != fasta_files.len() {
+        eprintln!("Error: --pair needs one R2 file per input file, {} given for {}", pair.len(), fasta_files.len());
+        Exit::Arguments.exit();
+    }
+    let loaded: Vec<&String> = matches.get_many::<String>("input_binary").into_iter().flatten().collect();
+    if matches.get_flag("save") && (loaded.len() > 1 || loaded.iter().any(|&file| file == "-")) {
+        eprintln!("Error: --save writes the k-mers back to the file of --load, which must be a single file");
+        Exit::Arguments.exit();
+    }
+    if matches.contains_id("membership") && fasta_files.len() > 1 {
+        eprintln!("Error: --membership takes a single input file, whose records are numbered");
+        Exit::Arguments.exit();
+    }
+    let reserve_size = matches.get_one::<String>("reserve_size").map(|s| {
+        s.parse::<usize>().unwrap_or_else(|_| {
+            eprintln!("Error: reserve_size must be a positive integer");
+            Exit::Arguments.exit();
+        })
+    });
     
-    let max_threads = matches
-        .get_one::<String>("max_threads")
+    // the number of threads is settled here, the counts being given it rather than 0
+    let cores = thread::available_parallelism().map_or(1, usize::from);
+    let threads = match matches.get_one::<String>("max_threads").map(String::as_str) {
+        None | Some("auto") | Some("0") => cores,
+        Some(s) => s.parse::<usize>().unwrap_or_else(|_| {
+            eprintln!("Error: max_threads must be a number of threads, or auto (or 0) for one per core");
+            Exit::Arguments.exit();
+        }),
+    };
+    if threads > cores {
+        log::warn!("{} threads requested, more than the cores available ({}): they will share them", threads, cores);
+    }
+    log::info!("Threads: {} (cores available: {})", threads, cores);
+
+    // four shards per thread, as dashmap does
+    let shards = matches
+        .get_one::<String>("shards")
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|&shards| shards > 0)
+        .unwrap_or(4 * threads);
+    log::debug!("Shards: {}", shards);
+
+    let strand = match matches.get_one::<String>("strand").map(String::as_str) {
+        _ if matches.get_flag("canonical") => Strand::Canonical,
+        Some("canonical") => Strand::Canonical,
+        Some("both") => Strand::Both,
+        _ => Strand::Forward,
+    };
+    if !alphabet.is_dna() && (strand != Strand::Forward || matches.get_one::<String>("n_policy").is_some_and(|policy| policy != "skip")) {
+        eprintln!("Error: alphabets other than ACGT are only counted on the forward strand, with --n-policy skip");
+        Exit::Arguments.exit();
+    }
+    let min_quality = matches.get_one::<String>("min_quality").map(|s| {
+        s.parse::<u8>().unwrap_or_else(|_| {
+            eprintln!("Error: min_quality must be an integer between 0 and 255");
+            Exit::Arguments.exit();
+        })
+    });
+    let max_ambiguous = matches
+        .get_one::<String>("max_ambiguous")
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or_else(|| {
+            eprintln!("Error: max_ambiguous must be a non-negative integer");
+            Exit::Arguments.exit();
+        });
+    let hll_precision = matches
+        .get_one::<String>("hll_precision")
+        .and_then(|s| s.parse::<u8>().ok())
+        .filter(|p| (hll::MIN_PRECISION..=hll::MAX_PRECISION).contains(p))
+        .unwrap_or_else(|| {
+            eprintln!(
+                "Error: hll_precision must be between {} and {}",
+                hll::MIN_PRECISION,
+                hll::MAX_PRECISION
+            );
+            Exit::Arguments.exit();
+        });
+    let bloom_size = matches
+        .get_one::<String>("bloom_size")
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|&size| size > 0)
+        .unwrap_or_else(|| {
+            eprintln!("Error: bloom_size must be a positive integer");
+            Exit::Arguments.exit();
+        });
+    let bloom_hashes = matches
+        .get_one::<String>("bloom_hashes")
+        .and_then(|s| s.parse::<u32>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or_else(|| {
+            eprintln!("Error: bloom_hashes must be a positive integer");
+            Exit::Arguments.exit();
+        });
+    let sketch = matches.get_one::<String>("sketch").map(|s| {
+        s.parse::<usize>().ok().filter(|&size| size > 0).unwrap_or_else(|| {
+            eprintln!("Error: sketch must be a positive integer");
+            Exit::Arguments.exit();
+        })
+    });
+    let memory_limit = matches.get_one::<String>("memory_limit").map(|s| {
+        s.parse::<usize>().ok().filter(|&size| size > 0).unwrap_or_else(|| {
+            eprintln!("Error: memory_limit must be a positive integer");
+            Exit::Arguments.exit();
+        })
+    });
+    let in_flight = matches
+        .get_one::<String>("in_flight")
         .and_then(|s| s.parse::<usize>().ok())
+        .filter(|&records| records > 0)
+        .unwrap_or_else(|| {
+            eprintln!("Error: in_flight must be a positive integer");
+            Exit::Arguments.exit();
+        });
+    let chunk_size = matches
+        .get_one::<String>("chunk_size")
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|&nucleotides| nucleotides > 0)
+        .unwrap_or_else(|| {
+            eprintln!("Error: chunk_size must be a positive integer");
+            Exit::Arguments.exit();
+        });
+    let max_homopolymer = matches.get_one::<String>("max_homopolymer").map(|s| {
+        s.parse::<usize>().ok().filter(|&length| length > 0).unwrap_or_else(|| {
+            eprintln!("Error: max_homopolymer must be a positive integer");
+            Exit::Arguments.exit();
+        })
+    });
+    let min_entropy = matches.get_one::<String>("min_entropy").map(|s| {
+        s.parse::<f64>().ok().filter(|bits| (0.0..=2.0).contains(bits)).unwrap_or_else(|| {
+            eprintln!("Error: min_entropy must be a number of bits between 0 and 2");
+            Exit::Arguments.exit();
+        })
+    });
+    let compression_level = matches
+        .get_one::<String>("compression_level")
+        .and_then(|s| s.parse::<u32>().ok())
+        .filter(|&level| level <= 9)
+        .unwrap_or_else(|| {
+            eprintln!("Error: compression_level must be an integer between 0 and 9");
+            Exit::Arguments.exit();
+        });
+    let min_count = matches.get_one::<String>("min_count").map(|s| {
+        s.parse::<u64>().ok().filter(|&count| count > 0).unwrap_or_else(|| {
+            eprintln!("Error: min_count must be a positive integer");
+            Exit::Arguments.exit();
+        })
+    });
+
+    let histo_max = matches
+        .get_one::<String>("histo_max")
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|&max| max > 0)
+        .unwrap_or_else(|| {
+            eprintln!("Error: histo_max must be a positive integer");
+            Exit::Arguments.exit();
+        });
+
+    let min_length = matches
+        .get_one::<String>("min_length")
+        .map(|s| {
+            s.parse::<usize>().unwrap_or_else(|_| {
+                eprintln!("Error: min_length must be a non-negative integer");
+                Exit::Arguments.exit();
+            })
+        })
         .unwrap_or(0);
+    let max_length = matches
+        .get_one::<String>("max_length")
+        .map(|s| {
+            s.parse::<usize>().unwrap_or_else(|_| {
+                eprintln!("Error: max_length must be a non-negative integer");
+                Exit::Arguments.exit();
+            })
+        })
+        .unwrap_or(usize::MAX);
 
+    let [trim_start, trim_end] = ["trim_start", "trim_end"].map(|name| {
+        matches.get_one::<String>(name).and_then(|s| s.parse::<usize>().ok()).unwrap_or_else(|| {
+            eprintln!("Error: {} must be a non-negative integer", name);
+            Exit::Arguments.exit();
+        })
+    });
 
-    if matches.get_flag("only_count") {
-        match process_fasta_parallel_only_count(fasta_file, k, max_threads) {
-            Ok((nuc_count, nb_total_kmers, nb_valid_kmers)) => {
-                println!("Total nucleotides: {}", nuc_count);
-                println!("Total k-mers: {}", nb_total_kmers);
-                println!("Valid k-mers: {}", nb_valid_kmers);
-            }
-            Err(e) => {
-                eprintln!("Error processing file: {}", e);
-                process::exit(1);
-            }
+    let ends_only = matches.get_one::<String>("ends_only").map(|s| {
+        s.parse::<usize>().ok().filter(|&end| end > 0).unwrap_or_else(|| {
+            eprintln!("Error: ends-only must be a positive integer");
+            Exit::Arguments.exit();
+        })
+    });
+
+    let stride = matches.get_one::<String>("stride").map_or(1, |s| {
+        s.parse::<usize>().ok().filter(|&stride| stride > 0).unwrap_or_else(|| {
+            eprintln!("Error: stride must be a positive integer");
+            Exit::Arguments.exit();
+        })
+    });
+
+    let minimizer = matches.get_one::<String>("minimizer").map(|s| {
+        s.parse::<usize>().ok().filter(|&w| w > 0).unwrap_or_else(|| {
+            eprintln!("Error: minimizer must be a positive integer");
+            Exit::Arguments.exit();
+        })
+    });
+    if minimizer.is_some() && strand == Strand::Both {
+        eprintln!("Error: minimizers are not available with --strand both");
+        Exit::Arguments.exit();
+    }
+
+    let max_distinct = matches.get_one::<String>("max_distinct").map(|s| {
+        s.parse::<usize>().unwrap_or_else(|_| {
+            eprintln!("Error: max_distinct must be a non-negative integer");
+            Exit::Arguments.exit();
+        })
+    });
+
+    let reserve_size = reserve_size.unwrap_or_else(|| {
+        let reserve = if matches.get_flag("only_count") {
+            0
+        } else {
+            auto_reserve(&[fasta_files.as_slice(), pair.as_slice()].concat(), k).min(max_distinct.unwrap_or(usize::MAX))
+        };
+        log::info!("Reserve: {} k-mers, estimated from the input size", reserve);
+        reserve
+    });
+
+    let snapshot_interval = matches.get_one::<String>("snapshot_interval").map(|s| {
+        s.parse::<f64>().ok().map(|megabases| (megabases * 1e6).round()).filter(|&nucleotides| nucleotides >= 1.0).unwrap_or_else(|| {
+            eprintln!("Error: snapshot_interval must be a positive number of megabases");
+            Exit::Arguments.exit();
+        }) as usize
+    });
+    let sample = matches.get_one::<String>("sample").map(|s| {
+        s.parse::<f64>().ok().filter(|fraction| (0.0..=1.0).contains(fraction)).unwrap_or_else(|| {
+            eprintln!("Error: sample must be a fraction between 0 and 1");
+            Exit::Arguments.exit();
+        })
+    });
+    let id_regex = matches.get_one::<String>("id_regex").map(|pattern| {
+        Regex::new(pattern).unwrap_or_else(|e| {
+            eprintln!("Error: invalid --id-regex: {}", e);
+            Exit::Arguments.exit();
+        })
+    });
+    let seed = matches
+        .get_one::<String>("seed")
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            eprintln!("Error: seed must be a non-negative integer");
+            Exit::Arguments.exit();
+        });
+
+    let per_record = matches.get_one::<String>("per_record").cloned();
+    if matches.get_flag("no_canon_lists") && !matches.contains_id("exclude") && !matches.contains_id("include") {
+        eprintln!("Error: --no-canon-lists needs --exclude or --include");
+        Exit::Arguments.exit();
+    }
+    let exclude = match matches.get_one::<String>("exclude") {
+        Some(path) => load_kmer_list(path, k).unwrap_or_else(|e| {
+            eprintln!("Error reading {}: {}", path, e);
+            Exit::Input.exit();
+        }),
+        None => Vec::new(),
+    };
+    let include = match matches.get_one::<String>("include") {
+        Some(path) => load_kmer_list(path, k).unwrap_or_else(|e| {
+            eprintln!("Error reading {}: {}", path, e);
+            Exit::Input.exit();
+        }),
+        None => Vec::new(),
+    };
+
+    let histogram = matches.get_one::<String>("histogram").cloned();
+    let top = matches.get_one::<String>("top").map(|s| {
+        s.parse::<usize>().ok().filter(|&n| n > 0).unwrap_or_else(|| {
+            eprintln!("Error: top must be a positive integer");
+            Exit::Arguments.exit();
+        })
+    });
+    // the histogram, the genome size estimate, the top and the solid k-mers are computed from the k-mer frequencies
+    // the targets are counted with the k-mer frequencies
+    let jellyfish_dump = matches.get_one::<String>("jellyfish_dump").map(|layout| match layout.as_str() {
+        "columns" => JellyfishDump::Columns,
+        _ => JellyfishDump::Fasta,
+    });
+    let frequencies = matches.get_flag("frequencies")
+        || histogram.is_some()
+        || jellyfish_dump.is_some()
+        || min_count.is_some()
+        || !include.is_empty()
+        || matches.get_flag("estimate_genome_size")
+        || top.is_some();
+    // without --disk, the k-mer table stops growing at the memory limit
+    let disk = matches.get_one::<String>("disk").cloned();
+    let entry_bytes = if k <= u64::MAX_K { 8 } else { 16 } + if frequencies { 8 } else { 0 };
+    let memory_cap = memory_limit
+        .filter(|_| disk.is_none())
+        .map(|limit| (limit as u64, memory::max_table_entries(limit as u64, entry_bytes)));
+    let given_max_distinct = max_distinct;
+    let max_distinct = [max_distinct, memory_cap.map(|(_, entries)| entries)].into_iter().flatten().min();
+    let reserve_size = reserve_size.min(max_distinct.unwrap_or(usize::MAX));
+    // the k-mers of --disk are not kept in tables
+    let reserve_size = if disk.is_none() { cap_reserve(reserve_size, entry_bytes, kmer_sizes.len().max(1)) } else { reserve_size };
+
+    let counter = KmerCounter::new(k)
+        .strand(strand)
+        .alphabet(alphabet.clone())
+        .case_sensitive(matches.get_flag("case_sensitive"))
+        .min_quality(min_quality)
+        .min_length(min_length)
+        .max_length(max_length)
+        .trim_start(trim_start)
+        .trim_end(trim_end)
+        .ends_only(ends_only)
+        .stride(stride)
+        .spaced_seed(spaced_seed)
+        .minimizer(minimizer)
+        .max_homopolymer(max_homopolymer)
+        .min_entropy(min_entropy)
+        .max_distinct(max_distinct)
+        .sample(sample)
+        .id_regex(id_regex)
+        .seed(seed)
+        .expand_iupac(matches.get_flag("expand_iupac").then_some(max_ambiguous))
+        .n_policy(match matches.get_one::<String>("n_policy").map(String::as_str) {
+            Some("a") => NPolicy::A,
+            Some("random") => NPolicy::Random,
+            _ => NPolicy::Skip,
+        })
+        .record_distinct(per_record.is_some())
+        .skip_invalid(matches.get_flag("skip_invalid"))
+        .dedup_records(matches.get_flag("dedup_records"))
+        .keep_lengths(matches.get_flag("length_stats"))
+        .snapshot_interval(snapshot_interval)
+        .interrupt(handle_interrupt())
+        .exclude(exclude)
+        .include(include.clone())
+        .list_both_strands(!matches.get_flag("no_canon_lists"))
+        .threads(threads)
+        .in_flight(in_flight)
+        .chunk_size(chunk_size);
+
+    let name = matches.get_one::<String>("name").cloned().unwrap_or_else(|| {
+        let mut inputs = fasta_files.iter().chain(matches.get_many::<String>("input_binary").into_iter().flatten());
+        inputs.next().map_or_else(String::new, |path| default_sample_name(path))
+    });
+    let options = Options {
+        fasta_files,
+        pair,
+        k,
+        kmer_sizes,
+        strand,
+        alphabet,
+        reserve_size,
+        shards,
+        only_count: matches.get_flag("only_count"),
+        frequencies,
+        min_count,
+        include: matches.get_one::<String>("include").is_some().then_some(include),
+        output: matches.get_one::<String>("output").cloned(),
+        compression: Compression::new(compression_level),
+        stream_output: matches.get_one::<String>("stream_output").cloned(),
+        sort: matches.get_flag("sort"),
+        output_binary: matches.get_one::<String>("output_binary").cloned(),
+        input_binary: matches.get_many::<String>("input_binary").into_iter().flatten().cloned().collect(),
+        save: matches.get_flag("save"),
+        histogram,
+        histo_max,
+        jellyfish_dump,
+        jellyfish_histo: matches.get_flag("jellyfish_histo"),
+        estimate_genome_size: matches.get_flag("estimate_genome_size"),
+        top,
+        membership: matches.get_one::<String>("membership").cloned(),
+        snapshot_output: matches.get_one::<String>("snapshot_output").cloned(),
+        benchmark: matches.get_flag("benchmark").then_some(threads),
+        file_parallel: matches.get_flag("file_parallel").then_some(threads),
+        per_file_distinct: matches.get_flag("per_file_distinct"),
+        timing: matches.get_flag("timing"),
+        report_memory: matches.get_flag("report_memory"),
+        exclude: matches.get_one::<String>("exclude").is_some(),
+        strict: matches.get_flag("strict"),
+        skip_invalid: matches.get_flag("skip_invalid"),
+        dedup_records: matches.get_flag("dedup_records"),
+        id_regex: matches.get_one::<String>("id_regex").is_some(),
+        low_complexity: max_homopolymer.is_some() || min_entropy.is_some(),
+        checkpoint: matches.get_one::<String>("checkpoint").cloned(),
+        resume: matches.get_one::<String>("resume").cloned(),
+        gc: matches.get_flag("gc"),
+        progress: matches.get_flag("progress") && io::stderr().is_terminal(),
+        per_record,
+        hll_precision: matches.get_flag("approximate").then_some(hll_precision),
+        bloom: matches.get_flag("bloom").then_some((bloom_size, bloom_hashes)),
+        bloom_output: matches.get_one::<String>("bloom_output").cloned(),
+        query: matches.get_one::<String>("query").cloned(),
+        sketch,
+        memory_cap,
+        max_distinct: given_max_distinct,
+        disk: disk.map(|dir| (dir, memory_limit.unwrap_or(DISK_BUFFER_BYTES))),
+        append_summary: matches.get_one::<String>("append_summary").cloned(),
+        name,
+        minimizer,
+        ends_only,
+        sample,
+        stride,
+        compare: matches.get_one::<String>("compare").cloned(),
+        set_op: matches.get_one::<String>("set_op").map(|op| match op.as_str() {
+            "intersection" => SetOp::Intersection,
+            "union" => SetOp::Union,
+            "a-only" => SetOp::AOnly,
+            _ => SetOp::BOnly,
+        }),
+        format,
+    };
+
+    if matches.get_flag("dry_run") {
+        if let Err(e) = dry_run(&options) {
+            eprintln!("Error: {}", e);
+            Exit::of(&e).exit();
         }
         return;
     }
 
-    match process_fasta_parallel(fasta_file, k, reserve_size, max_threads) {
-        Ok((kmer_count, nuc_count, nb_total_kmers, nb_valid_kmers)) => {
-            println!("Total nucleotides: {}", nuc_count);
-            println!("Total k-mers: {}", nb_total_kmers);
-            println!("Valid k-mers: {}", nb_valid_kmers);
-            println!("Number of distinct {}-mers: {}", k, kmer_count);
-        }
-        Err(e) => {
-            eprintln!("Error processing file: {}", e);
-            process::exit(1);
-        }
+    let hasher = matches.get_one::<String>("hasher").map(String::as_str);
+    let result = match (k <= u64::MAX_K, hasher) {
+        (true, Some("mix")) => run::<u64, _>(&counter, &options, KmerBuildHasher::new(seed)),
+        (true, _) => run::<u64, _>(&counter, &options, RandomState::new()),
+        (false, Some("mix")) => run::<u128, _>(&counter, &options, KmerBuildHasher::new(seed)),
+        (false, _) => run::<u128, _>(&counter, &options, RandomState::new()),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        Exit::of(&e).exit();
     }
 }
-
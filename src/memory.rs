@@ -0,0 +1,80 @@
+//! Memory used by a run, measured and estimated
+
+/// Peak resident set size of the process in bytes, read from /proc/self/status.
+/// None where it is not available (not Linux).
+pub fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    // VmHWM:     1234 kB
+    let kilobytes = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Memory available to new processes in bytes, read from /proc/meminfo: free memory and reclaimable caches.
+/// None where it is not available (not Linux).
+pub fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|line| line.starts_with("MemAvailable:"))?;
+    // MemAvailable:   1234 kB
+    let kilobytes = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Estimated size in bytes of a hash table holding `entries` entries of `entry_bytes` bytes.
+/// Tables keep at least 1/8 of their buckets free and have a power of two number of them,
+/// each bucket costing its entry plus a control byte.
+pub fn table_bytes(entries: usize, entry_bytes: usize) -> u64 {
+    let buckets = (entries.saturating_mul(8) / 7).max(4).next_power_of_two();
+    buckets as u64 * (entry_bytes as u64 + 1)
+}
+
+/// Largest number of entries of `entry_bytes` bytes whose hash table, as estimated by `table_bytes`,
+/// fits in `bytes` bytes
+pub fn max_table_entries(bytes: u64, entry_bytes: usize) -> usize {
+    let buckets = bytes / (entry_bytes as u64 + 1);
+    if buckets < 4 {
+        return 0;
+    }
+    // the largest power of two of buckets, with 1/8 of them free
+    ((1u64 << (63 - buckets.leading_zeros())) / 8 * 7) as usize
+}
+
+/// Size in bytes with a binary unit, e.g. "1.50 GiB"
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.2} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_estimate_and_units() {
+        // 1000 entries need 1143 buckets, rounded up to 2048
+        assert_eq!(table_bytes(1000, 8), 2048 * 9);
+        assert_eq!(table_bytes(0, 8), 4 * 9);
+        assert_eq!(max_table_entries(2048 * 9, 8), 1792);
+        assert_eq!(table_bytes(1792, 8), 2048 * 9);
+        assert_eq!(max_table_entries(4096 * 9 - 1, 8), 1792);
+        assert_eq!(max_table_entries(30, 8), 0);
+        assert_eq!(format_bytes(1000), "1000 B");
+        assert_eq!(format_bytes(3 << 29), "1.50 GiB");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn available_memory_is_read() {
+        assert!(available_memory().is_some_and(|bytes| bytes > 0));
+    }
+}
@@ -0,0 +1,222 @@
+//! Bottom-s MinHash sketches of k-mer sets, to estimate their Jaccard index from a few hashes, as Mash does.
+//!
+//! A sketch keeps the `size` smallest hashes of the distinct k-mers. The hash is `Kmer::hash64`, which does
+//! not depend on the run or the machine, so that sketches of separate runs can be compared.
+//!
+//! Sketch files are text: a header line `#ukc-sketch k=<k> strand=<strand> size=<size>`, then the hashes
+//! in increasing order, one per line.
+
+use crate::kmer::Strand;
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+const HEADER: &str = "#ukc-sketch";
+
+/// Smallest hashes inserted, up to `size` of them.
+/// Hashes above the largest kept are rejected without locking once the sketch is full.
+#[derive(Debug)]
+pub struct MinHash {
+    size: usize,
+    hashes: Mutex<BTreeSet<u64>>,
+    /// Largest hash kept when the sketch is full, u64::MAX before
+    threshold: AtomicU64,
+}
+
+impl MinHash {
+    /// Panics if `size` is 0
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "MinHash sketches must keep at least one hash");
+        MinHash { size, hashes: Mutex::new(BTreeSet::new()), threshold: AtomicU64::new(u64::MAX) }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Inserts an element given its 64-bit hash
+    pub fn insert_hash(&self, hash: u64) {
+        if hash >= self.threshold.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut hashes = self.hashes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if hashes.insert(hash) && hashes.len() > self.size {
+            hashes.pop_last();
+            self.threshold.store(*hashes.last().unwrap(), Ordering::Relaxed);
+        }
+    }
+
+    /// Hashes kept, in increasing order
+    pub fn hashes(&self) -> Vec<u64> {
+        self.hashes.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter().copied().collect()
+    }
+
+    /// Number of hashes kept, less than `size` for sets of fewer elements
+    pub fn len(&self) -> usize {
+        self.hashes.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Estimated number of distinct elements inserted: exact until the sketch is full,
+    /// then (size - 1) / x, x being the largest hash kept as a fraction of the hash range
+    pub fn estimate(&self) -> f64 {
+        let hashes = self.hashes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match hashes.last() {
+            Some(&largest) if hashes.len() == self.size && self.size > 1 => {
+                (self.size - 1) as f64 / ((largest as f64 + 1.0) / 2f64.powi(64))
+            }
+            _ => hashes.len() as f64,
+        }
+    }
+}
+
+/// Sketch of the k-mers of size `k` counted on `strand`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sketch {
+    pub k: usize,
+    pub strand: Strand,
+    /// Maximum number of hashes
+    pub size: usize,
+    /// Smallest hashes of the k-mers, in increasing order
+    pub hashes: Vec<u64>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid sketch: {}", message))
+}
+
+impl Sketch {
+    pub fn write(&self, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "{} k={} strand={} size={}", HEADER, self.k, self.strand, self.size)?;
+        for hash in &self.hashes {
+            writeln!(out, "{}", hash)?;
+        }
+        out.flush()
+    }
+
+    pub fn read(input: impl BufRead) -> io::Result<Self> {
+        let mut lines = input.lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let mut fields = header.split_whitespace();
+        if fields.next() != Some(HEADER) {
+            return Err(invalid("no #ukc-sketch header".to_string()));
+        }
+        let mut field = |name: &str| {
+            fields
+                .next()
+                .and_then(|field| field.strip_prefix(name)?.strip_prefix('='))
+                .map(str::to_string)
+                .ok_or_else(|| invalid(format!("no {} in the header", name)))
+        };
+        let number = |value: String| value.parse::<usize>().map_err(|_| invalid(format!("{} is not a number", value)));
+        let k = number(field("k")?)?;
+        let strand = match field("strand")?.as_str() {
+            "forward" => Strand::Forward,
+            "canonical" => Strand::Canonical,
+            "both" => Strand::Both,
+            other => return Err(invalid(format!("unknown strand mode {}", other))),
+        };
+        let size = number(field("size")?)?;
+        let hashes = lines
+            .map(|line| line?.trim().parse::<u64>().map_err(|_| invalid("hashes must be integers".to_string())))
+            .collect::<io::Result<Vec<u64>>>()?;
+        if hashes.len() > size || !hashes.windows(2).all(|pair| pair[0] < pair[1]) {
+            return Err(invalid("hashes must be increasing, and at most size of them".to_string()));
+        }
+        Ok(Sketch { k, strand, size, hashes })
+    }
+
+    /// Estimated Jaccard index of the k-mer sets of two sketches, from the smallest hashes of their union:
+    /// the fraction of them found in both. Fails if the sketches are of different k or strand modes.
+    pub fn jaccard(&self, other: &Sketch) -> io::Result<f64> {
+        if (self.k, self.strand) != (other.k, other.strand) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "sketches of {}-mers with strand mode {} and {}-mers with strand mode {} cannot be compared",
+                    self.k, self.strand, other.k, other.strand
+                ),
+            ));
+        }
+        let size = self.size.min(other.size);
+        let (mut a, mut b) = (self.hashes.iter().peekable(), other.hashes.iter().peekable());
+        let (mut union, mut shared) = (0, 0);
+        while union < size {
+            match (a.peek(), b.peek()) {
+                (Some(x), Some(y)) if x == y => {
+                    shared += 1;
+                    a.next();
+                    b.next();
+                }
+                (Some(x), Some(y)) if x < y => {
+                    a.next();
+                }
+                (Some(_), Some(_)) => {
+                    b.next();
+                }
+                (Some(_), None) => {
+                    a.next();
+                }
+                (None, Some(_)) => {
+                    b.next();
+                }
+                (None, None) => break,
+            }
+            union += 1;
+        }
+        Ok(if union == 0 { 0.0 } else { shared as f64 / union as f64 })
+    }
+}
+
+/// Mash distance of k-mer sets of Jaccard index `jaccard`, estimating the mutation rate between their sequences
+pub fn mash_distance(jaccard: f64, k: usize) -> f64 {
+    if jaccard <= 0.0 {
+        return 1.0;
+    }
+    (((1.0 + jaccard) / (2.0 * jaccard)).ln() / k as f64).min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_smallest_hashes() {
+        let minhash = MinHash::new(3);
+        for hash in [50, 10, 40, 10, 30, 20, 60] {
+            minhash.insert_hash(hash);
+        }
+        assert_eq!(minhash.hashes(), [10, 20, 30]);
+
+        // uniform hashes: the estimate is within a few standard errors, 1/sqrt(size - 2)
+        let minhash = MinHash::new(1000);
+        for i in 0..100_000u64 {
+            minhash.insert_hash(crate::kmer::Kmer::hash64(i));
+        }
+        assert!((minhash.estimate() / 100_000.0 - 1.0).abs() < 0.15);
+    }
+
+    #[test]
+    fn jaccard_of_sketches() {
+        let sketch = |hashes: &[u64]| Sketch { k: 21, strand: Strand::Canonical, size: 4, hashes: hashes.to_vec() };
+        let a = sketch(&[1, 2, 3, 4]);
+        assert_eq!(a.jaccard(&a).unwrap(), 1.0);
+        // the union starts with 1, 2, 3, 4, of which 2 and 4 are shared
+        assert_eq!(a.jaccard(&sketch(&[2, 4, 5, 6])).unwrap(), 0.5);
+        assert_eq!(a.jaccard(&sketch(&[5, 6])).unwrap(), 0.0);
+        assert_eq!(mash_distance(1.0, 21), 0.0);
+        assert_eq!(mash_distance(0.0, 21), 1.0);
+        let other_k = Sketch { k: 31, ..a.clone() };
+        assert!(a.jaccard(&other_k).is_err());
+
+        let mut file = Vec::new();
+        a.write(&mut file).unwrap();
+        assert!(file.starts_with(b"#ukc-sketch k=21 strand=canonical size=4\n1\n"));
+        assert_eq!(Sketch::read(&file[..]).unwrap(), a);
+        assert!(Sketch::read(&b"1\n2\n"[..]).is_err());
+    }
+}
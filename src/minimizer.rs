@@ -0,0 +1,97 @@
+//! Sliding window minimum of k-mers, for minimizer counting
+
+use std::collections::VecDeque;
+
+/// Minimizers of the windows of `w` consecutive k-mers, computed with a monotonic deque
+/// in amortized constant time per k-mer
+#[derive(Clone, Debug)]
+pub struct MinimizerWindow<T> {
+    w: usize,
+    /// Candidates (position, k-mer), increasing in both position and k-mer
+    candidates: VecDeque<(usize, T)>,
+    /// Position of the last k-mer pushed
+    last_position: Option<usize>,
+    /// Number of consecutive k-mers pushed
+    run: usize,
+    /// Position of the last minimizer returned
+    last_minimizer: Option<usize>,
+}
+
+impl<T: Copy + Ord> MinimizerWindow<T> {
+    /// Panics if `w` is 0
+    pub fn new(w: usize) -> Self {
+        assert!(w > 0, "minimizer windows must contain at least one k-mer");
+        MinimizerWindow {
+            w,
+            candidates: VecDeque::with_capacity(w),
+            last_position: None,
+            run: 0,
+            last_minimizer: None,
+        }
+    }
+
+    /// Adds the k-mer at `position`, a gap since the previous one restarting the windows.
+    /// Returns the minimizer of the window ending at `position` if it was not already returned.
+    /// Ties are broken by keeping the leftmost k-mer.
+    pub fn push(&mut self, position: usize, kmer: T) -> Option<T> {
+        if self.last_position.is_none_or(|last| last + 1 != position) {
+            self.candidates.clear();
+            self.run = 0;
+        }
+        self.last_position = Some(position);
+        self.run += 1;
+
+        while self.candidates.back().is_some_and(|&(_, candidate)| candidate > kmer) {
+            self.candidates.pop_back();
+        }
+        self.candidates.push_back((position, kmer));
+        while self.candidates.front().is_some_and(|&(start, _)| start + self.w <= position) {
+            self.candidates.pop_front();
+        }
+
+        if self.run < self.w {
+            return None;
+        }
+        let (minimizer_position, minimizer) = self.candidates[0];
+        if self.last_minimizer == Some(minimizer_position) {
+            return None;
+        }
+        self.last_minimizer = Some(minimizer_position);
+        Some(minimizer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_naive_minimum() {
+        let kmers: Vec<u64> = (0..200u64).map(|i| crate::kmer::mix64(i) % 50).collect();
+        for w in [1, 2, 5, 17] {
+            let mut window = MinimizerWindow::new(w);
+            let minimizers: Vec<u64> = kmers.iter().enumerate().filter_map(|(i, &kmer)| window.push(i, kmer)).collect();
+
+            let mut expected = Vec::new();
+            let mut last = None;
+            for end in w - 1..kmers.len() {
+                let start = end + 1 - w;
+                let (position, minimizer) = (start..=end).map(|i| (i, kmers[i])).min_by_key(|&(i, kmer)| (kmer, i)).unwrap();
+                if last != Some(position) {
+                    expected.push(minimizer);
+                    last = Some(position);
+                }
+            }
+            assert_eq!(minimizers, expected, "w = {}", w);
+        }
+    }
+
+    #[test]
+    fn gap_restarts_windows() {
+        let mut window = MinimizerWindow::new(2);
+        assert_eq!(window.push(0, 5), None);
+        assert_eq!(window.push(1, 3), Some(3));
+        assert_eq!(window.push(3, 1), None);
+        assert_eq!(window.push(4, 2), Some(1));
+    }
+}
@@ -0,0 +1,84 @@
+//! Reader of raw sequences, one per line, without headers.
+//!
+//! Each non-empty line is a record, named after its line number (`line1`, `line2`...). Blank lines are skipped,
+//! and line ends may be `\n` or `\r\n`. Such an input is recognized by its first line, made of nucleotides and
+//! IUPAC codes only, in either case: other text, and sequences of other alphabets, are not.
+
+use crate::kmer::iupac_nucleotides;
+use fxread::Record;
+use std::io::{self, BufRead};
+
+/// Whether the first line of `bytes` is a sequence: nucleotides and IUPAC codes only, possibly ending with `\r`
+pub fn is_raw(bytes: &[u8]) -> bool {
+    let line = bytes.split(|&byte| byte == b'\n').next().unwrap_or_default();
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    !line.is_empty() && line.iter().all(|&n| iupac_nucleotides(n).is_some())
+}
+
+/// Lines of raw sequences, as FASTA records
+pub struct RawRecords<R> {
+    reader: R,
+    /// Number of the last line read
+    line: usize,
+    buffer: Vec<u8>,
+}
+
+impl<R: BufRead> RawRecords<R> {
+    pub fn new(reader: R) -> Self {
+        RawRecords { reader, line: 0, buffer: Vec::new() }
+    }
+}
+
+impl<R: BufRead> Iterator for RawRecords<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buffer.clear();
+            match self.reader.read_until(b'\n', &mut self.buffer) {
+                Ok(0) => return None,
+                Ok(_) => self.line += 1,
+                Err(e) => return Some(Err(e)),
+            }
+            let seq = self.buffer.trim_ascii_end();
+            if !seq.is_empty() {
+                let id = format!("line{}", self.line);
+                return Some(Record::new_fasta_from_parts(id.as_bytes(), seq).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KmerCounter, KmerStore};
+    use dashmap::DashSet;
+
+    #[test]
+    fn lines_are_records() {
+        let raw = b"ACGTACGT\r\n\nacgtt\n  \nGGA";
+        assert!(is_raw(raw));
+        assert!(is_raw(b"acgtNRYu\nSequences\n"));
+        assert!(!is_raw(b">a\nACGT\n") && !is_raw(b"\nACGT\n") && !is_raw(b"not a FASTA file\n"));
+        assert!(!is_raw(b"Sequences\nACGT\n") && !is_raw(b"MKVLAAGIW\n"));
+        let records: Vec<Record> = RawRecords::new(&raw[..]).map(Result::unwrap).collect();
+        let ids: Vec<&[u8]> = records.iter().map(|record| record.id()).collect();
+        assert_eq!(ids, [&b"line1"[..], b"line3", b"line5"]);
+        let seqs: Vec<&[u8]> = records.iter().map(|record| record.seq()).collect();
+        assert_eq!(seqs, [&b"ACGTACGT"[..], b"acgtt", b"GGA"]);
+    }
+
+    #[test]
+    fn counts_match_the_equivalent_fasta() {
+        let counter = KmerCounter::new(3).canonical(true);
+        let count = |input: &[u8]| {
+            let kmers = DashSet::<u64>::new();
+            let stats = counter.count_reader(input, KmerStore::Set(&kmers)).unwrap();
+            let mut kmers: Vec<u64> = kmers.into_iter().collect();
+            kmers.sort_unstable();
+            (stats, kmers)
+        };
+        assert_eq!(count(b"ACGTTGCA\n\nTTTNGCA\n\n"), count(b">a\nACGTTGCA\n>b\nTTTNGCA\n"));
+    }
+}
@@ -0,0 +1,125 @@
+//! Results of a run, printed as text or JSON.
+//!
+//! The text lines have friendly labels by default. With `machine`, they are the keys and values of the JSON format
+//! instead, nested values being flattened to dotted keys (`files.0.total_nucleotides`) and null values left out.
+
+use serde_json::{Map, Value};
+use std::fmt::Display;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Lines of a label or key, `separator` and a value
+    Text { separator: &'static str, machine: bool },
+    Json,
+    /// JSON, the statistics of each record of --per-record being JSON lines too: the report is the last line
+    JsonLines,
+}
+
+struct Entry {
+    /// Key in JSON format; text-only entries have an empty one
+    key: &'static str,
+    /// Label and value printed in text format; JSON-only entries have none
+    text: Option<(String, String)>,
+    json: Value,
+}
+
+/// Ordered statistics, with optional per-file sub-reports
+#[derive(Default)]
+pub struct Report {
+    entries: Vec<Entry>,
+    files: Vec<(String, Report)>,
+}
+
+impl Report {
+    /// Adds a value printed as is in text format
+    pub fn add<V: Display + Into<Value>>(&mut self, key: &'static str, label: impl Into<String>, value: V) {
+        let text = value.to_string();
+        self.add_formatted(key, label, text, value);
+    }
+
+    /// Adds a value with a custom text representation
+    pub fn add_formatted(&mut self, key: &'static str, label: impl Into<String>, text: String, value: impl Into<Value>) {
+        self.entries.push(Entry {
+            key,
+            text: Some((label.into(), text)),
+            json: value.into(),
+        });
+    }
+
+    /// Adds a value only present in text format
+    pub fn add_text(&mut self, label: impl Into<String>, value: impl Display) {
+        self.entries.push(Entry {
+            key: "",
+            text: Some((label.into(), value.to_string())),
+            json: Value::Null,
+        });
+    }
+
+    /// Adds a value only present in JSON format
+    pub fn add_json(&mut self, key: &'static str, value: impl Into<Value>) {
+        self.entries.push(Entry {
+            key,
+            text: None,
+            json: value.into(),
+        });
+    }
+
+    pub fn add_file(&mut self, file: &str, report: Report) {
+        self.files.push((file.to_string(), report));
+    }
+
+    fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        for entry in self.entries.iter().filter(|entry| !entry.key.is_empty()) {
+            object.insert(entry.key.to_string(), entry.json.clone());
+        }
+        if !self.files.is_empty() {
+            let files = self
+                .files
+                .iter()
+                .map(|(file, report)| {
+                    let mut file_object = Map::new();
+                    file_object.insert("file".to_string(), Value::from(file.as_str()));
+                    if let Value::Object(entries) = report.to_json() {
+                        file_object.extend(entries);
+                    }
+                    Value::Object(file_object)
+                })
+                .collect();
+            object.insert("files".to_string(), Value::Array(files));
+        }
+        Value::Object(object)
+    }
+
+    fn print_text(&self, indent: &str, separator: &str) {
+        for (file, report) in &self.files {
+            println!("{}File {}:", indent, file);
+            report.print_text(&format!("{}  ", indent), separator);
+        }
+        for entry in &self.entries {
+            if let Some((label, text)) = &entry.text {
+                println!("{}{}{}{}", indent, label, separator, text);
+            }
+        }
+    }
+
+    pub fn print(&self, format: Format) {
+        match format {
+            Format::Text { separator, machine: false } => self.print_text("", separator),
+            Format::Text { separator, machine: true } => print_flattened("", &self.to_json(), separator),
+            Format::Json | Format::JsonLines => println!("{}", self.to_json()),
+        }
+    }
+}
+
+/// Prints the leaves of a JSON value as `key`, `separator` and value lines, strings without quotes
+fn print_flattened(key: &str, value: &Value, separator: &str) {
+    let child = |name: &dyn Display| if key.is_empty() { name.to_string() } else { format!("{}.{}", key, name) };
+    match value {
+        Value::Null => {}
+        Value::Object(object) => object.iter().for_each(|(name, value)| print_flattened(&child(name), value, separator)),
+        Value::Array(array) => array.iter().enumerate().for_each(|(i, value)| print_flattened(&child(&i), value, separator)),
+        Value::String(text) => println!("{}{}{}", key, separator, text),
+        _ => println!("{}{}{}", key, separator, value),
+    }
+}
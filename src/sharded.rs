@@ -0,0 +1,104 @@
+//! Set of distinct k-mers split into independent shards by k-mer prefix.
+//!
+//! Each shard is a plain `HashSet` behind its own lock, selected by the first nucleotides of the k-mer,
+//! so threads inserting k-mers of different prefixes never contend.
+//! Canonical k-mers and minimizers favour small prefixes, which makes the shards less balanced.
+
+use crate::kmer::Kmer;
+use crate::KmerHashing;
+use std::collections::HashSet;
+use std::hash::RandomState;
+use std::sync::{Mutex, MutexGuard};
+
+/// Distinct k-mers, in a power of two number of shards keyed by the top bits of their encoding
+#[derive(Debug)]
+pub struct ShardedSet<T, S = RandomState> {
+    k: usize,
+    /// Number of prefix bits selecting the shard
+    bits: u32,
+    shards: Vec<Mutex<HashSet<T, S>>>,
+}
+
+impl<T: Kmer, S: KmerHashing> ShardedSet<T, S> {
+    /// Set of `k`-mers with at least `shards` shards, rounded up to a power of two
+    /// but at most 4^k, sharing a `capacity` reserved up front
+    pub fn with_capacity_and_hasher(shards: usize, k: usize, capacity: usize, hasher: S) -> Self {
+        let bits = shards.max(1).next_power_of_two().trailing_zeros().min(2 * k as u32);
+        let capacity = capacity >> bits;
+        ShardedSet {
+            k,
+            bits,
+            shards: (0..1usize << bits)
+                .map(|_| Mutex::new(HashSet::with_capacity_and_hasher(capacity, hasher.clone())))
+                .collect(),
+        }
+    }
+
+    fn lock(&self, i: usize) -> MutexGuard<'_, HashSet<T, S>> {
+        // a thread panicking while inserting leaves the set consistent
+        self.shards[i].lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn shard(&self, kmer: T) -> MutexGuard<'_, HashSet<T, S>> {
+        self.lock(kmer.prefix(self.k, self.bits))
+    }
+
+    /// Adds a k-mer, returning whether it was absent
+    pub fn insert(&self, kmer: T) -> bool {
+        self.shard(kmer).insert(kmer)
+    }
+
+    pub fn contains(&self, kmer: &T) -> bool {
+        self.shard(*kmer).contains(kmer)
+    }
+
+    /// Number of distinct k-mers, the sum of the sizes of the shards
+    pub fn len(&self) -> usize {
+        (0..self.shards.len()).map(|i| self.lock(i).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Hasher of the shards, for another set of the same k-mers
+    pub fn hasher(&self) -> S {
+        self.lock(0).hasher().clone()
+    }
+
+    /// The k-mers, shard after shard. Each shard is copied while it is iterated,
+    /// so that it stays unlocked.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.shards.len()).flat_map(|i| self.lock(i).iter().copied().collect::<Vec<T>>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shards_by_prefix() {
+        let kmers = ShardedSet::<u64>::with_capacity_and_hasher(5, 3, 0, RandomState::new());
+        assert_eq!(kmers.shard_count(), 8);
+        for kmer in ["AAA", "AAC", "CAA", "TTT", "AAA"] {
+            kmers.insert(u64::encode(kmer.as_bytes()).unwrap());
+        }
+        assert_eq!(kmers.len(), 4);
+        assert!(kmers.contains(&u64::encode(b"CAA").unwrap()));
+        assert!(!kmers.contains(&u64::encode(b"GAA").unwrap()));
+        // AAA and AAC share their first 1.5 nucleotides
+        assert_eq!(kmers.lock(0).len(), 2);
+        assert_eq!(kmers.iter().count(), 4);
+
+        // at most 4^k shards, and a single one for k = 32 still works
+        assert_eq!(ShardedSet::<u64>::with_capacity_and_hasher(100, 2, 0, RandomState::new()).shard_count(), 16);
+        let single = ShardedSet::<u64>::with_capacity_and_hasher(1, 32, 0, RandomState::new());
+        single.insert(u64::MAX);
+        assert_eq!(single.len(), 1);
+    }
+}
@@ -0,0 +1,243 @@
+//! Reader of UCSC .2bit genome files.
+//!
+//! The file starts with a 16-byte header: the signature 0x1A412743 (whose byte order gives that of the file),
+//! the version (0, or 1 for 64-bit offsets), the number of sequences and a reserved word. An index follows,
+//! each sequence having a name (its length on one byte, then its bytes) and the offset of its data.
+//! The data of a sequence is its length, its N blocks and soft-masked blocks (counts, then starts, then sizes),
+//! a reserved word, and the nucleotides on 2 bits each (T = 00, C = 01, A = 10, G = 11), first in the high bits.
+//!
+//! Sequences are decoded to the bytes of the equivalent FASTA: N in N blocks, lowercase in masked blocks.
+//! They are read in the order of their data, skipping forward, so that the input needs no seeking.
+
+use fxread::Record;
+use std::cmp::Reverse;
+use std::io::{self, Read};
+
+pub const SIGNATURE: u32 = 0x1A41_2743;
+
+/// Nucleotides of the 2-bit codes
+const NUCLEOTIDES: [u8; 4] = *b"TCAG";
+
+/// Whether `bytes` start with the signature of a .2bit file, in either byte order
+pub fn is_two_bit(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && {
+        let signature = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        u32::from_le_bytes(signature) == SIGNATURE || u32::from_be_bytes(signature) == SIGNATURE
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid .2bit file: {}", message))
+}
+
+/// Reader of integers in the byte order of the file, keeping track of the position
+struct Input<R> {
+    reader: R,
+    big_endian: bool,
+    position: u64,
+}
+
+impl<R: Read> Input<R> {
+    fn read_bytes(&mut self, bytes: &mut [u8]) -> io::Result<()> {
+        self.reader.read_exact(bytes).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid("truncated"),
+            _ => e,
+        })?;
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Reads `length` bytes, allocating no more than those found in the input: a corrupt length fails as truncated
+    fn read_vec(&mut self, length: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        (&mut self.reader).take(length as u64).read_to_end(&mut bytes)?;
+        self.position += bytes.len() as u64;
+        if bytes.len() < length {
+            return Err(invalid("truncated"));
+        }
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        self.read_bytes(&mut bytes)?;
+        Ok(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn read_u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        self.read_bytes(&mut bytes)?;
+        Ok(if self.big_endian { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) })
+    }
+
+    fn read_u32s(&mut self, count: usize) -> io::Result<Vec<u32>> {
+        (0..count).map(|_| self.read_u32()).collect()
+    }
+
+    fn skip_to(&mut self, offset: u64) -> io::Result<()> {
+        if offset < self.position {
+            return Err(invalid("overlapping sequences"));
+        }
+        let skipped = io::copy(&mut (&mut self.reader).take(offset - self.position), &mut io::sink())?;
+        self.position += skipped;
+        if self.position < offset {
+            return Err(invalid("truncated"));
+        }
+        Ok(())
+    }
+}
+
+/// Sequences of a .2bit file, as FASTA records
+pub struct TwoBitRecords<R> {
+    input: Input<R>,
+    /// Names and data offsets of the sequences left, by decreasing offset
+    index: Vec<(Vec<u8>, u64)>,
+}
+
+impl<R: Read> TwoBitRecords<R> {
+    /// Reads the header and the index of a .2bit file
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut input = Input { reader, big_endian: false, position: 0 };
+        let mut signature = [0; 4];
+        input.read_bytes(&mut signature)?;
+        if !is_two_bit(&signature) {
+            return Err(invalid("bad signature"));
+        }
+        input.big_endian = u32::from_be_bytes(signature) == SIGNATURE;
+        let version = input.read_u32()?;
+        if version > 1 {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        let count = input.read_u32()?;
+        input.read_u32()?;
+        let mut index = Vec::new();
+        for _ in 0..count {
+            let mut length = [0];
+            input.read_bytes(&mut length)?;
+            let mut name = vec![0; length[0] as usize];
+            input.read_bytes(&mut name)?;
+            let offset = if version == 1 { input.read_u64()? } else { input.read_u32()? as u64 };
+            index.push((name, offset));
+        }
+        index.sort_by_key(|&(_, offset)| Reverse(offset));
+        Ok(TwoBitRecords { input, index })
+    }
+
+    fn read_record(&mut self, name: &[u8], offset: u64) -> io::Result<Record> {
+        let input = &mut self.input;
+        input.skip_to(offset)?;
+        let length = input.read_u32()? as usize;
+        let n_count = input.read_u32()? as usize;
+        let n_starts = input.read_u32s(n_count)?;
+        let n_sizes = input.read_u32s(n_count)?;
+        let mask_count = input.read_u32()? as usize;
+        let mask_starts = input.read_u32s(mask_count)?;
+        let mask_sizes = input.read_u32s(mask_count)?;
+        input.read_u32()?;
+        let packed = input.read_vec(length.div_ceil(4))?;
+
+        let mut seq: Vec<u8> = packed
+            .iter()
+            .flat_map(|&byte| [6, 4, 2, 0].map(|shift| NUCLEOTIDES[(byte >> shift & 0b11) as usize]))
+            .take(length)
+            .collect();
+        let block = |start: u32, size: u32| {
+            let start = (start as usize).min(length);
+            start..(start + size as usize).min(length)
+        };
+        for (&start, &size) in n_starts.iter().zip(&n_sizes) {
+            seq[block(start, size)].fill(b'N');
+        }
+        for (&start, &size) in mask_starts.iter().zip(&mask_sizes) {
+            seq[block(start, size)].make_ascii_lowercase();
+        }
+        Record::new_fasta_from_parts(name, &seq).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+}
+
+impl<R: Read> Iterator for TwoBitRecords<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (name, offset) = self.index.pop()?;
+        let record = self.read_record(&name, offset);
+        if record.is_err() {
+            // the position in the input is unknown after an error
+            self.index.clear();
+        }
+        Some(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Name, nucleotides, N blocks and mask blocks (start, size) of a sequence
+    type Sequence<'a> = (&'a str, &'a [u8], &'a [(u32, u32)], &'a [(u32, u32)]);
+
+    /// .2bit file of the given sequences, in little-endian
+    fn two_bit_file(sequences: &[Sequence]) -> Vec<u8> {
+        let words = |values: &[u32]| values.iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<u8>>();
+        let index_size: usize = sequences.iter().map(|(name, ..)| 1 + name.len() + 4).sum();
+        let mut data = Vec::new();
+        let mut index = Vec::new();
+        for (name, seq, n_blocks, mask_blocks) in sequences {
+            index.push(name.len() as u8);
+            index.extend(name.as_bytes());
+            index.extend(words(&[(16 + index_size + data.len()) as u32]));
+            data.extend(words(&[seq.len() as u32, n_blocks.len() as u32]));
+            data.extend(words(&n_blocks.iter().map(|block| block.0).collect::<Vec<u32>>()));
+            data.extend(words(&n_blocks.iter().map(|block| block.1).collect::<Vec<u32>>()));
+            data.extend(words(&[mask_blocks.len() as u32]));
+            data.extend(words(&mask_blocks.iter().map(|block| block.0).collect::<Vec<u32>>()));
+            data.extend(words(&mask_blocks.iter().map(|block| block.1).collect::<Vec<u32>>()));
+            data.extend(words(&[0]));
+            for chunk in seq.chunks(4) {
+                let code = |n: &u8| NUCLEOTIDES.iter().position(|m| m == n).unwrap() as u8;
+                data.push(chunk.iter().enumerate().fold(0, |byte, (i, n)| byte | code(n) << (6 - 2 * i)));
+            }
+        }
+        let mut file = words(&[SIGNATURE, 0, sequences.len() as u32, 0]);
+        file.extend(index);
+        file.extend(data);
+        file
+    }
+
+    #[test]
+    fn decodes_sequences_with_n_and_mask_blocks() {
+        let file = two_bit_file(&[
+            ("chr1", b"ACGTACGTTG", &[(2, 3)], &[(6, 10)]),
+            ("chr2", b"GGA", &[], &[]),
+        ]);
+        assert!(is_two_bit(&file));
+        let records: Vec<Record> = TwoBitRecords::new(&file[..]).unwrap().map(Result::unwrap).collect();
+        assert_eq!(records[0].id(), b"chr1");
+        assert_eq!(records[0].seq(), b"ACNNNCgttg");
+        assert_eq!(records[1].seq(), b"GGA");
+        assert!(TwoBitRecords::new(&file[..file.len() - 1]).unwrap().any(|record| record.is_err()));
+        assert!(TwoBitRecords::new(&file[..30]).is_err());
+    }
+
+    #[test]
+    fn corrupt_lengths_fail_as_truncated() {
+        let mut file = two_bit_file(&[("chr", b"GGA", &[], &[])]);
+        // the data follows the 16-byte header and the index entry of 1 + 3 + 4 bytes
+        file[24..28].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = TwoBitRecords::new(&file[..]).unwrap().next().unwrap().unwrap_err();
+        assert_eq!(error.to_string(), "invalid .2bit file: truncated");
+    }
+
+    #[test]
+    fn counts_match_the_equivalent_fasta() {
+        let file = two_bit_file(&[("a", b"ACGTTGCATGCAAACGT", &[(5, 2)], &[(0, 4)]), ("b", b"TTTTGCAACG", &[], &[(3, 2)])]);
+        let fasta = b">a\nacgtTNNATGCAAACGT\n>b\nTTTtgCAACG\n";
+        for case_sensitive in [false, true] {
+            let counter = crate::KmerCounter::new(4).case_sensitive(case_sensitive);
+            let kmers = dashmap::DashSet::<u64>::new();
+            let expected = counter.count_reader(&fasta[..], crate::KmerStore::Set(&kmers)).unwrap();
+            let kmers = dashmap::DashSet::<u64>::new();
+            assert_eq!(counter.count_reader(&file[..], crate::KmerStore::Set(&kmers)).unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,924 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+const DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data");
+
+/// Runs the binary with the given arguments and returns its stdout
+fn run(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(args)
+        .output()
+        .expect("failed to run unique_kmer_counter");
+    assert!(
+        output.status.success(),
+        "unique_kmer_counter {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Runs the binary reading its input from stdin and returns its stdout
+fn run_stdin(args: &[&str], input: &[u8]) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run unique_kmer_counter");
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "unique_kmer_counter {:?} failed", args);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn distinct_kmers(file: &str, k: usize) -> String {
+    let path = format!("{}/{}", DATA, file);
+    run(&["-k", &k.to_string(), "-f", &path, "-r", "1000"])
+        .lines()
+        .find(|line| line.starts_with("Number of distinct"))
+        .expect("no distinct k-mer line in output")
+        .to_string()
+}
+
+#[test]
+fn gzipped_input_gives_same_counts() {
+    for k in [5, 21, 40] {
+        let plain = distinct_kmers("sample.fa", k);
+        assert_eq!(plain, distinct_kmers("sample.fa.gz", k));
+    }
+}
+
+#[test]
+fn gzip_is_detected_from_content() {
+    // sample_gzipped.fa is gzip-compressed despite its extension
+    assert_eq!(
+        distinct_kmers("sample.fa", 21),
+        distinct_kmers("sample_gzipped.fa", 21)
+    );
+}
+
+#[test]
+fn stdin_gives_same_output_as_file() {
+    let file_output = run(&["-k", "21", "-f", &format!("{}/sample.fa", DATA), "-r", "1000"]);
+    for file in ["sample.fa", "sample.fa.gz"] {
+        let input = fs::read(format!("{}/{}", DATA, file)).unwrap();
+        assert_eq!(file_output, run_stdin(&["-k", "21", "-f", "-", "-r", "1000"], &input));
+    }
+}
+
+#[test]
+fn json_statistics() {
+    let path = format!("{}/sample.fa", DATA);
+    let stats: serde_json::Value = serde_json::from_str(&run(&["-k", "21", "-f", &path, "-r", "1000", "--format", "json"])).unwrap();
+    assert_eq!(stats["k"], 21);
+    assert_eq!(stats["canonical"], false);
+    assert_eq!(stats["total_nucleotides"], 466);
+    assert_eq!(stats["distinct_kmers"], 366);
+    // only reported after a Ctrl-C
+    assert!(stats.get("interrupted").is_none());
+
+    let stats: serde_json::Value = serde_json::from_str(&run(&["-k", "21", "-f", &path, "-c", "--format", "json"])).unwrap();
+    assert_eq!(stats["valid_kmers"], 366);
+    assert!(stats["distinct_kmers"].is_null());
+}
+
+#[test]
+fn machine_statistics_share_their_keys_between_modes() {
+    let path = format!("{}/sample.fa", DATA);
+    let full = run(&["-k", "21", "-f", &format!("{},{}", path, path), "-r", "1000", "--machine", "--stats-sep", "tab"]);
+    let only_count = run(&["-k", "21", "-f", &format!("{},{}", path, path), "-c", "--machine", "--stats-sep", "tab"]);
+    assert!(full.contains("\ntotal_nucleotides\t932\n"));
+    assert!(full.contains("\ndistinct_kmers\t366\n"));
+    assert!(full.contains("\nfiles.1.new_distinct_kmers\t0\n"));
+    // the same lines, but for the distinct k-mers
+    let shared: Vec<&str> = full.lines().filter(|line| !line.contains("distinct_kmers")).collect();
+    assert_eq!(only_count.lines().collect::<Vec<&str>>(), shared);
+
+    let friendly = run(&["-k", "21", "-f", &path, "-r", "1000", "--stats-sep", "tab"]);
+    assert!(friendly.contains("\nTotal nucleotides\t466\n"));
+}
+
+#[test]
+fn per_record_statistics() {
+    let path = format!("{}/sample.fa", DATA);
+    let output = run(&["-k", "21", "-f", &path, "-r", "1000", "--per-record", "-"]);
+    let mut lines = output.lines();
+    assert_eq!(lines.next(), Some("record_id\tlength\ttotal_kmers\tvalid_kmers\tdistinct_kmers"));
+    assert_eq!(lines.next(), Some("seq0\t100\t80\t80\t80"));
+    let lengths: usize = lines
+        .take_while(|line| line.contains('\t'))
+        .map(|line| line.split('\t').nth(1).unwrap().parse::<usize>().unwrap())
+        .sum();
+    assert_eq!(lengths, 466 - 100);
+}
+
+#[test]
+fn per_record_json_lines_end_with_the_summary() {
+    let path = format!("{}/sample.fa", DATA);
+    let output = run(&["-k", "21", "-f", &path, "-r", "1000", "--per-record", "-", "--format", "jsonl"]);
+    let lines: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines[0], serde_json::json!({"id": "seq0", "length": 100, "total_kmers": 80, "valid_kmers": 80, "distinct_kmers": 80}));
+    let (summary, records) = lines.split_last().unwrap();
+    assert_eq!(records.iter().map(|record| record["length"].as_u64().unwrap()).sum::<u64>(), 466);
+    assert_eq!(summary["total_nucleotides"], 466);
+}
+
+#[test]
+fn compare_with_same_content_gives_jaccard_one() {
+    let path = format!("{}/sample.fa", DATA);
+    let other = format!("{}/sample.fa.gz", DATA);
+    let stats: serde_json::Value =
+        serde_json::from_str(&run(&["-k", "21", "-f", &path, "-r", "1000", "--compare", &other, "--format", "json"])).unwrap();
+    assert_eq!(stats["intersection"], 366);
+    assert_eq!(stats["union"], 366);
+    assert_eq!(stats["jaccard"], 1.0);
+}
+
+#[test]
+fn sorted_output_is_reproducible() {
+    let path = format!("{}/sample.fa", DATA);
+    let outputs: Vec<Vec<u8>> = (0..2)
+        .map(|i| {
+            let output = std::env::temp_dir().join(format!("ukc_sorted_{}_{}.txt", std::process::id(), i));
+            run(&["-k", "21", "-f", &path, "-r", "1000", "--sort", "-o", output.to_str().unwrap()]);
+            let content = fs::read(&output).unwrap();
+            fs::remove_file(&output).unwrap();
+            content
+        })
+        .collect();
+    assert_eq!(outputs[0], outputs[1]);
+    let kmers: Vec<&str> = std::str::from_utf8(&outputs[0]).unwrap().lines().collect();
+    assert_eq!(kmers.len(), 366);
+    assert!(kmers.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn disk_count_matches_the_in_memory_count() {
+    let path = format!("{}/sample.fa", DATA);
+    let outputs: Vec<Vec<u8>> = [&["--sort"][..], &["--disk", std::env::temp_dir().to_str().unwrap(), "--memory-limit", "100"]]
+        .iter()
+        .enumerate()
+        .map(|(i, args)| {
+            let output = std::env::temp_dir().join(format!("ukc_disk_{}_{}.txt", std::process::id(), i));
+            let mut all = vec!["-k", "21", "-f", &path, "--canonical", "-o", output.to_str().unwrap()];
+            all.extend(*args);
+            assert!(run(&all).contains("Number of distinct 21-mers: "));
+            let content = fs::read(&output).unwrap();
+            fs::remove_file(&output).unwrap();
+            content
+        })
+        .collect();
+    assert_eq!(outputs[0], outputs[1]);
+}
+
+#[test]
+fn several_kmer_sizes_match_single_runs() {
+    let path = format!("{}/sample.fa", DATA);
+    let stats: serde_json::Value =
+        serde_json::from_str(&run(&["--kmer-sizes", "15,21,40", "-f", &path, "-r", "1000", "--format", "json"])).unwrap();
+    for (i, k) in [15, 21, 40].into_iter().enumerate() {
+        assert_eq!(stats["kmer_sizes"][i]["k"], k);
+        let expected = format!("Number of distinct {}-mers: {}", k, stats["kmer_sizes"][i]["distinct_kmers"]);
+        assert_eq!(distinct_kmers("sample.fa", k), expected);
+    }
+}
+
+#[test]
+fn k_range_writes_a_table_of_the_counts() {
+    let path = format!("{}/tiny.fa", DATA);
+    let table = std::env::temp_dir().join(format!("ukc_k_range_{}.tsv", std::process::id()));
+    let output = run(&["--k-range", "3:7:2", "-f", &path, "-o", table.to_str().unwrap()]);
+    assert!(output.ends_with("Number of distinct 3-mers: 6\nNumber of distinct 5-mers: 6\nNumber of distinct 7-mers: 2\n"), "{}", output);
+    let expected = "k\ttotal_kmers\tvalid_kmers\tdistinct_kmers\n3\t10\t10\t6\n5\t6\t6\t6\n7\t2\t2\t2\n";
+    assert_eq!(fs::read_to_string(&table).unwrap(), expected);
+    fs::remove_file(&table).unwrap();
+    // the same sizes listed, and ranges mixed with sizes
+    assert_eq!(run(&["--kmer-sizes", "3,5,7", "-f", &path]), output);
+    assert_eq!(run(&["--kmer-sizes", "3,5:7:2", "-f", &path]), output);
+
+    for range in ["7:3", "3:7:0", "3:7:2:1", "3:70:2"] {
+        let status = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter")).args(["--k-range", range, "-f", &path]).output().unwrap().status;
+        assert_eq!(status.code(), Some(2), "{}", range);
+    }
+}
+
+#[test]
+fn streamed_kmers_precede_the_statistics() {
+    let path = format!("{}/sample.fa", DATA);
+    let output = run(&["-k", "21", "-f", &path, "-r", "1000", "--stream-output", "-"]);
+    let (kmers, stats): (Vec<&str>, Vec<&str>) = output.lines().partition(|line| !line.contains(':'));
+    assert_eq!(kmers.len(), 366);
+    assert!(kmers.iter().all(|kmer| kmer.len() == 21));
+    assert_eq!(stats.last(), Some(&"Number of distinct 21-mers: 366"));
+    assert_eq!(output.lines().position(|line| line.contains(':')), Some(366));
+}
+
+#[test]
+fn input_without_valid_kmer_warns_or_fails_with_strict() {
+    let protein = b">protein\nMKVLLWEEQRSTP\n";
+    for (strict, code) in [(false, 0), (true, 5)] {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"));
+        command.args(["-k", "5", "-f", "-", "-r", "10"]).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        if strict {
+            command.arg("--strict");
+        }
+        let mut child = command.spawn().unwrap();
+        child.stdin.take().unwrap().write_all(protein).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(output.status.code(), Some(code));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("no valid k-mer found, the input may not be DNA"));
+    }
+}
+
+#[test]
+fn memory_limit_stops_with_the_counts_so_far() {
+    let path = format!("{}/sample.fa", DATA);
+    let run_with_limit = |limit: &str| {
+        Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+            .args(["-k", "21", "-f", &path, "--memory-limit", limit, "--format", "json"])
+            .output()
+            .unwrap()
+    };
+    // a table of 128 buckets of 9 bytes: at most 112 k-mers, checked after each record
+    let output = run_with_limit("2000");
+    assert_eq!(output.status.code(), Some(4));
+    let stats: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stats["memory_limit_reached"], true);
+    assert!(stats["distinct_kmers"].as_u64().unwrap() < 366);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("memory limit of 1.95 KiB reached"));
+
+    let output = run_with_limit("100000");
+    assert!(output.status.success());
+    let stats: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!((&stats["memory_limit_reached"], &stats["distinct_kmers"]), (&false.into(), &366.into()));
+}
+
+#[test]
+fn max_distinct_stops_with_a_sample() {
+    // the first record brings 6 distinct 3-mers, checked after it
+    let fasta = b">a\nACGTACGTTT\n>b\nGGGCCCAAAT\n";
+    let json = |extra: &[&str]| -> serde_json::Value {
+        serde_json::from_str(&run_stdin(&[&["-k", "3", "-f", "-", "--format", "json"], extra].concat(), fasta)).unwrap()
+    };
+    let stats = json(&["--max-distinct", "2"]);
+    assert_eq!((&stats["max_distinct_reached"], &stats["distinct_kmers"]), (&true.into(), &6.into()));
+    let stats = json(&["--max-distinct", "100"]);
+    assert_eq!((&stats["max_distinct_reached"], &stats["distinct_kmers"]), (&false.into(), &14.into()));
+    assert!(json(&[]).get("max_distinct_reached").is_none());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn reserve_beyond_the_memory_available_is_capped() {
+    let path = format!("{}/sample.fa", DATA);
+    // 10^15 k-mers take petabytes
+    let output = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(["-k", "21", "-f", &path, "-r", "1000000000000000"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Warning: a reserve of 1000000000000000 k-mers would take"), "{}", stderr);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Number of distinct 21-mers: 366\n"));
+}
+
+#[test]
+fn top_kmers_are_the_most_frequent() {
+    let fasta = b">a\nACGTACGTAC\n>b\nTTTTTT\n";
+    let output = run_stdin(&["-k", "4", "-f", "-", "--top", "2"], fasta);
+    assert!(output.contains("Top 4-mers #1: TTTT (3 occurrences)\nTop 4-mers #2: ACGT (2 occurrences)\n"));
+    let stats: serde_json::Value = serde_json::from_str(&run_stdin(&["-k", "4", "-f", "-", "--top", "1", "--format", "json"], fasta)).unwrap();
+    assert_eq!(stats["top_kmers"], serde_json::json!([{"kmer": "TTTT", "count": 3}]));
+}
+
+#[test]
+fn kmers_are_written_in_the_symbols_of_the_alphabet() {
+    let fasta = b">a\nHPPHPHHx\n>b\nhpph\n";
+    let output = run_stdin(&["-k", "3", "-f", "-", "--alphabet", "HP", "--sort", "-o", "-"], fasta);
+    assert_eq!(output.lines().take(6).collect::<Vec<_>>(), ["HPH", "HPP", "PHH", "PHP", "PPH", "Strand: forward"]);
+    let stats: serde_json::Value =
+        serde_json::from_str(&run_stdin(&["-k", "3", "-f", "-", "--alphabet", "HP", "--top", "1", "--format", "json"], fasta)).unwrap();
+    assert_eq!(stats["top_kmers"][0], serde_json::json!({"kmer": "HPP", "count": 2}));
+    assert_eq!(stats["max_distinct_kmers"], 8);
+    let status = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(["-k", "3", "-f", "-", "--alphabet", "HP", "--strand", "both"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+        .status;
+    assert_eq!(status.code(), Some(2));
+}
+
+#[test]
+fn membership_gives_the_records_of_each_kmer() {
+    let fasta = b">c1\nACGTACGG\n>c2\nCGTACGTT\n>c3\nTTTTT\n";
+    let output = run_stdin(&["-k", "4", "-f", "-", "--membership", "-", "--sort"], fasta);
+    assert!(output.starts_with("ACGG\t100\nACGT\t110\nCGTA\t110\nCGTT\t010\nGTAC\t110\nTACG\t110\nTTTT\t001\n"), "{}", output);
+    assert!(output.contains("Records of the membership bitvectors: 3\n"));
+
+    let many: Vec<u8> = (0..65).flat_map(|i| format!(">c{}\nACGT\n", i).into_bytes()).collect();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(["-k", "4", "-f", "-", "--membership", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&many).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("more than 64 records"));
+}
+
+#[test]
+fn benchmark_times_each_thread_count() {
+    let path = format!("{}/sample.fa", DATA);
+    let output = run(&["-k", "21", "-f", &path, "--benchmark", "-t", "3"]);
+    let labels: Vec<&str> = output.lines().filter_map(|line| line.split_once(':')).map(|(label, _)| label).collect();
+    assert_eq!(labels, ["Strand", "1 thread", "2 threads", "3 threads", "Number of distinct 21-mers"]);
+    let stats: serde_json::Value = serde_json::from_str(&run(&["-k", "21", "-f", &path, "--benchmark", "-t", "2", "--format", "json"])).unwrap();
+    assert_eq!(stats["benchmark"][1]["threads"], 2);
+    assert_eq!(stats["distinct_kmers"].to_string(), distinct_kmers("sample.fa", 21).rsplit(' ').next().unwrap());
+}
+
+#[test]
+fn auto_threads_are_one_per_core() {
+    let path = format!("{}/tiny.fa", DATA);
+    let cores = std::thread::available_parallelism().map_or(1, usize::from);
+    let run_with = |threads: &str| {
+        Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+            .args(["-k", "3", "-f", &path, "-v", "--threads", threads])
+            .output()
+            .unwrap()
+    };
+    let auto = format!("Threads: {} (cores available: {})", cores, cores);
+    for threads in ["auto", "0"] {
+        let stderr = String::from_utf8(run_with(threads).stderr).unwrap();
+        assert!(stderr.contains(&auto), "{}", stderr);
+        assert!(!stderr.contains("Warning"), "{}", stderr);
+    }
+    let stderr = String::from_utf8(run_with(&(cores + 1).to_string()).stderr).unwrap();
+    assert!(stderr.contains(&format!("Warning: {} threads requested, more than the cores available ({})", cores + 1, cores)), "{}", stderr);
+    assert_eq!(run_with("all").status.code(), Some(2));
+}
+
+#[test]
+fn saturation_is_reported_for_small_k() {
+    let path = format!("{}/sample.fa", DATA);
+    let output = run(&["-k", "3", "-f", &path, "--canonical"]);
+    assert!(output.ends_with("Number of distinct 3-mers: 32\nPossible distinct 3-mers: 32\nSaturation: 100.00%\n"), "{}", output);
+    assert!(!run(&["-k", "21", "-f", &path]).contains("Saturation"));
+}
+
+#[test]
+fn min_count_keeps_solid_kmers() {
+    let fasta = b">a\nACGTACGTAC\n>b\nACGTAGG\n";
+    let output = run_stdin(&["-k", "4", "-f", "-", "-r", "10", "--min-count", "2", "-o", "-"], fasta);
+    let mut lines = output.lines();
+    let mut solid: Vec<&str> = lines.by_ref().take(3).collect();
+    solid.sort_unstable();
+    assert_eq!(solid, ["ACGT\t3", "CGTA\t3", "GTAC\t2"]);
+    assert!(lines.any(|line| line == "Number of distinct 4-mers: 6"));
+    assert_eq!(output.lines().last(), Some("Number of distinct 4-mers occurring at least 2 times: 3"));
+}
+
+#[test]
+fn jellyfish_layouts_of_the_counts_and_histogram() {
+    let fasta = b">a\nACGTACGTAC\n>b\nACGTAGG\n";
+    let output = run_stdin(&["-k", "4", "-f", "-", "-r", "10", "--sort", "--jellyfish-dump", "-o", "-"], fasta);
+    assert!(output.starts_with(">3\nACGT\n>3\nCGTA\n>2\nGTAC\n>1\nGTAG\n>1\nTACG\n>1\nTAGG\nStrand"), "{}", output);
+
+    let histogram = std::env::temp_dir().join(format!("ukc_jellyfish_histo_{}.txt", std::process::id()));
+    let args = ["-k", "4", "-f", "-", "-r", "10", "--sort", "--jellyfish-dump", "columns", "-o", "-", "--jellyfish-histo", "--histogram"];
+    let output = run_stdin(&[&args[..], &[histogram.to_str().unwrap()]].concat(), fasta);
+    assert!(output.starts_with("ACGT 3\nCGTA 3\nGTAC 2\nGTAG 1\nTACG 1\nTAGG 1\nStrand"), "{}", output);
+    assert_eq!(fs::read_to_string(&histogram).unwrap(), "1 3\n2 1\n3 2\n");
+    fs::remove_file(&histogram).unwrap();
+}
+
+#[test]
+fn genome_size_is_estimated_from_the_coverage_peak() {
+    // 4000 reads of 100 nucleotides from a random genome of 20,000: a coverage of 16 for 21-mers
+    let mut state: u64 = 1;
+    let mut random = |n: u64| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) % n
+    };
+    let genome: Vec<u8> = (0..20_000).map(|_| b"ACGT"[random(4) as usize]).collect();
+    let mut reads = Vec::new();
+    for i in 0..4000 {
+        let start = random(genome.len() as u64 - 100) as usize;
+        reads.extend(format!(">r{}\n", i).bytes().chain(genome[start..start + 100].iter().copied()).chain([b'\n']));
+    }
+    let stats: serde_json::Value =
+        serde_json::from_str(&run_stdin(&["-k", "21", "-f", "-", "--estimate-genome-size", "--format", "json"], &reads)).unwrap();
+    let peak = stats["coverage_peak"].as_u64().unwrap();
+    assert!((14..=18).contains(&peak), "coverage peak {}", peak);
+    let size = stats["estimated_genome_size"].as_u64().unwrap();
+    assert!((17_000..=23_000).contains(&size), "genome size {}", size);
+}
+
+#[test]
+fn include_counts_each_target_in_file_order() {
+    let targets = std::env::temp_dir().join(format!("ukc_targets_{}.txt", std::process::id()));
+    fs::write(&targets, "GTAC\nACGT\nTTTT\n").unwrap();
+    let fasta = b">a\nACGTACGTAC\n>b\nACGTAGG\n";
+    let output = run_stdin(&["-k", "4", "-f", "-", "-r", "10", "--include", targets.to_str().unwrap()], fasta);
+    fs::remove_file(&targets).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[..3], ["GTAC\t2", "ACGT\t3", "TTTT\t0"]);
+    assert!(lines.contains(&"Target k-mers found: 2"));
+    assert!(lines.contains(&"Number of distinct 4-mers: 2"));
+}
+
+#[test]
+fn verbosity_controls_the_diagnostics() {
+    let path = format!("{}/sample.fa", DATA);
+    let stderr = |level: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+            .args(["-k", "21", "-f", &path, "-r", "1000", "--min-quality", "20"])
+            .args(level)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).contains("Number of distinct 21-mers: 366"));
+        String::from_utf8(output.stderr).unwrap()
+    };
+    assert_eq!(stderr(&["--quiet"]), "");
+    assert_eq!(stderr(&[]), "Warning: --min-quality ignored, the input has no quality scores\n");
+    let verbose = stderr(&["-v"]);
+    assert!(verbose.contains(&format!("Processed 5 records of {}", path)));
+    assert!(!verbose.contains("nucleotides"));
+    assert!(stderr(&["-vv"]).contains(&format!("{}: ", path)));
+}
+
+#[test]
+fn missing_input_fails_without_panicking() {
+    let output = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(["-k", "21", "-f", "/nonexistent/input.fa", "-r", "1000"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Error: /nonexistent/input.fa: "), "{}", stderr);
+    assert!(!stderr.contains("panicked"));
+
+    // an output that cannot be created is named, with the exit code of other failures
+    let output_path = std::env::temp_dir().join(format!("ukc_missing_{}", std::process::id())).join("kmers.txt");
+    let output = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(["-k", "3", "-f", &format!("{}/tiny.fa", DATA), "-o", output_path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with(&format!("Error: {}: ", output_path.display())), "{}", stderr);
+}
+
+#[test]
+fn malformed_record_is_named_or_skipped() {
+    let fastq = b"@r1\nACGTA\n+\nIIIII\n@r2\nACGT\n+\nII\n@r3\nACGTT\n+\nIIIII\n";
+    let mut child = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(["-k", "3", "-f", "-", "-r", "10"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(fastq).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Error: -: record 2 (r2): quality and sequence of different lengths\n"
+    );
+
+    let output = run_stdin(&["-k", "3", "-f", "-", "-r", "10", "--skip-invalid"], fastq);
+    assert!(output.contains("Malformed records skipped: 1\n"));
+    assert!(output.contains("Total nucleotides: 10\n"));
+}
+
+#[test]
+fn homopolymer_kmers_are_reported_as_low_complexity() {
+    let output = run_stdin(&["-k", "4", "-f", "-", "-r", "10", "--max-homopolymer", "3", "--format", "json"], b">a\nACAAAAAG\n");
+    let stats: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(stats["low_complexity_kmers"], 2);
+    assert_eq!(stats["valid_kmers"], 3);
+    assert_eq!(stats["distinct_kmers"], 3);
+}
+
+#[test]
+fn low_entropy_kmers_add_up_with_homopolymers() {
+    let fasta = b">a\nATATACGTAAAAAA\n";
+    let low_complexity = |args: &[&str]| {
+        let mut all = vec!["-k", "4", "-f", "-", "-r", "10", "--format", "json"];
+        all.extend(args);
+        let stats: serde_json::Value = serde_json::from_str(&run_stdin(&all, fasta)).unwrap();
+        stats["low_complexity_kmers"].as_u64().unwrap()
+    };
+    // ATAT, TATA, TAAA, AAAA x 3 below 1.5 bits; AAAA x 3 with runs of more than 3
+    assert_eq!(low_complexity(&["--min-entropy", "1.5"]), 6);
+    assert_eq!(low_complexity(&["--max-homopolymer", "3"]), 3);
+    assert_eq!(low_complexity(&["--min-entropy", "1.5", "--max-homopolymer", "3"]), 6);
+}
+
+#[test]
+fn gzipped_output_decompresses_to_the_plain_output() {
+    let path = format!("{}/sample.fa", DATA);
+    for extra in [&[][..], &["--sort"], &["--frequencies", "--compression-level", "9"]] {
+        let outputs: Vec<String> = ["txt", "txt.gz"]
+            .iter()
+            .map(|extension| {
+                let output = std::env::temp_dir().join(format!("ukc_gzip_{}.{}", std::process::id(), extension));
+                let mut args = vec!["-k", "21", "-f", &path, "-r", "1000", "-o", output.to_str().unwrap()];
+                args.extend(extra);
+                run(&args);
+                let mut content = String::new();
+                let file = fs::File::open(&output).unwrap();
+                if extension.ends_with(".gz") {
+                    flate2::read::GzDecoder::new(file).read_to_string(&mut content).unwrap();
+                } else {
+                    io::BufReader::new(file).read_to_string(&mut content).unwrap();
+                }
+                fs::remove_file(&output).unwrap();
+                content
+            })
+            .collect();
+        let mut lines: Vec<Vec<&str>> = outputs.iter().map(|output| output.lines().collect()).collect();
+        lines.iter_mut().for_each(|lines| lines.sort_unstable());
+        assert_eq!(lines[0].len(), 366);
+        assert_eq!(lines[0], lines[1]);
+    }
+}
+
+#[test]
+fn resumed_count_matches_an_uninterrupted_one() {
+    let first = format!("{}/sample.fa", DATA);
+    let second = format!("{}/sample.fa.gz", DATA);
+    let checkpoint = std::env::temp_dir().join(format!("ukc_checkpoint_{}", std::process::id()));
+    let checkpoint = checkpoint.to_str().unwrap();
+    let json = |args: &[&str]| -> serde_json::Value {
+        let mut all = vec!["-k", "21", "-r", "1000", "--format", "json"];
+        all.extend(args);
+        serde_json::from_str(&run(&all)).unwrap()
+    };
+    // interrupted after the first file
+    json(&["-f", &first, "--checkpoint", checkpoint]);
+    let mut resumed = json(&["-f", &first, "-f", &second, "--resume", checkpoint]);
+    fs::remove_file(checkpoint).unwrap();
+    assert_eq!(resumed["resumed_files"], 1);
+    let mut uninterrupted = json(&["-f", &first, "-f", &second]);
+    for stats in [&mut resumed, &mut uninterrupted] {
+        let stats = stats.as_object_mut().unwrap();
+        stats.remove("resumed_files");
+        stats.remove("files");
+    }
+    assert_eq!(resumed, uninterrupted);
+    assert_eq!(uninterrupted["total_nucleotides"], 932);
+}
+
+#[test]
+fn saved_set_grows_with_each_sample() {
+    let sample = format!("{}/sample.fa", DATA);
+    let set = std::env::temp_dir().join(format!("ukc_set_{}.bin.gz", std::process::id()));
+    let set = set.to_str().unwrap();
+    let json = |args: &[&str]| -> serde_json::Value {
+        let mut all = vec!["-k", "21", "-r", "1000", "--format", "json"];
+        all.extend(args);
+        serde_json::from_str(&run(&all)).unwrap()
+    };
+    json(&["-f", &sample, "--output-binary", set]);
+    let new_sample = std::env::temp_dir().join(format!("ukc_new_sample_{}.fa", std::process::id()));
+    fs::write(&new_sample, ">new\nACGTACGTACGTAACCGGTTAACCGGT\n").unwrap();
+    let added = json(&["-f", new_sample.to_str().unwrap(), "--load", set, "--save"]);
+    assert_eq!((added["loaded_distinct_kmers"].as_u64(), added["new_distinct_kmers"].as_u64()), (Some(366), Some(7)));
+    assert_eq!(added["distinct_kmers"], 373);
+    // the set saved holds both samples
+    let again = json(&["-f", &sample, "--load", set]);
+    fs::remove_file(set).unwrap();
+    fs::remove_file(new_sample).unwrap();
+    assert_eq!((again["loaded_distinct_kmers"].as_u64(), again["new_distinct_kmers"].as_u64()), (Some(373), Some(0)));
+}
+
+#[test]
+fn bloom_file_answers_queries() {
+    let sample = format!("{}/sample.fa", DATA);
+    let bloom = std::env::temp_dir().join(format!("ukc_bloom_{}.bin", std::process::id()));
+    let bloom = bloom.to_str().unwrap();
+    run(&["-k", "21", "-C", "-f", &sample, "--bloom", "--bloom-size", "100000", "--bloom-output", bloom]);
+    let query = |args: &[&str], input: &[u8]| -> serde_json::Value {
+        let mut all = vec!["-k", "21", "-C", "--query", bloom, "--format", "json"];
+        all.extend(args);
+        serde_json::from_str(&run_stdin(&all, input)).unwrap()
+    };
+    let same = query(&["-f", &sample], b"");
+    assert_eq!((same["distinct_kmers"].as_u64(), same["query_found_kmers"].as_u64()), (Some(366), Some(366)));
+    assert_eq!(same["query_fraction"], 1.0);
+    // a k-mer of the sample, and one that is not
+    let other = query(&["-f", "-"], b">q\nGGGGGGGGGGGGGGGGGGGGG\n");
+    assert_eq!((other["distinct_kmers"].as_u64(), other["query_found_kmers"].as_u64()), (Some(1), Some(0)));
+    // the k-mers must be of the same k and strand mode
+    let status = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(["-k", "21", "--query", bloom, "-f", &sample])
+        .output()
+        .unwrap()
+        .status;
+    fs::remove_file(bloom).unwrap();
+    assert_eq!(status.code(), Some(3));
+}
+
+#[test]
+fn snapshots_give_a_rarefaction_curve() {
+    // 200 records of 50 nucleotides, the same 20 over and over: the distinct k-mers saturate after 1000 nucleotides
+    let mut state = 1u64;
+    let sequences: Vec<String> = (0..20)
+        .map(|_| {
+            (0..50)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    b"ACGT"[(state >> 62) as usize] as char
+                })
+                .collect()
+        })
+        .collect();
+    let fasta: String = (0..200).map(|i| format!(">r{}\n{}\n", i, sequences[i % 20])).collect();
+    let snapshots = std::env::temp_dir().join(format!("ukc_snapshots_{}.tsv", std::process::id()));
+    let snapshots = snapshots.to_str().unwrap();
+    run_stdin(&["-k", "11", "-f", "-", "-t", "1", "--snapshot-interval", "0.0005", "--snapshot-output", snapshots], fasta.as_bytes());
+    let curve = fs::read_to_string(snapshots).unwrap();
+    fs::remove_file(snapshots).unwrap();
+    let rows: Vec<(f64, usize)> = curve
+        .lines()
+        .map(|line| line.split_once('\t').map(|(megabases, distinct)| (megabases.parse().unwrap(), distinct.parse().unwrap())).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 20);
+    assert_eq!((rows[0].0, rows[19].0), (0.0005, 0.01));
+    assert!(rows[0].1 < rows[1].1 && rows[1..].iter().all(|row| row.1 == rows[1].1));
+}
+
+#[test]
+fn sketches_of_the_same_kmers_have_jaccard_one() {
+    let sketches: Vec<String> = [format!("{}/sample.fa", DATA), format!("{}/sample.fa.gz", DATA)]
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let sketch = std::env::temp_dir().join(format!("ukc_sketch_{}_{}.txt", std::process::id(), i));
+            let sketch = sketch.to_str().unwrap().to_string();
+            run(&["-k", "21", "-C", "-f", path, "--sketch", "100", "-o", &sketch]);
+            sketch
+        })
+        .collect();
+    let stats: serde_json::Value =
+        serde_json::from_str(&run(&["--compare-sketches", &sketches[0], &sketches[1], "--format", "json"])).unwrap();
+    for sketch in &sketches {
+        fs::remove_file(sketch).unwrap();
+    }
+    assert_eq!(stats["sketch_size"], 100);
+    assert_eq!(stats["jaccard"], 1.0);
+    assert_eq!(stats["mash_distance"], 0.0);
+}
+
+#[test]
+fn paired_files_are_counted_as_one_sample() {
+    let path = format!("{}/sample.fa", DATA);
+    let mate = format!("{}/sample.fa.gz", DATA);
+    let single: serde_json::Value = serde_json::from_str(&run(&["-k", "21", "-f", &path, "-r", "1000", "--format", "json"])).unwrap();
+    let paired: serde_json::Value =
+        serde_json::from_str(&run(&["-k", "21", "-f", &path, "--pair", &mate, "-r", "1000", "--format", "json"])).unwrap();
+    assert_eq!(paired["total_nucleotides"], 2 * single["total_nucleotides"].as_u64().unwrap());
+    assert_eq!(paired["distinct_kmers"], single["distinct_kmers"]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(["-k", "21", "-f", &format!("{},{}", path, path), "--pair", &mate])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("one R2 file per input file"));
+}
+
+#[test]
+fn dry_run_checks_the_inputs_without_counting() {
+    let path = format!("{}/sample.fa.gz", DATA);
+    let output = run(&["-k", "21", "-f", &path, "--dry-run"]);
+    assert_eq!(output, format!("File {}:\n  Format: FASTA\n  First record: seq0 (100 nucleotides)\n", path));
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(["-k", "5", "-f", "-", "--dry-run"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b">protein\nMKVLLWEEQRSTP\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Error: -: the first record is not DNA: it contains 'L'\n");
+}
+
+#[test]
+fn format_is_detected_from_content_whatever_the_extension() {
+    let fasta = format!("{}/sample.fa", DATA);
+    let named_txt = std::env::temp_dir().join(format!("ukc_sample_{}.txt", std::process::id()));
+    fs::copy(&fasta, &named_txt).unwrap();
+    let fastq = b"@r1\nACGTACGTTG\n+\nIIIIIIIIII\n@r2\nTTGCAACG\n+\nIIIIIIII\n";
+    let gzipped_fastq = std::env::temp_dir().join(format!("ukc_reads_{}.fa", std::process::id()));
+    let mut encoder = flate2::write::GzEncoder::new(fs::File::create(&gzipped_fastq).unwrap(), flate2::Compression::default());
+    encoder.write_all(fastq).unwrap();
+    encoder.finish().unwrap();
+
+    assert_eq!(run(&["-k", "21", "-f", named_txt.to_str().unwrap(), "-r", "1000"]), run(&["-k", "21", "-f", &fasta, "-r", "1000"]));
+    let output = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(["-k", "3", "-f", gzipped_fastq.to_str().unwrap(), "-r", "100", "-v"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), run_stdin(&["-k", "3", "-f", "-", "-r", "100"], fastq));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("Format of {}: FASTQ, gzip-compressed\n", gzipped_fastq.display())), "{}", stderr);
+    fs::remove_file(named_txt).unwrap();
+    fs::remove_file(gzipped_fastq).unwrap();
+}
+
+#[test]
+fn raw_sequences_are_counted_as_fasta_records() {
+    let raw = b"ACGTACGTTG\r\n\nTTGCAACG\n\n";
+    let fasta = b">r1\nACGTACGTTG\n>r2\nTTGCAACG\n";
+    assert_eq!(run_stdin(&["-k", "3", "-f", "-", "-r", "100"], raw), run_stdin(&["-k", "3", "-f", "-", "-r", "100"], fasta));
+    let per_record = run_stdin(&["-k", "3", "-f", "-", "-r", "100", "--per-record", "-"], raw);
+    assert!(per_record.contains("line1\t10\t8\t8\t") && per_record.contains("line3\t8\t6\t6\t"), "{}", per_record);
+}
+
+#[test]
+fn concurrent_runs_append_one_summary_row_each() {
+    let summary = std::env::temp_dir().join(format!("ukc_summary_{}.tsv", std::process::id()));
+    let path = format!("{}/sample.fa.gz", DATA);
+    let children: Vec<_> = (1..=8)
+        .map(|k| {
+            Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+                .args(["-k", &k.to_string(), "-f", &path, "-r", "1000", "--append-summary", summary.to_str().unwrap()])
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+    let content = fs::read_to_string(&summary).unwrap();
+    let mut lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.remove(0), "sample\tk\ttotal_nucleotides\ttotal_kmers\tvalid_kmers\tdistinct_kmers\tgc_content");
+    lines.sort_by_key(|line| line.split('\t').nth(1).unwrap().parse::<usize>().unwrap());
+    assert_eq!(lines.len(), 8);
+    assert!(lines[4].starts_with("sample\t5\t466\t446\t446\t352\t"), "{}", lines[4]);
+    fs::remove_file(summary).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_prints_the_counts_so_far() {
+    let records = |from: usize, to: usize| -> Vec<u8> { (from..to).flat_map(|i| format!(">r{}\nACGTTGCA{:016b}\n", i, i).into_bytes()).collect() };
+    let interrupt = |pid: u32| assert!(Command::new("kill").args(["-INT", &pid.to_string()]).status().unwrap().success());
+    let wait_for = |stderr: &mut dyn io::BufRead, text: &str| {
+        let mut line = String::new();
+        while !line.contains(text) {
+            line.clear();
+            assert!(stderr.read_line(&mut line).unwrap() > 0, "no {}", text);
+        }
+    };
+    let mut child = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(["-k", "21", "-f", "-", "-v", "--format", "json"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(&records(0, 100)).unwrap();
+    // the handler is installed before the input is read
+    wait_for(&mut io::BufReader::new(child.stderr.as_mut().unwrap()), "Reading -");
+    interrupt(child.id());
+    // the records after the interrupt are not read, or only the first ones: 9 k-mers each
+    let _ = stdin.write_all(&records(100, 10000));
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130));
+    let stats: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stats["interrupted"], true);
+    assert!(stats["valid_kmers"].as_u64().unwrap() < 10000 * 9);
+
+    // a second Ctrl-C quits at once, here while waiting for the input
+    let mut child = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(["-k", "21", "-f", "-", "-v"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // kept open, so that the count never ends by itself
+    let stdin = child.stdin.take();
+    let mut stderr = io::BufReader::new(child.stderr.take().unwrap());
+    wait_for(&mut stderr, "Reading -");
+    interrupt(child.id());
+    wait_for(&mut stderr, "Interrupted: counting stops after the records read");
+    interrupt(child.id());
+    assert_eq!(child.wait().unwrap().code(), Some(130));
+    drop(stdin);
+}
+
+#[test]
+fn fofn_lists_the_input_files() {
+    let (tiny, gzipped) = (format!("{}/tiny.fa", DATA), format!("{}/tiny.fa.gz", DATA));
+    let fofn = format!("# samples\n{}\n\n  {}  \n", tiny, gzipped);
+    let listed = run_stdin(&["-k", "3", "--fofn", "-", "--format", "json"], fofn.as_bytes());
+    assert_eq!(listed, run(&["-k", "3", "-f", &format!("{},{}", tiny, gzipped), "--format", "json"]));
+    let stats: serde_json::Value = serde_json::from_str(&listed).unwrap();
+    assert_eq!(stats["files"].as_array().unwrap().len(), 2);
+    // after the files of --input-file
+    let output = run_stdin(&["-k", "3", "-f", &tiny, "--fofn", "-"], gzipped.as_bytes());
+    assert!(output.contains("Number of distinct 3-mers: 6\n"), "{}", output);
+
+    let status = Command::new(env!("CARGO_BIN_EXE_unique_kmer_counter"))
+        .args(["-k", "3", "--fofn", "-"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+        .status;
+    assert_eq!(status.code(), Some(3));
+}
+
+#[test]
+fn listed_kmers_match_both_strands_unless_no_canon_lists() {
+    let list = std::env::temp_dir().join(format!("ukc_list_{}.txt", std::process::id()));
+    fs::write(&list, "GTT\n").unwrap();
+    let list = list.to_str().unwrap();
+    // AAC on each strand: canonical AAC, the reverse complement of the listed GTT
+    let fasta = b">f\nAAC\n>r\nGTT\n";
+    let output = run_stdin(&["-k", "3", "-f", "-", "--canonical", "--exclude", list], fasta);
+    assert!(output.contains("Excluded k-mer occurrences: 2\n") && output.contains("Number of distinct 3-mers: 0\n"), "{}", output);
+    let output = run_stdin(&["-k", "3", "-f", "-", "--canonical", "--exclude", list, "--no-canon-lists"], fasta);
+    assert!(output.contains("Excluded k-mer occurrences: 0\n") && output.contains("Number of distinct 3-mers: 1\n"), "{}", output);
+    let output = run_stdin(&["-k", "3", "-f", "-", "--canonical", "--include", list], fasta);
+    assert!(output.starts_with("GTT\t2\n"), "{}", output);
+    let output = run_stdin(&["-k", "3", "-f", "-", "--canonical", "--include", list, "--no-canon-lists"], fasta);
+    assert!(output.starts_with("GTT\t0\n"), "{}", output);
+    fs::remove_file(list).unwrap();
+}
+
+#[test]
+fn validity_ratio_is_reported() {
+    let path = format!("{}/with_n.fa", DATA);
+    assert!(run(&["-k", "3", "-f", &path]).contains("Valid k-mers: 3\nK-mer validity ratio: 42.86% (valid k-mers of all the windows)\n"));
+    let stats: serde_json::Value = serde_json::from_str(&run(&["-k", "3", "-f", &path, "--format", "json"])).unwrap();
+    assert_eq!(stats["validity_ratio"], 3.0 / 7.0);
+    let stats: serde_json::Value = serde_json::from_str(&run_stdin(&["-k", "3", "-f", "-", "--format", "json"], b">a\nAC\n")).unwrap();
+    assert_eq!(stats["validity_ratio"], serde_json::Value::Null);
+}
+
+#[test]
+fn file_parallel_gives_the_same_counts() {
+    let files: Vec<String> = ["tiny.fa", "with_n.fa", "sample.fa", "tiny.fa.gz", "short_record.fa"].iter().map(|file| format!("{}/{}", DATA, file)).collect();
+    let count = |extra: &[&str]| -> serde_json::Value {
+        serde_json::from_str(&run(&[&["-k", "5", "-f", &files.join(","), "--format", "json"], extra].concat())).unwrap()
+    };
+    let sequential = count(&[]);
+    for threads in ["1", "3"] {
+        let parallel = count(&["--file-parallel", "-t", threads]);
+        assert_eq!(parallel["distinct_kmers"], sequential["distinct_kmers"]);
+        assert_eq!(parallel["valid_kmers"], sequential["valid_kmers"]);
+        // the files in order, without their new distinct k-mers
+        for (parallel, sequential) in parallel["files"].as_array().unwrap().iter().zip(sequential["files"].as_array().unwrap()) {
+            assert_eq!((&parallel["file"], &parallel["valid_kmers"]), (&sequential["file"], &sequential["valid_kmers"]));
+            assert!(parallel.get("new_distinct_kmers").is_none());
+        }
+    }
+}
+
+#[test]
+fn per_file_distinct_reports_the_core_kmers() {
+    // tiny.fa: ACG CGT GTA GTT TAC TTT, short_record.fa: ACG CGT GTA TAC
+    let files: Vec<String> = ["tiny.fa", "short_record.fa", "tiny.fa.gz"].iter().map(|file| format!("{}/{}", DATA, file)).collect();
+    let stats: serde_json::Value = serde_json::from_str(&run(&["-k", "3", "-f", &files.join(","), "--per-file-distinct", "--format", "json"])).unwrap();
+    let per_file: Vec<(u64, u64)> = stats["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| (file["distinct_kmers"].as_u64().unwrap(), file["new_distinct_kmers"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(per_file, [(6, 6), (4, 0), (6, 0)]);
+    assert_eq!((stats["distinct_kmers"].as_u64(), stats["core_kmers"].as_u64()), (Some(6), Some(4)));
+    assert!(run(&["-k", "3", "-f", &files.join(",")]).lines().all(|line| !line.contains("shared by all")));
+}
+
+#[test]
+fn stride_reads_every_few_windows() {
+    let path = format!("{}/sample.fa", DATA);
+    let all: serde_json::Value = serde_json::from_str(&run(&["-k", "21", "-f", &path, "--format", "json"])).unwrap();
+    let strided: serde_json::Value = serde_json::from_str(&run(&["-k", "21", "-f", &path, "--stride", "10", "--format", "json"])).unwrap();
+    assert_eq!(strided["stride"], 10);
+    let (total, strided_total) = (all["total_kmers"].as_u64().unwrap(), strided["total_kmers"].as_u64().unwrap());
+    // one window in 10 of each of the 5 records, rounded up
+    assert!(strided_total * 10 >= total && strided_total * 10 < total + 10 * 5, "{} of {}", strided_total, total);
+    assert!(strided["distinct_kmers"].as_u64().unwrap() < all["distinct_kmers"].as_u64().unwrap());
+    assert!(all.get("stride").is_none());
+}
@@ -0,0 +1,88 @@
+//! Counts of small fixture files, computed by hand
+
+use dashmap::DashSet;
+use unique_kmer_counter::{Kmer, KmerCounter, KmerStats, KmerStore};
+
+const DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data");
+
+/// Statistics and distinct k-mers of a fixture
+fn count(counter: &KmerCounter, file: &str) -> (KmerStats, Vec<String>) {
+    let kmers = DashSet::<u64>::new();
+    let stats = counter.count_file(&format!("{}/{}", DATA, file), KmerStore::Set(&kmers)).unwrap();
+    let mut kmers: Vec<String> = kmers.iter().map(|kmer| kmer.decode(3)).collect();
+    kmers.sort_unstable();
+    (stats, kmers)
+}
+
+#[test]
+fn tiny() {
+    // ACGTACGT: ACG CGT GTA TAC ACG CGT, ACGTTT: ACG CGT GTT TTT
+    let expected = KmerStats {
+        distinct: 6,
+        total_nucleotides: 14,
+        records: 2,
+        total_kmers: 10,
+        valid_kmers: 10,
+        acgt_nucleotides: 14,
+        gc_nucleotides: 6,
+        ..KmerStats::default()
+    };
+    let (stats, kmers) = count(&KmerCounter::new(3), "tiny.fa");
+    assert_eq!(stats, expected);
+    assert_eq!(kmers, ["ACG", "CGT", "GTA", "GTT", "TAC", "TTT"]);
+
+    // ACG and CGT, GTA and TAC are reverse complements, AAC and AAA are the smaller of GTT and TTT
+    let (stats, kmers) = count(&KmerCounter::new(3).canonical(true), "tiny.fa");
+    assert_eq!(stats, KmerStats { distinct: 4, ..expected });
+    assert_eq!(kmers, ["AAA", "AAC", "ACG", "GTA"]);
+}
+
+#[test]
+fn gzipped_tiny() {
+    let counter = KmerCounter::new(3);
+    assert_eq!(count(&counter, "tiny.fa.gz"), count(&counter, "tiny.fa"));
+}
+
+#[test]
+fn with_n() {
+    // ACGNACGTN: ACG CGN GNA NAC ACG CGT GTN, of which the 4 with an N are not valid
+    let (stats, kmers) = count(&KmerCounter::new(3), "with_n.fa");
+    let expected = KmerStats {
+        distinct: 2,
+        total_nucleotides: 9,
+        records: 1,
+        total_kmers: 7,
+        valid_kmers: 3,
+        acgt_nucleotides: 7,
+        gc_nucleotides: 4,
+        n_nucleotides: 2,
+        n_kmers: 4,
+        ..KmerStats::default()
+    };
+    assert_eq!(stats, expected);
+    assert_eq!(kmers, ["ACG", "CGT"]);
+}
+
+#[test]
+fn empty() {
+    assert_eq!(count(&KmerCounter::new(3), "empty.fa"), (KmerStats::default(), Vec::new()));
+}
+
+#[test]
+fn record_shorter_than_k() {
+    // ACGTAC: ACG CGT GTA TAC, AC: none
+    let (stats, kmers) = count(&KmerCounter::new(3), "short_record.fa");
+    let expected = KmerStats {
+        distinct: 4,
+        total_nucleotides: 8,
+        records: 2,
+        total_kmers: 4,
+        valid_kmers: 4,
+        acgt_nucleotides: 8,
+        gc_nucleotides: 4,
+        short_records: 1,
+        ..KmerStats::default()
+    };
+    assert_eq!(stats, expected);
+    assert_eq!(kmers, ["ACG", "CGT", "GTA", "TAC"]);
+}